@@ -1,4 +1,9 @@
-use std::{env, fs::File, path::Path, process::Command};
+use std::{
+    env,
+    fs::File,
+    path::{Path, PathBuf},
+    process::Command,
+};
 use zip::ZipArchive;
 
 const ONNX_SOURCE: (&str, &str) = (
@@ -32,6 +37,8 @@ fn get_build_config() -> &'static str {
 }
 
 fn main() {
+    embed_model();
+
     build_warning!("Starting build script for ONNX Runtime");
     let target_dir = env::var("OUT_DIR").expect("OUT_DIR environment variable not set");
 
@@ -106,6 +113,50 @@ fn main() {
     );
 }
 
+/// With the embed-model feature, passes the absolute paths of the model and
+/// YAML to embed and the model file name to the crate
+fn embed_model() {
+    if env::var_os("CARGO_FEATURE_EMBED_MODEL").is_none() {
+        return;
+    }
+    println!("cargo:rerun-if-env-changed=BLUE_ONYX_EMBED_MODEL");
+    println!("cargo:rerun-if-env-changed=BLUE_ONYX_EMBED_MODEL_YAML");
+
+    let Some(model) = env::var_os("BLUE_ONYX_EMBED_MODEL") else {
+        build_error!(
+            "The embed-model feature requires BLUE_ONYX_EMBED_MODEL set to the .onnx model"
+        );
+        panic!("BLUE_ONYX_EMBED_MODEL is not set");
+    };
+    let model = PathBuf::from(model);
+    let yaml = env::var_os("BLUE_ONYX_EMBED_MODEL_YAML")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| model.with_extension("yaml"));
+    let model = model
+        .canonicalize()
+        .unwrap_or_else(|e| panic!("Model to embed {} not found: {e}", model.display()));
+    let yaml = yaml
+        .canonicalize()
+        .unwrap_or_else(|e| panic!("YAML to embed {} not found: {e}", yaml.display()));
+    let model_file_name = model
+        .file_name()
+        .and_then(|name| name.to_str())
+        .expect("Model to embed has no valid file name");
+
+    println!("cargo:rerun-if-changed={}", model.display());
+    println!("cargo:rerun-if-changed={}", yaml.display());
+    println!(
+        "cargo:rustc-env=BLUE_ONYX_EMBEDDED_MODEL={}",
+        model.display()
+    );
+    println!(
+        "cargo:rustc-env=BLUE_ONYX_EMBEDDED_MODEL_YAML={}",
+        yaml.display()
+    );
+    println!("cargo:rustc-env=BLUE_ONYX_EMBEDDED_MODEL_FILE_NAME={model_file_name}");
+    build_warning!("Embedding model {}", model.display());
+}
+
 fn check_and_download_onnx_source(target_dir: &str) {
     let onnx_dir = Path::new(target_dir).join(ONNX_SOURCE.0);
     let zip_path = Path::new(target_dir).join("onnxruntime.zip");
@@ -4,15 +4,100 @@ use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
 use std::fmt::Debug;
 
+/// Hand-written OpenAPI document describing the detection endpoints.
+pub const OPENAPI_JSON: &str = include_str!("../assets/openapi.json");
+
 #[derive(Default)]
 pub struct VisionDetectionRequest {
     pub min_confidence: f32,
     pub image_data: Bytes,
     pub image_name: String,
+    /// Optional camera name used by the save image template
+    pub camera: Option<String>,
+    /// Include the detailed per stage timings in the response
+    pub timings: bool,
+    /// Name of the detection profile to apply to this request
+    pub profile: Option<String>,
+    /// Regions of interest, predictions centered outside all of them are
+    /// dropped. Empty keeps every prediction.
+    pub rois: Vec<Roi>,
+    /// Number of best scoring classes to attach to each prediction, 0 attaches none
+    pub top_k_classes: usize,
+}
+
+/// Upper limit of the top_k_classes a request can ask for
+pub const MAX_TOP_K_CLASSES: usize = 10;
+
+/// A region of interest rectangle in pixels of the image as it was sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Roi {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Roi {
+    /// Parses one or more `x,y,w,h` rectangles separated by `;`, None if any
+    /// of them is malformed or empty
+    pub fn parse_list(text: &str) -> Option<Vec<Roi>> {
+        text.split(';')
+            .map(str::trim)
+            .filter(|roi| !roi.is_empty())
+            .map(|roi| {
+                let values = roi
+                    .split(',')
+                    .map(|value| value.trim().parse::<usize>().ok())
+                    .collect::<Option<Vec<_>>>()?;
+                let [x, y, width, height] = values[..] else {
+                    return None;
+                };
+                (width > 0 && height > 0).then_some(Roi {
+                    x,
+                    y,
+                    width,
+                    height,
+                })
+            })
+            .collect()
+    }
+
+    /// True if the center of the prediction box is inside the region
+    pub fn contains_center(&self, prediction: &Prediction) -> bool {
+        let center_x = (prediction.x_min + prediction.x_max) as f32 / 2.0;
+        let center_y = (prediction.y_min + prediction.y_max) as f32 / 2.0;
+        (self.x as f32..self.x.saturating_add(self.width) as f32).contains(&center_x)
+            && (self.y as f32..self.y.saturating_add(self.height) as f32).contains(&center_y)
+    }
+}
+
+/// Scale of the prediction confidences in detection responses, internally
+/// confidences are always a 0-1 fraction
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+pub enum ConfidenceScale {
+    /// 0-1, CodeProject.AI compatible
+    #[default]
+    Fraction,
+    /// 0-100
+    Percent,
+}
+
+impl ConfidenceScale {
+    /// Converts the fraction confidences of a response about to be sent to this scale
+    pub fn apply(self, response: &mut VisionDetectionResponse) {
+        if self == ConfidenceScale::Percent {
+            for prediction in response.predictions.iter_mut() {
+                prediction.confidence *= 100.0;
+                for (_, score) in prediction.top_classes.iter_mut().flatten() {
+                    *score *= 100.0;
+                }
+            }
+        }
+    }
 }
 
 #[allow(non_snake_case)]
-#[derive(Serialize, Deserialize, Default, Debug)]
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
 #[serde(rename_all = "camelCase", default)]
 pub struct VisionDetectionResponse {
     /// True if successful.
@@ -29,6 +114,10 @@ pub struct VisionDetectionResponse {
     pub command: String,
     /// The Id of the module that processed this request.
     pub moduleId: String,
+    /// The name of the module that processed this request, only set when
+    /// configured with `--module-name`.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub moduleName: String,
     /// The name of the device or package handling the inference. eg CPU, GPU
     pub executionProvider: String,
     /// True if this module can use the current GPU if one is present.
@@ -39,9 +128,56 @@ pub struct VisionDetectionResponse {
     pub processMs: i32,
     // The time (ms) for the round trip to the analysis module and back.
     pub analysisRoundTripMs: i32,
+    /// Detailed per stage timings, only set when requested with `?timings=true`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timings: Option<DetectionTimings>,
+    /// Width in pixels of the image as it was sent, 0 if it could not be decoded.
+    pub imageWidth: u32,
+    /// Height in pixels of the image as it was sent, 0 if it could not be decoded.
+    pub imageHeight: u32,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DetectionTimings {
+    /// The time (ms) to decode the image.
+    pub decode_ms: f64,
+    /// The time (ms) to resize the image to the model input size.
+    pub resize_ms: f64,
+    /// The time (ms) to convert the resized image into the model input tensor.
+    pub pre_processing_ms: f64,
+    /// The time (ms) to perform the AI inference.
+    pub inference_ms: f64,
+    /// The time (ms) to turn the model output into predictions.
+    pub post_processing_ms: f64,
 }
 
-#[derive(Serialize, Deserialize, Clone, PartialEq)]
+/// A finished detection published to the `/events` stream.
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DetectionEvent {
+    /// RFC 3339 time the detection finished.
+    pub timestamp: String,
+    /// Name of the model that ran the detection, None for the default model.
+    pub model: Option<String>,
+    /// Name of the camera the image was taken by.
+    pub camera: Option<String>,
+    /// Name of the image file.
+    pub image_name: String,
+    /// Labels of the predictions, in the same order as the predictions.
+    pub labels: Vec<String>,
+    pub predictions: Vec<Prediction>,
+    /// The number of objects found.
+    pub count: i32,
+    /// The time (ms) to perform the AI inference.
+    pub inference_ms: i32,
+    /// The time (ms) to process the image, including inference.
+    pub process_ms: i32,
+    /// The time (ms) from receiving the request until the detection finished.
+    pub analysis_round_trip_ms: i32,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq)]
 pub struct Prediction {
     pub x_max: usize,
     pub x_min: usize,
@@ -49,6 +185,107 @@ pub struct Prediction {
     pub y_min: usize,
     pub confidence: f32,
     pub label: String,
+    /// Sub-pixel coordinates as the model returned them, only sent when
+    /// requested with `?float_coordinates=true`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x_max_f: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x_min_f: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub y_max_f: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub y_min_f: Option<f32>,
+    /// The best scoring classes of the box as (label, score), highest first,
+    /// only sent when requested with `?top_k_classes=<k>`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_classes: Option<Vec<(String, f32)>>,
+}
+
+impl Prediction {
+    /// Creates a prediction from the float box, `round` decides how the
+    /// integer coordinates are derived from it
+    pub fn from_float_box(
+        (x_min, y_min, x_max, y_max): (f32, f32, f32, f32),
+        round: fn(f32) -> f32,
+        confidence: f32,
+        label: String,
+    ) -> Self {
+        Self {
+            x_max: round(x_max) as usize,
+            x_min: round(x_min) as usize,
+            y_max: round(y_max) as usize,
+            y_min: round(y_min) as usize,
+            confidence,
+            label,
+            x_max_f: Some(x_max),
+            x_min_f: Some(x_min),
+            y_max_f: Some(y_max),
+            y_min_f: Some(y_min),
+            top_classes: None,
+        }
+    }
+
+    /// Moves the prediction right by `dx` and down by `dy` pixels
+    pub fn translate(&mut self, dx: usize, dy: usize) {
+        self.x_min += dx;
+        self.x_max += dx;
+        self.y_min += dy;
+        self.y_max += dy;
+        for (coordinate, offset) in [
+            (&mut self.x_min_f, dx),
+            (&mut self.x_max_f, dx),
+            (&mut self.y_min_f, dy),
+            (&mut self.y_max_f, dy),
+        ] {
+            if let Some(coordinate) = coordinate {
+                *coordinate += offset as f32;
+            }
+        }
+    }
+
+    /// Drops the sub-pixel coordinates so they are not serialized
+    pub fn clear_float_coordinates(&mut self) {
+        self.x_max_f = None;
+        self.x_min_f = None;
+        self.y_max_f = None;
+        self.y_min_f = None;
+    }
+}
+
+/// A prediction in the COCO results format used by mAP evaluation tools like
+/// pycocotools.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CocoResult {
+    pub image_id: u64,
+    /// Index of the label in the object classes
+    pub category_id: usize,
+    /// [x, y, width, height] in pixels
+    pub bbox: [f32; 4],
+    pub score: f32,
+}
+
+impl CocoResult {
+    /// None if the label is not one of the object classes
+    pub fn from_prediction(
+        image_id: u64,
+        prediction: &Prediction,
+        object_classes: &[String],
+    ) -> Option<Self> {
+        let category_id = object_classes
+            .iter()
+            .position(|class| *class == prediction.label)?;
+        // Prefer the sub-pixel coordinates when the post-processor kept them
+        let x_min = prediction.x_min_f.unwrap_or(prediction.x_min as f32);
+        let y_min = prediction.y_min_f.unwrap_or(prediction.y_min as f32);
+        let x_max = prediction.x_max_f.unwrap_or(prediction.x_max as f32);
+        let y_max = prediction.y_max_f.unwrap_or(prediction.y_max as f32);
+        Some(Self {
+            image_id,
+            category_id,
+            bbox: [x_min, y_min, x_max - x_min, y_max - y_min],
+            score: prediction.confidence,
+        })
+    }
 }
 
 impl Debug for Prediction {
@@ -76,6 +313,27 @@ pub struct VisionCustomListResponse {
     pub timestampUTC: String,
 }
 
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase", default)]
+pub struct VisionInfoResponse {
+    /// True if successful.
+    pub success: bool,
+    /// The name of the loaded model.
+    pub model_name: String,
+    /// The execution provider running the inference. eg CPU, DirectML(GPU 0)
+    pub execution_provider: String,
+    /// The model input width in pixels.
+    pub input_width: usize,
+    /// The model input height in pixels.
+    pub input_height: usize,
+    /// The number of object classes the model can detect.
+    pub object_class_count: usize,
+    /// The time (ms) it took to load the model and build the session.
+    pub model_load_ms: u64,
+    /// The time (ms) the warmup detections took at startup.
+    pub warmup_ms: u64,
+}
+
 #[allow(non_snake_case)]
 #[derive(Serialize, Default, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -158,9 +416,39 @@ impl Ord for VersionInfo {
 
 #[cfg(test)]
 mod tests {
-    use super::VersionInfo;
+    use super::{
+        CocoResult, ConfidenceScale, DetectionTimings, OPENAPI_JSON, Prediction, Roi, VersionInfo,
+        VisionDetectionResponse,
+    };
     use std::cmp::Ordering;
 
+    #[test]
+    fn test_openapi_documents_detection_response() {
+        let doc: serde_json::Value = serde_json::from_str(OPENAPI_JSON).unwrap();
+        assert!(doc["paths"]["/v1/vision/detection"]["post"].is_object());
+        assert!(doc["paths"]["/v1/vision/detection/{model_name}"]["post"].is_object());
+
+        let schemas = &doc["components"]["schemas"];
+        let response = serde_json::to_value(VisionDetectionResponse {
+            moduleName: "BlueOnyx".into(),
+            timings: Some(DetectionTimings::default()),
+            ..Default::default()
+        })
+        .unwrap();
+        for field in response.as_object().unwrap().keys() {
+            assert!(
+                schemas["VisionDetectionResponse"]["properties"][field].is_object(),
+                "{field} is not documented"
+            );
+        }
+        for field in response["timings"].as_object().unwrap().keys() {
+            assert!(
+                schemas["DetectionTimings"]["properties"][field].is_object(),
+                "timings.{field} is not documented"
+            );
+        }
+    }
+
     #[test]
     fn test_eq_and_ne() {
         let v1 = VersionInfo {
@@ -216,4 +504,116 @@ mod tests {
         assert_eq!(v3.cmp(&v2), Ordering::Greater);
         assert!(v3 > v2);
     }
+
+    #[test]
+    fn test_float_coordinates_only_serialized_when_set() {
+        let mut prediction = Prediction::from_float_box(
+            (10.5, 20.25, 30.5, 40.75),
+            f32::trunc,
+            0.5,
+            "person".to_string(),
+        );
+        prediction.translate(5, 0);
+        assert_eq!((prediction.x_min, prediction.y_min), (15, 20));
+        assert_eq!(prediction.x_min_f, Some(15.5));
+
+        let json = serde_json::to_value(&prediction).unwrap();
+        assert_eq!(json["x_max_f"], serde_json::json!(35.5));
+        prediction.clear_float_coordinates();
+        let json = serde_json::to_value(&prediction).unwrap();
+        assert!(json.get("x_max_f").is_none());
+    }
+
+    #[test]
+    fn test_coco_result_from_prediction() {
+        let classes = ["person", "bicycle", "car"].map(String::from);
+        let prediction = Prediction {
+            x_min: 10,
+            y_min: 20,
+            x_max: 40,
+            y_max: 100,
+            confidence: 0.9,
+            label: "car".to_string(),
+            ..Default::default()
+        };
+        let result = CocoResult::from_prediction(42, &prediction, &classes).unwrap();
+        assert_eq!(result.category_id, 2);
+        assert_eq!(result.bbox, [10.0, 20.0, 30.0, 80.0]);
+        assert_eq!(
+            serde_json::to_value(&result).unwrap(),
+            serde_json::json!({
+                "image_id": 42,
+                "category_id": 2,
+                "bbox": [10.0, 20.0, 30.0, 80.0],
+                "score": 0.9f32,
+            })
+        );
+
+        let unknown = Prediction {
+            label: "dog".to_string(),
+            ..prediction
+        };
+        assert!(CocoResult::from_prediction(42, &unknown, &classes).is_none());
+    }
+
+    #[test]
+    fn test_roi_parse_and_contains_center() {
+        let rois = Roi::parse_list("0,0,100,50; 200,200,10,10;").unwrap();
+        assert_eq!(
+            rois,
+            vec![
+                Roi {
+                    x: 0,
+                    y: 0,
+                    width: 100,
+                    height: 50
+                },
+                Roi {
+                    x: 200,
+                    y: 200,
+                    width: 10,
+                    height: 10
+                }
+            ]
+        );
+        assert!(Roi::parse_list("0,0,100").is_none());
+        assert!(Roi::parse_list("0,0,0,10").is_none());
+        assert!(Roi::parse_list("a,0,10,10").is_none());
+
+        let prediction = Prediction {
+            x_min: 80,
+            x_max: 110,
+            y_min: 0,
+            y_max: 40,
+            ..Default::default()
+        };
+        assert!(rois[0].contains_center(&prediction));
+        assert!(!rois[1].contains_center(&prediction));
+
+        // Huge client rectangles must not overflow
+        let huge = Roi::parse_list(&format!("1,0,{},{}", usize::MAX, usize::MAX)).unwrap();
+        assert!(huge[0].contains_center(&prediction));
+    }
+
+    #[test]
+    fn test_confidence_scale() {
+        let response = || VisionDetectionResponse {
+            predictions: vec![Prediction {
+                x_max: 1,
+                x_min: 0,
+                y_max: 1,
+                y_min: 0,
+                confidence: 0.75,
+                label: "person".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let mut fraction = response();
+        ConfidenceScale::Fraction.apply(&mut fraction);
+        assert_eq!(fraction.predictions[0].confidence, 0.75);
+        let mut percent = response();
+        ConfidenceScale::Percent.apply(&mut percent);
+        assert_eq!(percent.predictions[0].confidence, 75.0);
+    }
 }
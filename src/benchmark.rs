@@ -0,0 +1,266 @@
+//! Inference benchmark statistics, shared by the benchmark binary and the
+//! self-benchmark the server can run at startup with `--benchmark-on-start`.
+
+use crate::{
+    detector::{DetectOptions, Detector, DeviceType, EndpointProvider},
+    system_info::{cpu_model, gpu_model},
+};
+use bytes::Bytes;
+use std::{io::Write, path::PathBuf, time::Duration};
+use tracing::info;
+
+/// Time the benchmark stats are computed from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Measure {
+    /// Model inference only
+    Inference,
+    /// Total processing time, including image decode and resize
+    Total,
+}
+
+impl std::fmt::Display for Measure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Measure::Inference => write!(f, "Inference"),
+            Measure::Total => write!(f, "Total"),
+        }
+    }
+}
+
+fn average(times: &[Duration]) -> Duration {
+    if times.is_empty() {
+        return Duration::ZERO;
+    }
+    Duration::from_secs_f64(times.iter().sum::<Duration>().as_secs_f64() / times.len() as f64)
+}
+
+#[derive(Debug, Clone)]
+pub enum Platform {
+    Linux,
+    Windows,
+}
+
+impl std::fmt::Display for Platform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Platform::Linux => write!(f, "Linux  "),
+            Platform::Windows => write!(f, "Windows"),
+        }
+    }
+}
+impl Default for Platform {
+    fn default() -> Self {
+        if cfg!(target_os = "windows") {
+            Platform::Windows
+        } else if cfg!(target_os = "linux") {
+            Platform::Linux
+        } else {
+            panic!("Unsupported platform");
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct InferenceStats {
+    pub model_name: String,
+    pub version: String,
+    pub device_name: String,
+    pub device_type: DeviceType,
+    pub platform: Platform,
+    pub endpoint_provider: EndpointProvider,
+    pub number_of_images: u64,
+    pub total_inference: Duration,
+    pub images_per_second: f64,
+    pub min_inference: Duration,
+    pub max_inference: Duration,
+    pub average_inference: Duration,
+    /// Which time the total, min, max, average and FPS are computed from
+    pub measure: Measure,
+    /// Average of the model inference only
+    pub average_inference_time: Duration,
+    /// Average of the total processing, including image decode and resize
+    pub average_processing_time: Duration,
+}
+
+impl InferenceStats {
+    pub fn new(
+        model_name: String,
+        device_name: String,
+        device_type: DeviceType,
+        endpoint_provider: EndpointProvider,
+        measure: Measure,
+        inference_times: Vec<Duration>,
+        processing_times: Vec<Duration>,
+    ) -> Self {
+        let measured_times = match measure {
+            Measure::Inference => &inference_times,
+            Measure::Total => &processing_times,
+        };
+        let number_of_images = measured_times.len() as u64;
+        let total_inference: Duration = measured_times.iter().sum();
+        let average_inference = average(measured_times);
+        let min_inference = *measured_times.iter().min().unwrap_or(&Duration::ZERO);
+        let max_inference = *measured_times.iter().max().unwrap_or(&Duration::ZERO);
+        let total_inference_secs = total_inference.as_secs_f64();
+        let images_per_second = if total_inference_secs > 1. {
+            number_of_images as f64 / total_inference_secs
+        } else {
+            0. // Not enough time to calculate images per second moar images please!
+        };
+        Self {
+            model_name: model_name.replace(".onnx", ""),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            device_name,
+            device_type,
+            platform: Platform::default(),
+            endpoint_provider,
+            average_inference,
+            min_inference,
+            max_inference,
+            total_inference,
+            images_per_second,
+            number_of_images,
+            measure,
+            average_inference_time: average(&inference_times),
+            average_processing_time: average(&processing_times),
+        }
+    }
+
+    pub fn print_table(&self) {
+        info!("Inference stats for {}", self.device_name);
+        info!("{}", InferenceStats::format_stats_header());
+        info!("{}", self.format_stats());
+    }
+
+    pub fn format_stats_header() -> String {
+        format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            "Model Name",
+            "Device Name",
+            "Version",
+            "Type",
+            "Platform",
+            "EndpointProvider",
+            "Images",
+            "Total [s]",
+            "Min [ms]",
+            "Max [ms]",
+            "Average [ms]",
+            "FPS",
+            "Measure",
+            "Inference Average [ms]",
+            "Total Average [ms]"
+        )
+    }
+
+    pub fn format_stats(&self) -> String {
+        let total_inference_secs = format!("{:.1}", self.total_inference.as_secs_f64());
+        let min_inference_ms = format!("{:.1}", self.min_inference.as_micros() as f64 / 1000.0);
+        let max_inference_ms = format!("{:.1}", self.max_inference.as_micros() as f64 / 1000.0);
+        let average_inference_ms =
+            format!("{:.1}", self.average_inference.as_micros() as f64 / 1000.0);
+        let images_per_second = format!("{:.1}", self.images_per_second);
+        let average_inference_time_ms = format!(
+            "{:.1}",
+            self.average_inference_time.as_micros() as f64 / 1000.0
+        );
+        let average_processing_time_ms = format!(
+            "{:.1}",
+            self.average_processing_time.as_micros() as f64 / 1000.0
+        );
+
+        format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            self.model_name,
+            self.device_name,
+            self.version,
+            self.device_type,
+            self.platform,
+            self.endpoint_provider,
+            self.number_of_images,
+            total_inference_secs,
+            min_inference_ms,
+            max_inference_ms,
+            average_inference_ms,
+            images_per_second,
+            self.measure,
+            average_inference_time_ms,
+            average_processing_time_ms
+        )
+    }
+
+    pub fn save_to_file(&self, path: Option<PathBuf>) -> std::io::Result<()> {
+        let Some(path) = path else {
+            return Ok(());
+        };
+        let sanitized_device_name: String = self
+            .device_name
+            .chars()
+            .filter(|c| c.is_alphanumeric() || *c == '_')
+            .collect();
+        let sanitized_model_name = self.model_name.replace(" ", "_").replace(".onnx", "");
+        let file_name =
+            format!("blue_onyx_{sanitized_device_name}_{sanitized_model_name}_report.txt");
+        let path = path.join(file_name);
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path.clone())?;
+
+        writeln!(file, "{}", InferenceStats::format_stats_header())?;
+        write!(file, "{}", self.format_stats())?;
+        info!(?path, "Inference stats saved");
+        Ok(())
+    }
+}
+
+/// Runs `iterations` detections on the built-in test image and logs the
+/// stats, a quick check of the speed and that the GPU is used
+pub fn run_self_benchmark(
+    detector: &mut Detector,
+    iterations: usize,
+) -> anyhow::Result<InferenceStats> {
+    info!("Running the startup self-benchmark with {iterations} detections");
+    let mut inference_times = Vec::with_capacity(iterations);
+    let mut processing_times = Vec::with_capacity(iterations);
+    let mut device = None;
+    // The same image every run, the motion gate would skip the inference
+    detector.without_motion_gate(|detector| {
+        for _ in 0..iterations {
+            let result = detector.detect(
+                Bytes::from(crate::DOG_BIKE_CAR_BYTES),
+                None,
+                DetectOptions::default(),
+            )?;
+            inference_times.push(result.inference_time);
+            processing_times.push(result.processing_time);
+            device = Some((result.device_type, result.endpoint_provider));
+        }
+        anyhow::Ok(())
+    })?;
+    let (device_type, endpoint_provider) =
+        device.ok_or_else(|| anyhow::anyhow!("The self-benchmark needs at least 1 detection"))?;
+    let device_name = match device_type {
+        DeviceType::CPU => cpu_model(),
+        DeviceType::GPU => gpu_model(detector.get_gpu_index() as usize),
+    };
+    let total_processing_time: Duration = processing_times.iter().sum();
+    let inference_stats = InferenceStats::new(
+        detector.get_model_name().clone(),
+        device_name,
+        device_type,
+        endpoint_provider,
+        Measure::Inference,
+        inference_times,
+        processing_times,
+    );
+    inference_stats.print_table();
+    info!(
+        "Self-benchmark: {iterations} detections on {} in {:?}, {:.1} FPS",
+        endpoint_provider,
+        total_processing_time,
+        iterations as f64 / total_processing_time.as_secs_f64().max(f64::EPSILON)
+    );
+    Ok(inference_stats)
+}
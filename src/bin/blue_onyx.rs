@@ -1,14 +1,18 @@
 use blue_onyx::{
-    blue_onyx_service as create_blue_onyx_service, cli::Cli, init_logging,
+    blue_onyx_service as create_blue_onyx_service, cli::Cli, init_logging, service_finished,
     system_info::system_info, update_log_level,
 };
-use tracing::{error, info, warn};
+use tracing::{info, warn};
 
 fn main() -> anyhow::Result<()> {
     let Some(mut current_args) = Cli::from_config_and_args()? else {
         return Ok(());
     };
-    let _guard = init_logging(current_args.log_level, &mut current_args.log_path)?;
+    let _guard = init_logging(
+        current_args.effective_log_level(),
+        current_args.log_format,
+        &mut current_args.log_path,
+    )?;
     system_info()?; // Print the configuration being used
     current_args.print_config();
 
@@ -33,35 +37,23 @@ fn main() -> anyhow::Result<()> {
             create_blue_onyx_service(current_args.clone())?;
 
         let should_restart = rt.block_on(async {
+            tokio::pin!(blue_onyx_service_future);
             // Wait for either the service to complete, restart to be requested, or global shutdown
-            tokio::select! {
-                result = blue_onyx_service_future => {
-                    match result {
-                        Ok((restart_requested, worker_handle)) => {
-                            // Wait for worker thread to complete if available
-                            if let Some(handle) = worker_handle {
-                                info!("Waiting for worker thread to complete...");
-                                if let Err(e) = handle.join() {
-                                    error!("Worker thread panicked: {:?}", e);
-                                }
-                            }
-                            restart_requested
-                        },
-                        Err(e) => {
-                            error!("Service failed: {}", e);
-                            false // Don't restart on error
-                        }
-                    }
-                }
+            let restart_requested = tokio::select! {
+                result = &mut blue_onyx_service_future => return service_finished(result),
                 _ = restart_token.cancelled() => {
                     info!("Restart requested via API");
                     true // Restart requested
-                }                _ = global_shutdown.cancelled() => {
+                }
+                _ = global_shutdown.cancelled() => {
                     info!("Global shutdown requested");
                     cancellation_token.cancel(); // Cancel the current service
                     false // Don't restart, just exit
                 }
-            }
+            };
+            // Let the server finish the requests it already accepted
+            service_finished(blue_onyx_service_future.await);
+            restart_requested
         });
         if should_restart {
             info!("Restarting server with updated configuration...");
@@ -70,14 +62,15 @@ fn main() -> anyhow::Result<()> {
             let new_args = Cli::from_config_and_args()?.expect("Should always have args");
 
             // Check if log level changed and update dynamically
-            if new_args.log_level != current_args.log_level {
+            let new_log_level = new_args.effective_log_level();
+            if new_log_level != current_args.effective_log_level() {
                 info!(
-                    old_level = ?current_args.log_level,
-                    new_level = ?new_args.log_level,
+                    old_level = ?current_args.effective_log_level(),
+                    new_level = ?new_log_level,
                     "Log level change detected, applying dynamically"
                 );
 
-                if let Err(e) = update_log_level(new_args.log_level) {
+                if let Err(e) = update_log_level(new_log_level) {
                     warn!("Failed to update log level dynamically: {}", e);
                 }
             }
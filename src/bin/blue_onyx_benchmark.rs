@@ -3,7 +3,10 @@
 //! This application benchmarks the inference performance of the rt-detrv2 model across
 //! different device configurations. It records statistics such as total inference time,
 //! average, minimum, and maximum inference durations, as well as images processed per second.
-//! The results is logged and can be saved to a file.
+//! The results is logged and can be saved to a file. With `--measure total` the statistics are
+//! computed from the total processing time, including image decode and resize, instead.
+//! With `--coco-results-path` the predictions are saved in the COCO results JSON format for
+//! mAP evaluation, run it on a directory of images with `--image`.
 //!
 //! The default model is the small rt-detrv2 model, and the default object classes are the 80
 //! standard COCO classes. The application can also filter the results to include only the specified
@@ -25,18 +28,24 @@
 //!
 use anyhow::bail;
 use blue_onyx::{
-    LogLevel,
+    LogFormat, LogLevel,
+    api::CocoResult,
+    benchmark::{InferenceStats, Measure},
     detector::{
-        Detector, DetectorConfig, DeviceType, EndpointProvider, ObjectDetectionModel, OnnxConfig,
+        DetectOptions, Detector, DetectorConfig, DeviceType, NmsMode, ObjectDetectionModel,
+        OnnxConfig,
     },
     download_models::Model,
-    image::load_image,
+    image::{ResizeFilter, is_supported_image, load_image},
     init_logging,
     system_info::{cpu_model, gpu_model, system_info},
 };
 use bytes::Bytes;
 use clap::Parser;
-use std::{io::Write, path::PathBuf, time::Duration};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
 use tracing::{error, info};
 
 #[derive(Parser)]
@@ -54,23 +63,31 @@ labels. The confidence threshold for object detection can be set, and the applic
 forced to use the CPU for inference. The application can also be configured to save the processed
 image and the reference image, repeat the image processing. ")]
 struct Cli {
-    /// Path to the image file
+    /// Path to the image file or a directory of images, every repetition runs
+    /// all images in the directory
     /// If not given default test image is used
     #[clap(long)]
     image: Option<PathBuf>,
+    /// Save the predictions of the first repetition to this file in the COCO
+    /// results JSON format, for mAP evaluation against ground truth. The
+    /// image_id is the number in the image file name, e.g. 397133 for
+    /// 000000397133.jpg, and the category_id the index in the object classes.
+    #[clap(long)]
+    coco_results_path: Option<PathBuf>,
     /// Path to the ONNX model file.
     /// If not specified, the default rt-detrv2 small model will be used
     /// provided it is available in the directory.
     #[clap(long)]
     pub model: Option<PathBuf>,
     /// Type of model type to use.
-    /// Default: rt-detrv2
-    #[clap(long, default_value_t = ObjectDetectionModel::RtDetrv2)]
-    pub object_detection_model_type: ObjectDetectionModel,
-    /// Path to the object classes yaml file
-    /// Default: coco_classes.yaml which is the 80 standard COCO classes
+    /// If not set, the type is inferred from the model outputs.
     #[clap(long)]
-    object_classes: Option<PathBuf>,
+    pub object_detection_model_type: Option<ObjectDetectionModel>,
+    /// Path to the object classes yaml file, multiple files separated by ','
+    /// are concatenated in order
+    /// Default: coco_classes.yaml which is the 80 standard COCO classes
+    #[clap(long, value_delimiter = ',', num_args = 1..)]
+    object_classes: Vec<PathBuf>,
     /// Filters the results to include only the specified labels. Provide labels separated by ','.
     /// Example: --object_filter "person,cup"
     #[arg(long, value_delimiter = ',', num_args = 1..)]
@@ -96,9 +113,19 @@ struct Cli {
     /// Save the reference image (only if save_image_path is provided)
     #[clap(long, default_value_t = false)]
     save_ref_image: bool,
+    /// Filter used to resize images to the model input size
+    #[clap(long, value_enum, default_value_t = ResizeFilter::Lanczos3)]
+    resize_filter: ResizeFilter,
+    /// How overlapping boxes are suppressed for the YOLO models
+    #[clap(long, value_enum, default_value_t = NmsMode::Hard)]
+    nms_mode: NmsMode,
     /// Repeat the image processing
     #[clap(long, default_value_t = 1)]
     repeat: u32,
+    /// Time the stats are computed from, only the model inference or the total
+    /// processing time including image decode and resize
+    #[clap(long, value_enum, default_value_t = Measure::Inference)]
+    measure: Measure,
     /// GPU
     #[clap(long, default_value_t = 0)]
     gpu_index: i32,
@@ -117,7 +144,7 @@ struct Cli {
 
 fn main() -> anyhow::Result<()> {
     let args = Cli::parse();
-    let _guard = init_logging(args.log_level, &mut None)?;
+    let _guard = init_logging(args.log_level, LogFormat::default(), &mut None)?;
     system_info()?;
 
     if args.list_models {
@@ -143,53 +170,107 @@ fn main() -> anyhow::Result<()> {
             model: args.model,
             force_cpu: args.force_cpu,
             gpu_index: args.gpu_index,
+            gpu_name: None,
             intra_threads: args.intra_threads,
             inter_threads: args.inter_threads,
+            graph_optimization_level: None,
+            enable_cpu_mem_arena: true,
+            models_dir: None,
+            ort_lib_path: None,
+            classes_yaml: None,
+            input_width: None,
+            input_height: None,
         },
         object_classes: args.object_classes,
         object_filter: args.object_filter,
+        object_exclude: vec![],
+        strict_object_filter: false,
         confidence_threshold: args.confidence_threshold,
         save_image_path: args.save_image_path,
+        save_image_template: None,
         save_ref_image: args.save_ref_image,
+        save_min_objects: 0,
+        output_image_format: Default::default(),
         timeout: Duration::MAX,
         object_detection_model: args.object_detection_model_type,
+        notify_cooldown: None,
+        timing_log: None,
+        detection_cache_size: 0,
+        warmup_iterations: 1,
+        benchmark_on_start: 0,
+        resize_filter: args.resize_filter,
+        nms_mode: args.nms_mode,
+        center_crop: false,
+        lenient_decode: false,
+        motion_threshold: None,
+        rotate: Default::default(),
+        worker_priority: Default::default(),
+        worker_pin_core: cfg!(windows),
+        adaptive_queue: false,
+        auto_timeout: false,
+        profiles: Default::default(),
     };
 
     let mut detector = Detector::new(detector_config)?;
 
-    let (image_bytes, image_name) = if let Some(image) = args.image {
-        (load_image(&image)?, image.to_string_lossy().to_string())
-    } else {
-        (
+    let images = match args.image {
+        Some(image) if image.is_dir() => load_image_dir(&image)?,
+        Some(image) => vec![(load_image(&image)?, image.to_string_lossy().to_string())],
+        None => vec![(
             Bytes::from(blue_onyx::DOG_BIKE_CAR_BYTES),
             "dog_bike_car.jpg".to_string(),
-        )
+        )],
     };
+    let detections = args.repeat.max(1) as usize * images.len();
 
-    let mut inference_times: Vec<Duration> = Vec::with_capacity(args.repeat as usize);
+    let mut inference_times: Vec<Duration> = Vec::with_capacity(detections);
+    let mut processing_times: Vec<Duration> = Vec::with_capacity(detections);
+    let mut coco_results = Vec::new();
 
     info!(
-        "Starting inference benchmark with {} repetitions",
-        args.repeat
+        "Starting inference benchmark with {} repetitions of {} images",
+        args.repeat,
+        images.len()
     );
     let start_time = std::time::Instant::now();
-    let mut predictions = detector.detect(image_bytes.clone(), Some(image_name.clone()), None)?;
-    if predictions.predictions.is_empty() {
-        error!(?predictions, "No objects detected");
-        bail!("No objects detected");
-    }
-    inference_times.push(predictions.inference_time);
-
-    for _ in 1..args.repeat {
-        predictions = detector.detect(image_bytes.clone(), Some(image_name.clone()), None)?;
-        inference_times.push(predictions.inference_time);
+    let mut predictions = None;
+    for repetition in 0..args.repeat.max(1) {
+        for (image_bytes, image_name) in &images {
+            let result = detector.detect(
+                image_bytes.clone(),
+                Some(image_name.clone()),
+                DetectOptions::default(),
+            )?;
+            inference_times.push(result.inference_time);
+            processing_times.push(result.processing_time);
+            if repetition == 0 && args.coco_results_path.is_some() {
+                let image_id = coco_image_id(image_name);
+                coco_results.extend(result.predictions.iter().filter_map(|prediction| {
+                    CocoResult::from_prediction(image_id, prediction, detector.get_object_classes())
+                }));
+            }
+            predictions = Some(result);
+        }
+        if repetition == 0 && images.len() == 1 {
+            let predictions = predictions.as_ref().expect("one image was processed");
+            if predictions.predictions.is_empty() {
+                error!(?predictions, "No objects detected");
+                bail!("No objects detected");
+            }
+        }
     }
+    let predictions = predictions.expect("at least one image was processed");
     let elapsed = start_time.elapsed();
     info!("All done predictions: {:#?} in {:?}", predictions, elapsed);
 
+    if let Some(path) = args.coco_results_path {
+        std::fs::write(&path, serde_json::to_string(&coco_results)?)?;
+        info!(?path, results = coco_results.len(), "COCO results saved");
+    }
+
     let device_name = match predictions.device_type {
         DeviceType::CPU => cpu_model(),
-        DeviceType::GPU => gpu_model(args.gpu_index as usize),
+        DeviceType::GPU => gpu_model(detector.get_gpu_index() as usize),
     };
 
     let inference_stats = InferenceStats::new(
@@ -197,7 +278,9 @@ fn main() -> anyhow::Result<()> {
         device_name,
         predictions.device_type,
         predictions.endpoint_provider,
+        args.measure,
         inference_times,
+        processing_times,
     );
 
     inference_stats.print_table();
@@ -205,155 +288,29 @@ fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
-#[derive(Debug, Clone)]
-pub enum Platform {
-    Linux,
-    Windows,
-}
-
-impl std::fmt::Display for Platform {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Platform::Linux => write!(f, "Linux  "),
-            Platform::Windows => write!(f, "Windows"),
-        }
-    }
-}
-impl Default for Platform {
-    fn default() -> Self {
-        if cfg!(target_os = "windows") {
-            Platform::Windows
-        } else if cfg!(target_os = "linux") {
-            Platform::Linux
-        } else {
-            panic!("Unsupported platform");
-        }
+/// All supported images in the directory, sorted by name
+fn load_image_dir(dir: &Path) -> anyhow::Result<Vec<(Bytes, String)>> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| is_supported_image(&path.to_string_lossy()))
+        .collect();
+    paths.sort();
+    if paths.is_empty() {
+        bail!("No images found in {}", dir.display());
     }
+    paths
+        .into_iter()
+        .map(|path| Ok((load_image(&path)?, path.to_string_lossy().to_string())))
+        .collect()
 }
 
-#[derive(Debug, Clone)]
-pub struct InferenceStats {
-    pub model_name: String,
-    pub version: String,
-    pub device_name: String,
-    pub device_type: DeviceType,
-    pub platform: Platform,
-    pub endpoint_provider: EndpointProvider,
-    pub number_of_images: u64,
-    pub total_inference: Duration,
-    pub images_per_second: f64,
-    pub min_inference: Duration,
-    pub max_inference: Duration,
-    pub average_inference: Duration,
-}
-
-impl InferenceStats {
-    pub fn new(
-        model_name: String,
-        device_name: String,
-        device_type: DeviceType,
-        endpoint_provider: EndpointProvider,
-        inference_times: Vec<Duration>,
-    ) -> Self {
-        let number_of_images = inference_times.len() as u64;
-        let total_inference: Duration = inference_times.iter().sum();
-        let average_inference =
-            Duration::from_secs_f64(total_inference.as_secs_f64() / number_of_images as f64);
-        let min_inference = *inference_times.iter().min().unwrap_or(&Duration::ZERO);
-        let max_inference = *inference_times.iter().max().unwrap_or(&Duration::ZERO);
-        let total_inference_secs = total_inference.as_secs_f64();
-        let images_per_second = if total_inference_secs > 1. {
-            number_of_images as f64 / total_inference_secs
-        } else {
-            0. // Not enough time to calculate images per second moar images please!
-        };
-        Self {
-            model_name: model_name.replace(".onnx", ""),
-            version: env!("CARGO_PKG_VERSION").to_string(),
-            device_name,
-            device_type,
-            platform: Platform::default(),
-            endpoint_provider,
-            average_inference,
-            min_inference,
-            max_inference,
-            total_inference,
-            images_per_second,
-            number_of_images,
-        }
-    }
-
-    pub fn print_table(&self) {
-        info!("Inference stats for {}", self.device_name);
-        info!("{}", InferenceStats::format_stats_header());
-        info!("{}", self.format_stats());
-    }
-
-    pub fn format_stats_header() -> String {
-        format!(
-            "{},{},{},{},{},{},{},{},{},{},{},{}",
-            "Model Name",
-            "Device Name",
-            "Version",
-            "Type",
-            "Platform",
-            "EndpointProvider",
-            "Images",
-            "Total [s]",
-            "Min [ms]",
-            "Max [ms]",
-            "Average [ms]",
-            "FPS"
-        )
-    }
-
-    pub fn format_stats(&self) -> String {
-        let total_inference_secs = format!("{:.1}", self.total_inference.as_secs_f64());
-        let min_inference_ms = format!("{:.1}", self.min_inference.as_micros() as f64 / 1000.0);
-        let max_inference_ms = format!("{:.1}", self.max_inference.as_micros() as f64 / 1000.0);
-        let average_inference_ms =
-            format!("{:.1}", self.average_inference.as_micros() as f64 / 1000.0);
-        let images_per_second = format!("{:.1}", self.images_per_second);
-
-        format!(
-            "{},{},{},{},{},{},{},{},{},{},{},{}",
-            self.model_name,
-            self.device_name,
-            self.version,
-            self.device_type,
-            self.platform,
-            self.endpoint_provider,
-            self.number_of_images,
-            total_inference_secs,
-            min_inference_ms,
-            max_inference_ms,
-            average_inference_ms,
-            images_per_second
-        )
-    }
-
-    pub fn save_to_file(&self, path: Option<PathBuf>) -> std::io::Result<()> {
-        let Some(path) = path else {
-            return Ok(());
-        };
-        let sanitized_device_name: String = self
-            .device_name
-            .chars()
-            .filter(|c| c.is_alphanumeric() || *c == '_')
-            .collect();
-        let sanitized_model_name = self.model_name.replace(" ", "_").replace(".onnx", "");
-        let file_name =
-            format!("blue_onyx_{sanitized_device_name}_{sanitized_model_name}_report.txt");
-        let path = path.join(file_name);
-        let mut file = std::fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(path.clone())?;
-
-        writeln!(file, "{}", InferenceStats::format_stats_header())?;
-        write!(file, "{}", self.format_stats())?;
-        info!(?path, "Inference stats saved");
-        Ok(())
-    }
+/// COCO image id from the digits of the file name, 000000397133.jpg is
+/// 397133. Images without digits get 0.
+fn coco_image_id(image_name: &str) -> u64 {
+    let file_stem = Path::new(image_name)
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let digits: String = file_stem.chars().filter(char::is_ascii_digit).collect();
+    digits.parse().unwrap_or(0)
 }
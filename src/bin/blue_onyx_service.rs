@@ -36,7 +36,8 @@ fn main() {
 #[cfg(windows)]
 mod blue_onyx_service {
     use blue_onyx::{
-        ServiceResult, blue_onyx_service, cli::Cli, init_service_logging, update_service_log_level,
+        ModelUnavailable, ServiceResult, blue_onyx_service, cli::Cli, ensure_model_files,
+        init_service_logging, is_embedded_model, service_finished, update_service_log_level,
     };
     use std::{ffi::OsString, future::Future, time::Duration};
     use tokio_util::sync::CancellationToken;
@@ -53,6 +54,14 @@ mod blue_onyx_service {
 
     const SERVICE_NAME: &str = "BlueOnyxService";
     const SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
+    /// Delay before the first retry of a failed start, doubled on every
+    /// failure up to MAX_RETRY_DELAY
+    const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(5);
+    const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+    /// Starts that may fail on a missing model before the service stops as failed
+    const MAX_MODEL_UNAVAILABLE_ATTEMPTS: u32 = 10;
+    /// Service specific exit code reported when the model could not be found or downloaded
+    const MODEL_UNAVAILABLE_EXIT_CODE: u32 = 1;
 
     pub fn run() -> Result<()> {
         service_dispatcher::start(SERVICE_NAME, ffi_service_main)
@@ -92,6 +101,8 @@ mod blue_onyx_service {
         current_args.print_config();
 
         // Main service loop with restart support
+        let mut retry_delay = INITIAL_RETRY_DELAY;
+        let mut model_unavailable_attempts = 0;
         loop {
             // Reload configuration on each restart to pick up changes
             if let Ok(updated_args) = Cli::for_service() {
@@ -112,24 +123,55 @@ mod blue_onyx_service {
             } else {
                 info!("Using previous configuration (failed to reload config)");
             }
-            let (blue_onyx_service, cancellation_token, restart_token) =
-                match blue_onyx_service(current_args.clone()) {
-                    Ok(v) => v,
-                    Err(err) => {
+            let init_result =
+                ensure_models(&current_args).and_then(|()| blue_onyx_service(current_args.clone()));
+            let (blue_onyx_service, cancellation_token, restart_token) = match init_result {
+                Ok(v) => v,
+                Err(err) if err.downcast_ref::<ModelUnavailable>().is_some() => {
+                    model_unavailable_attempts += 1;
+                    if model_unavailable_attempts >= MAX_MODEL_UNAVAILABLE_ATTEMPTS {
                         error!(
                             ?err,
-                            "Failed to init blue onyx service, will retry after delay"
+                            attempts = model_unavailable_attempts,
+                            "Model not found and could not be downloaded, giving up. Copy the model next to the service or make sure the machine is online, then start the service again"
                         );
-                        std::thread::sleep(Duration::from_secs(5));
-                        continue;
+                        report_failed(MODEL_UNAVAILABLE_EXIT_CODE);
+                        break;
                     }
-                };
+                    error!(
+                        ?err,
+                        ?retry_delay,
+                        attempts = model_unavailable_attempts,
+                        "Model not found and could not be downloaded, the machine may be offline, will retry after delay"
+                    );
+                    retry_delay = backoff(retry_delay);
+                    continue;
+                }
+                Err(err) => {
+                    error!(
+                        ?err,
+                        ?retry_delay,
+                        "Failed to init blue onyx service, will retry after delay"
+                    );
+                    retry_delay = backoff(retry_delay);
+                    continue;
+                }
+            };
             let (should_restart, status_handle) =
                 match run_service(blue_onyx_service, cancellation_token, restart_token.clone()) {
-                    Ok((restart, handle)) => (restart, Some(handle)),
+                    Ok((restart, handle)) => {
+                        // The service started, later failures start over with a short delay
+                        retry_delay = INITIAL_RETRY_DELAY;
+                        model_unavailable_attempts = 0;
+                        (restart, Some(handle))
+                    }
                     Err(err) => {
-                        error!(?err, "Blue onyx service failed, will retry after delay");
-                        std::thread::sleep(Duration::from_secs(5));
+                        error!(
+                            ?err,
+                            ?retry_delay,
+                            "Blue onyx service failed, will retry after delay"
+                        );
+                        retry_delay = backoff(retry_delay);
                         (true, None) // Force restart after error
                     }
                 };
@@ -155,6 +197,60 @@ mod blue_onyx_service {
             }
         }
     }
+    /// Sleeps for the retry delay and returns the delay for the next retry
+    fn backoff(retry_delay: Duration) -> Duration {
+        std::thread::sleep(retry_delay);
+        (retry_delay * 2).min(MAX_RETRY_DELAY)
+    }
+
+    /// Makes sure the model files of the default and the additional models
+    /// exist, downloading them if needed, so a missing model fails the start
+    /// instead of leaving the service running without a detector
+    fn ensure_models(args: &Cli) -> anyhow::Result<()> {
+        let detector_configs = std::iter::once(args.detector_config()).chain(
+            args.named_detector_configs()
+                .into_iter()
+                .map(|(_, detector_config)| detector_config),
+        );
+        for detector_config in detector_configs {
+            let onnx_config = detector_config.object_detection_onnx_config;
+            if is_embedded_model(onnx_config.model_file_name().as_deref()) {
+                continue;
+            }
+            ensure_model_files(
+                onnx_config.model_file_name(),
+                onnx_config.models_dir.as_deref(),
+                onnx_config.classes_yaml.as_deref(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Reports the service as stopped with a service specific error code so
+    /// the failure shows in the service manager and its recovery actions apply
+    fn report_failed(exit_code: u32) {
+        let status_handle = match service_control_handler::register(SERVICE_NAME, |_| {
+            ServiceControlHandlerResult::NotImplemented
+        }) {
+            Ok(status_handle) => status_handle,
+            Err(err) => {
+                error!(?err, "Failed to register the service control handler");
+                return;
+            }
+        };
+        if let Err(err) = status_handle.set_service_status(ServiceStatus {
+            service_type: SERVICE_TYPE,
+            current_state: ServiceState::Stopped,
+            controls_accepted: ServiceControlAccept::empty(),
+            exit_code: ServiceExitCode::ServiceSpecific(exit_code),
+            checkpoint: 0,
+            wait_hint: Duration::default(),
+            process_id: None,
+        }) {
+            error!(?err, "Failed to report the service as failed");
+        }
+    }
+
     pub fn run_service(
         blue_onyx_service: impl Future<Output = ServiceResult>,
         cancellation_token: CancellationToken,
@@ -221,27 +317,13 @@ mod blue_onyx_service {
         })?;
 
         let should_restart = rt.block_on(async {
+            tokio::pin!(blue_onyx_service);
             tokio::select! {
-                result = blue_onyx_service => {
-                    match result {
-                        Ok((restart_requested, worker_handle)) => {
-                            // Wait for worker thread to complete if available
-                            if let Some(handle) = worker_handle {
-                                info!("Waiting for worker thread to complete...");
-                                if let Err(e) = handle.join() {
-                                    error!("Worker thread panicked: {:?}", e);
-                                }
-                            }
-                            restart_requested
-                        },
-                        Err(err) => {
-                            error!(?err, "Blue onyx service encountered an error");
-                            false // Don't restart on error
-                        }
-                    }
-                }
+                result = &mut blue_onyx_service => service_finished(result),
                 _ = restart_token.cancelled() => {
                     info!("Restart signal received");
+                    // Let the server finish the requests it already accepted
+                    service_finished(blue_onyx_service.await);
                     true // Restart requested
                 }
             }
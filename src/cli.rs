@@ -1,60 +1,194 @@
-use crate::{LogLevel, detector::ObjectDetectionModel, download_models::Model, init_logging};
-use clap::Parser;
+use crate::{
+    LogFormat, LogLevel, NANO_RF_DETR_MODEL_FILE_NAME,
+    api::ConfidenceScale,
+    detector::{
+        DetectionProfile, DetectorConfig, GraphOptimizationLevel, NmsMode, ObjectDetectionModel,
+        OnnxConfig,
+    },
+    download_models::Model,
+    image::{OutputImageFormat, ResizeFilter, Rotation},
+    init_logging,
+    worker::WorkerPriority,
+};
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, parser::ValueSource};
 use serde::{Deserialize, Serialize};
-use std::{path::PathBuf, time::Duration};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 #[derive(Parser, Serialize, Deserialize, Clone)]
 #[command(author = "Marcus Asteborg", version=env!("CARGO_PKG_VERSION"), about = "TODO")]
 #[serde(default)]
 pub struct Cli {
     /// Path to configuration file (JSON format)
-    #[arg(long)]
+    #[arg(long, env = "BLUE_ONYX_CONFIG")]
     #[serde(skip)]
     pub config: Option<PathBuf>,
+    /// Never create or rewrite config files automatically, e.g. on read-only
+    /// filesystems. An explicitly provided --config is still read.
+    #[arg(long, env = "BLUE_ONYX_NO_CONFIG_WRITE")]
+    #[serde(skip)]
+    pub no_config_write: bool,
     /// The port on which the server will listen for HTTP requests.
     /// Default is 32168. Example usage: --port 1337
-    #[arg(long, default_value_t = 32168)]
+    #[arg(long, env = "BLUE_ONYX_PORT", default_value_t = 32168)]
     pub port: u16,
+    /// The address the server listens on, 0.0.0.0 for all IPv4 interfaces.
+    /// An IPv6 address like :: also accepts IPv4 clients (dual-stack).
+    /// Example usage: --bind-address ::
+    #[arg(long, env = "BLUE_ONYX_BIND_ADDRESS", default_value_t = IpAddr::V4(Ipv4Addr::UNSPECIFIED))]
+    pub bind_address: IpAddr,
     /// Duration to wait for a response from the detection worker.
     /// Ideally, this should be similar to the client's timeout setting.
-    #[arg(long, default_value = "15", value_parser = parse_duration)]
+    #[arg(long, env = "BLUE_ONYX_REQUEST_TIMEOUT", default_value = "15", value_parser = parse_duration)]
     #[serde(with = "duration_serde")]
     pub request_timeout: Duration,
+    /// Raise the request timeout to a multiple of the processing time measured
+    /// at startup when the model is too slow for the configured timeout, the
+    /// configured timeout stays the minimum.
+    #[arg(long, env = "BLUE_ONYX_AUTO_TIMEOUT")]
+    pub auto_timeout: bool,
     /// Worker queue size.
     /// The number of requests that can be queued before the server starts rejecting them.
     /// If not set, the server will estimate the queue size based on the timeout and the
     /// inference performance.
     /// This estimation is based on the timeout and the expected number of requests per second.
-    #[arg(long)]
+    #[arg(long, env = "BLUE_ONYX_WORKER_QUEUE_SIZE")]
     pub worker_queue_size: Option<usize>,
+    /// Recompute the number of queued requests accepted from the average
+    /// processing time of recent detections, so the backpressure threshold
+    /// follows the live throughput instead of the startup estimate. The
+    /// worker queue size stays the upper bound.
+    #[arg(long, env = "BLUE_ONYX_ADAPTIVE_QUEUE")]
+    pub adaptive_queue: bool,
+    /// Number of detector workers, each with its own model session, pulling
+    /// requests from the same queue. More workers improve CPU throughput but
+    /// each worker uses intra_threads threads and its own copy of the model.
+    #[arg(long, env = "BLUE_ONYX_WORKERS", default_value_t = 1)]
+    pub workers: usize,
+    /// Interval in seconds between heartbeat logs summarizing the requests per
+    /// second, average inference time and dropped requests since the last
+    /// heartbeat. 0 disables the heartbeat.
+    #[arg(long, env = "BLUE_ONYX_HEARTBEAT_SECS", default_value_t = 0)]
+    pub heartbeat_secs: u64,
+    /// Prometheus push gateway URL the metrics are pushed to, e.g.
+    /// http://pushgateway:9091/metrics/job/blue_onyx. For deployments that
+    /// can't be scraped.
+    #[arg(long, env = "BLUE_ONYX_METRICS_PUSH_URL")]
+    pub metrics_push_url: Option<String>,
+    /// Interval in seconds between metrics pushes to metrics_push_url
+    #[arg(long, env = "BLUE_ONYX_METRICS_PUSH_INTERVAL", default_value_t = 15)]
+    pub metrics_push_interval: u64,
+    /// Compress responses with gzip or deflate when the client sends a matching
+    /// Accept-Encoding header.
+    #[arg(long, env = "BLUE_ONYX_COMPRESSION", default_value_t = false)]
+    pub compression: bool,
+    /// Scale of the prediction confidences in the JSON detection response,
+    /// fraction (0-1) as CodeProject.AI or percent (0-100).
+    #[arg(long, env = "BLUE_ONYX_CONFIDENCE_SCALE", value_enum, default_value_t = ConfidenceScale::Fraction)]
+    pub confidence_scale: ConfidenceScale,
+    /// Module id reported in the detection responses and the custom model
+    /// list, e.g. to look like the CodeProject.AI module an existing Blue Iris
+    /// setup expects. By default the model name is reported.
+    #[clap(long, env = "BLUE_ONYX_MODULE_ID")]
+    pub module_id: Option<String>,
+    /// Module name reported in the detection responses and the custom model
+    /// list, by default no module name is reported.
+    #[clap(long, env = "BLUE_ONYX_MODULE_NAME")]
+    pub module_name: Option<String>,
+    /// Restart the server when a detector worker thread dies, by default the
+    /// detector is marked as failed and requests to it return an error.
+    #[arg(
+        long,
+        env = "BLUE_ONYX_RESTART_ON_WORKER_FAILURE",
+        default_value_t = false
+    )]
+    pub restart_on_worker_failure: bool,
+    /// Maximum number of open HTTP connections, new connections over the limit
+    /// get a 503 and are closed. 0 means no limit.
+    #[arg(long, env = "BLUE_ONYX_MAX_CONNECTIONS", default_value_t = 0)]
+    pub max_connections: usize,
+    /// Close keep-alive connections that have not sent a new request within
+    /// this many seconds. 0 keeps idle connections open.
+    #[arg(long, env = "BLUE_ONYX_KEEP_ALIVE_SECS", default_value_t = 0)]
+    pub keep_alive_secs: u64,
     /// Path to the ONNX model file.
     /// If not specified, the default rt-detrv2 small model will be used
     /// provided it is available in the directory.
-    #[clap(long)]
+    #[clap(long, env = "BLUE_ONYX_MODEL")]
     pub model: Option<PathBuf>,
+    /// Directory models are loaded from and downloaded to.
+    /// If not specified, the directory of the executable is used.
+    #[clap(long, env = "BLUE_ONYX_MODELS_DIR")]
+    pub models_dir: Option<PathBuf>,
+    /// Restart the server to load the model again when its file in the models
+    /// directory changes, e.g. after copying a retrained model over it.
+    #[arg(long, env = "BLUE_ONYX_WATCH_MODEL", default_value_t = false)]
+    pub watch_model: bool,
+    /// Path to the ONNX Runtime library (onnxruntime.dll or libonnxruntime.so)
+    /// to load instead of the one shipped next to the executable, e.g. a build
+    /// with more execution providers. It must be ONNX Runtime 1.22.x, the
+    /// version blue-onyx is built against.
+    #[clap(long, env = "ORT_DYLIB_PATH")]
+    pub ort_lib_path: Option<PathBuf>,
+    /// Model input width in pixels, overrides the width read from the model.
+    /// Only needed when the model input shape can't be parsed, a width that
+    /// does not match a known model shape is rejected.
+    #[clap(long, env = "BLUE_ONYX_INPUT_WIDTH")]
+    pub input_width: Option<usize>,
+    /// Model input height in pixels, overrides the height read from the model.
+    #[clap(long, env = "BLUE_ONYX_INPUT_HEIGHT")]
+    pub input_height: Option<usize>,
     /// Type of model type to use.
-    /// Default: rt-detrv2
-    #[clap(long, default_value_t = ObjectDetectionModel::RfDetr)]
-    pub object_detection_model_type: ObjectDetectionModel,
-    /// Path to the object classes yaml file
+    /// If not set, the type is inferred from the model outputs.
+    #[clap(long, env = "BLUE_ONYX_OBJECT_DETECTION_MODEL_TYPE")]
+    pub object_detection_model_type: Option<ObjectDetectionModel>,
+    /// Path to the object classes yaml file, or a .txt file with one class per line.
+    /// Multiple files separated by ',' are concatenated in order, for models
+    /// that combine detection heads with different label sets.
     /// Default: coco_classes.yaml which is the 80 standard COCO classes
-    #[clap(long)]
-    pub object_classes: Option<PathBuf>,
+    #[clap(long, env = "BLUE_ONYX_OBJECT_CLASSES", value_delimiter = ',', num_args = 1..)]
+    #[serde(deserialize_with = "object_classes_serde::deserialize")]
+    pub object_classes: Vec<PathBuf>,
     /// Filters the results to include only the specified labels. Provide labels separated by ','.
     /// Example: --object_filter "person,cup"
-    #[arg(long, value_delimiter = ',', num_args = 1..)]
+    #[arg(long, env = "BLUE_ONYX_OBJECT_FILTER", value_delimiter = ',', num_args = 1..)]
     pub object_filter: Vec<String>,
+    /// Excludes the specified labels from the results. Provide labels separated by ','.
+    /// Labels in both object_filter and object_exclude are excluded.
+    /// Example: --object_exclude "car,truck"
+    #[arg(long, env = "BLUE_ONYX_OBJECT_EXCLUDE", value_delimiter = ',', num_args = 1..)]
+    pub object_exclude: Vec<String>,
+    /// Fail at startup if an object_filter or object_exclude label does not
+    /// match any object class instead of only logging a warning.
+    #[clap(long, env = "BLUE_ONYX_STRICT_OBJECT_FILTER")]
+    pub strict_object_filter: bool,
     /// Sets the level of logging
-    #[clap(long, value_enum, default_value_t = LogLevel::Info)]
+    #[clap(long, env = "BLUE_ONYX_LOG_LEVEL", value_enum, default_value_t = LogLevel::Info)]
     pub log_level: LogLevel,
+    /// Only log errors, takes precedence over log_level
+    #[clap(short, long, conflicts_with = "verbose")]
+    #[serde(skip)]
+    pub quiet: bool,
+    /// Log debug messages, -vv also logs trace messages.
+    /// Takes precedence over log_level.
+    #[clap(short, long, action = clap::ArgAction::Count)]
+    #[serde(skip)]
+    pub verbose: u8,
+    /// Sets the format of the log output, json writes one JSON object per line
+    #[clap(long, env = "BLUE_ONYX_LOG_FORMAT", value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
     /// If log_path is set, then stdout logging will be disabled and it will log to file
-    #[clap(long)]
+    #[clap(long, env = "BLUE_ONYX_LOG_PATH")]
     pub log_path: Option<PathBuf>,
     /// Confidence threshold for object detection
-    #[clap(long, default_value_t = 0.5)]
+    #[clap(long, env = "BLUE_ONYX_CONFIDENCE_THRESHOLD", default_value_t = 0.5)]
     pub confidence_threshold: f32,
     /// Force using CPU for inference
-    #[clap(long, default_value_t = false)]
+    #[clap(long, env = "BLUE_ONYX_FORCE_CPU", default_value_t = false)]
     pub force_cpu: bool,
     /// Intra thread parallelism max is CPU cores - 1.
     /// On Windows, you can use high thread counts, but if you use too high
@@ -62,10 +196,10 @@ pub struct Cli {
     /// So default is 1, then you can increase it if you want to test the
     /// performance.
     #[cfg(target_os = "windows")]
-    #[clap(long, default_value_t = 16)]
+    #[clap(long, env = "BLUE_ONYX_INTRA_THREADS", default_value_t = 16)]
     pub intra_threads: usize,
     #[cfg(not(target_os = "windows"))]
-    #[clap(long, default_value_t = 2)]
+    #[clap(long, env = "BLUE_ONYX_INTRA_THREADS", default_value_t = 2)]
     pub intra_threads: usize,
     /// Inter thread parallelism max is CPU cores - 1.
     /// On Windows, you can use high thread counts, but if you use too high
@@ -73,26 +207,137 @@ pub struct Cli {
     /// So default is 2, then you can increase it if you want to test the
     /// performance.
     #[cfg(target_os = "windows")]
-    #[clap(long, default_value_t = 16)]
+    #[clap(long, env = "BLUE_ONYX_INTER_THREADS", default_value_t = 16)]
     pub inter_threads: usize,
     #[cfg(not(target_os = "windows"))]
-    #[clap(long, default_value_t = 2)]
+    #[clap(long, env = "BLUE_ONYX_INTER_THREADS", default_value_t = 2)]
     pub inter_threads: usize,
+    /// ONNX graph optimization level. Higher levels take longer at startup
+    /// but can run faster. If not set, the ONNX Runtime default is used.
+    #[clap(long, env = "BLUE_ONYX_GRAPH_OPTIMIZATION_LEVEL", value_enum)]
+    pub graph_optimization_level: Option<GraphOptimizationLevel>,
+    /// Use the ONNX Runtime arena allocator for CPU memory. Disable it on
+    /// memory constrained devices to lower steady-state memory usage.
+    #[clap(long, env = "BLUE_ONYX_ENABLE_CPU_MEM_ARENA", default_value_t = true, action = clap::ArgAction::Set)]
+    pub enable_cpu_mem_arena: bool,
     /// Optional path to save the processed images
-    #[clap(long)]
+    #[clap(long, env = "BLUE_ONYX_SAVE_IMAGE_PATH")]
     pub save_image_path: Option<PathBuf>,
+    /// Optional path template for processed images relative to save_image_path,
+    /// e.g. "{date}/{camera}/{name}_od.jpg". Supports {date}, {time}, {label},
+    /// {camera} and {name}
+    #[clap(long, env = "BLUE_ONYX_SAVE_IMAGE_TEMPLATE")]
+    pub save_image_template: Option<String>,
     /// Save the reference image (only if save_image_path is provided)
-    #[clap(long, default_value_t = false)]
+    #[clap(long, env = "BLUE_ONYX_SAVE_REF_IMAGE", default_value_t = false)]
     pub save_ref_image: bool,
+    /// Only save processed and reference images with at least one detection,
+    /// same as save_min_objects 1
+    #[clap(
+        long,
+        env = "BLUE_ONYX_SAVE_ONLY_ON_DETECTION",
+        default_value_t = false
+    )]
+    pub save_only_on_detection: bool,
+    /// Only save processed and reference images with at least this many detections
+    #[clap(long, env = "BLUE_ONYX_SAVE_MIN_OBJECTS", default_value_t = 0)]
+    pub save_min_objects: usize,
+    /// Format of the saved processed images and of the annotated images of
+    /// the test page. Png is lossless for archival at the cost of larger files.
+    #[clap(long, env = "BLUE_ONYX_OUTPUT_IMAGE_FORMAT", value_enum, default_value_t = OutputImageFormat::Jpeg)]
+    pub output_image_format: OutputImageFormat,
     /// GPU Index, best effort to select the correct one if multiple GPUs exist.
     /// Default is 0. The list and actual GPU index might differ.
     /// If the wrong GPU is selected, try changing this value.
     /// Verify through GPU usage to ensure the correct GPU is selected.
-    #[clap(long, default_value_t = 0)]
+    #[clap(long, env = "BLUE_ONYX_GPU_INDEX", default_value_t = 0)]
     pub gpu_index: i32,
+    /// Select the GPU by a case-insensitive substring of its adapter name,
+    /// e.g. --gpu-name "RTX". Overrides gpu_index when set (Windows only).
+    #[clap(long, env = "BLUE_ONYX_GPU_NAME")]
+    pub gpu_name: Option<String>,
     /// Save inference stats to file
-    #[clap(long)]
+    #[clap(long, env = "BLUE_ONYX_SAVE_STATS_PATH")]
     pub save_stats_path: Option<PathBuf>,
+    /// Minimum number of seconds between detection notifications for the same label.
+    /// Setting this enables detection notifications. Repeated detections of a label
+    /// within the cooldown are still returned to the client but do not fire a new
+    /// notification.
+    #[clap(long, env = "BLUE_ONYX_NOTIFY_COOLDOWN_SECS")]
+    pub notify_cooldown_secs: Option<u64>,
+    /// Append a CSV row per detection with the image name, a timestamp and the
+    /// decode, resize, pre-processing, inference and post-processing times in ms.
+    #[clap(long, env = "BLUE_ONYX_TIMING_LOG")]
+    pub timing_log: Option<PathBuf>,
+    /// Number of detection responses to cache for clients that resend the same
+    /// image, e.g. on retries. A request with the same image bytes, min_confidence
+    /// and profile as a cached one gets the cached response without inference.
+    /// 0 disables the cache.
+    #[clap(long, env = "BLUE_ONYX_DETECTION_CACHE_SIZE", default_value_t = 0)]
+    pub detection_cache_size: usize,
+    /// Filter used to resize images to the model input size.
+    /// Nearest is the fastest on CPU at a small loss of accuracy.
+    #[clap(long, env = "BLUE_ONYX_RESIZE_FILTER", value_enum, default_value_t = ResizeFilter::Lanczos3)]
+    pub resize_filter: ResizeFilter,
+    /// How overlapping boxes of the same label are suppressed for the YOLO
+    /// models. Hard drops them, Linear and Gaussian soft-NMS decay their
+    /// confidence instead which keeps more objects in crowded scenes.
+    #[clap(long, env = "BLUE_ONYX_NMS_MODE", value_enum, default_value_t = NmsMode::Hard)]
+    pub nms_mode: NmsMode,
+    /// Crop the largest centered square (the model aspect ratio) before resizing
+    /// instead of stretching the whole frame. Useful when the subject is always
+    /// in the middle of the frame, objects near the edges are not detected.
+    #[clap(long, env = "BLUE_ONYX_CENTER_CROP", default_value_t = false)]
+    pub center_crop: bool,
+    /// Decode slightly malformed JPEGs, e.g. from cheap cameras, best effort
+    /// instead of rejecting them. A warning is logged for each image that
+    /// needed it since the image quality may be affected.
+    #[clap(long, env = "BLUE_ONYX_LENIENT_DECODE", default_value_t = false)]
+    pub lenient_decode: bool,
+    /// Skip inference for frames that barely changed, e.g. from static cameras.
+    /// A frame whose downsampled grayscale mean absolute difference (0-255) to
+    /// the last inferred frame of the same camera is below this gets that
+    /// frame's result again. Unset runs inference on every frame.
+    #[clap(long, env = "BLUE_ONYX_MOTION_THRESHOLD")]
+    pub motion_threshold: Option<f32>,
+    /// Rotate images clockwise by 0, 90, 180 or 270 degrees before detection,
+    /// for cameras mounted sideways. Boxes are returned in the coordinates of
+    /// the image as sent and saved images are upright.
+    #[clap(long, env = "BLUE_ONYX_ROTATE", default_value_t = Rotation::None)]
+    pub rotate: Rotation,
+    /// Number of warmup detections to run when loading the model, 0 skips the warmup.
+    /// The warmup timings are also used to estimate the worker queue size.
+    #[clap(long, env = "BLUE_ONYX_WARMUP_ITERATIONS", default_value_t = 1)]
+    pub warmup_iterations: usize,
+    /// Run this many detections on the built-in test image after the warmup and
+    /// log the benchmark stats and FPS before serving, a quick check of the
+    /// speed and that the GPU is used. 0 skips it.
+    #[clap(long, env = "BLUE_ONYX_BENCHMARK_ON_START", default_value_t = 0)]
+    pub benchmark_on_start: usize,
+    /// Scheduling priority of the detector worker threads.
+    /// Defaults to time-critical on Windows and normal elsewhere, raising the
+    /// priority on Linux requires CAP_SYS_NICE.
+    #[clap(long, env = "BLUE_ONYX_WORKER_PRIORITY", value_enum, default_value_t = WorkerPriority::default())]
+    pub worker_priority: WorkerPriority,
+    /// Pin the detector worker thread to the core it starts on, only applies
+    /// with a single worker. Defaults to true on Windows.
+    #[clap(long, env = "BLUE_ONYX_WORKER_PIN_CORE", default_value_t = cfg!(windows), action = clap::ArgAction::Set)]
+    pub worker_pin_core: bool,
+    /// Additional models loaded next to the default model (config file only).
+    /// Requests are routed to them with /v1/vision/detection/<name> or a `model`
+    /// multipart field, requests without a model use the default model.
+    #[clap(skip)]
+    pub models: Vec<NamedModel>,
+    /// Class YAML files by model file name (config file only), e.g.
+    /// {"delivery.onnx": "custom/delivery.yaml"}. Models without an entry use
+    /// the YAML with the same name as the model.
+    #[clap(skip)]
+    pub model_classes: HashMap<String, PathBuf>,
+    /// Detection profiles with confidence_threshold, object_filter and
+    /// min_box_area overrides (config file only). Requests select one with a
+    /// `profile` multipart field.
+    #[clap(skip)]
+    pub profiles: HashMap<String, DetectionProfile>,
     /// Path to download all models to
     /// This command will download models to the specified path and then exit.
     /// Use --download-rt-detr2 or --download-yolo5 to download specific model types,
@@ -119,20 +364,83 @@ pub struct Cli {
     #[clap(long)]
     #[serde(skip)]
     pub list_models: bool,
+    /// Print the effective configuration, after merging the config file and
+    /// command line arguments, as JSON to stdout and exit
+    #[clap(long)]
+    #[serde(skip)]
+    pub print_config_json: bool,
+    /// Path to a video file to run detection on instead of starting the server.
+    /// Sampled frames are annotated and saved together with a JSON timeline of
+    /// detections to save_image_path, or next to the video if not set.
+    #[cfg(feature = "video")]
+    #[clap(long)]
+    #[serde(skip)]
+    pub video: Option<PathBuf>,
+    /// Run detection on every Nth frame of the video (use with --video)
+    #[cfg(feature = "video")]
+    #[clap(long, default_value_t = 1)]
+    #[serde(skip)]
+    pub fps_sample: usize,
+}
+
+/// An additional model that requests can be routed to by name
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct NamedModel {
+    /// Name used to route requests to this model
+    pub name: String,
+    /// Path to the ONNX model file
+    pub model: PathBuf,
+    /// Type of the model, inferred from the model outputs if not set
+    #[serde(default)]
+    pub object_detection_model_type: Option<ObjectDetectionModel>,
+    /// Paths to the object classes yaml or .txt files, concatenated in order
+    #[serde(default, deserialize_with = "object_classes_serde::deserialize")]
+    pub object_classes: Vec<PathBuf>,
+    /// Model input size overrides, the input_width and input_height of the
+    /// default model don't apply to named models
+    #[serde(default)]
+    pub input_width: Option<usize>,
+    #[serde(default)]
+    pub input_height: Option<usize>,
 }
 
 impl Default for Cli {
     fn default() -> Self {
         Self {
             config: None,
+            no_config_write: false,
             port: 32168,
+            bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
             request_timeout: Duration::from_secs(15),
+            auto_timeout: false,
             worker_queue_size: None,
+            adaptive_queue: false,
+            workers: 1,
+            heartbeat_secs: 0,
+            metrics_push_url: None,
+            metrics_push_interval: 15,
+            compression: false,
+            confidence_scale: ConfidenceScale::Fraction,
+            module_id: None,
+            module_name: None,
+            restart_on_worker_failure: false,
+            max_connections: 0,
+            keep_alive_secs: 0,
             model: None,
-            object_detection_model_type: ObjectDetectionModel::default(),
-            object_classes: None,
+            models_dir: None,
+            watch_model: false,
+            ort_lib_path: None,
+            input_width: None,
+            input_height: None,
+            object_detection_model_type: None,
+            object_classes: vec![],
             object_filter: vec![],
+            object_exclude: vec![],
+            strict_object_filter: false,
             log_level: LogLevel::Info,
+            quiet: false,
+            verbose: 0,
+            log_format: LogFormat::Text,
             log_path: None,
             confidence_threshold: 0.5,
             force_cpu: false,
@@ -144,44 +452,105 @@ impl Default for Cli {
             inter_threads: 16,
             #[cfg(not(target_os = "windows"))]
             inter_threads: 2,
+            graph_optimization_level: None,
+            enable_cpu_mem_arena: true,
             save_image_path: None,
+            save_image_template: None,
             save_ref_image: false,
+            save_only_on_detection: false,
+            save_min_objects: 0,
+            output_image_format: OutputImageFormat::Jpeg,
             gpu_index: 0,
+            gpu_name: None,
             save_stats_path: None,
+            notify_cooldown_secs: None,
+            timing_log: None,
+            detection_cache_size: 0,
+            resize_filter: ResizeFilter::default(),
+            nms_mode: NmsMode::default(),
+            center_crop: false,
+            lenient_decode: false,
+            motion_threshold: None,
+            rotate: Rotation::None,
+            warmup_iterations: 1,
+            benchmark_on_start: 0,
+            worker_priority: WorkerPriority::default(),
+            worker_pin_core: cfg!(windows),
+            models: vec![],
+            model_classes: HashMap::new(),
+            profiles: HashMap::new(),
             download_model_path: None,
             download_rt_detr2: false,
             download_yolo5: false,
             download_all_models: false,
             list_models: false,
+            print_config_json: false,
+            #[cfg(feature = "video")]
+            video: None,
+            #[cfg(feature = "video")]
+            fps_sample: 1,
         }
     }
 }
 
 impl Cli {
+    /// Log level to use, --quiet and --verbose take precedence over log_level
+    pub fn effective_log_level(&self) -> LogLevel {
+        match (self.quiet, self.verbose) {
+            (true, _) => LogLevel::Error,
+            (false, 0) => self.log_level,
+            (false, 1) => LogLevel::Debug,
+            (false, _) => LogLevel::Trace,
+        }
+    }
+
+    /// Minimum number of detections for a processed image to be saved,
+    /// save_only_on_detection raises it to at least 1
+    pub fn effective_save_min_objects(&self) -> usize {
+        if self.save_only_on_detection {
+            self.save_min_objects.max(1)
+        } else {
+            self.save_min_objects
+        }
+    }
+
     /// Create a new Cli from a combination of config file and command line arguments
     /// CLI arguments always override config file values
     pub fn from_config_and_args() -> anyhow::Result<Option<Self>> {
-        // First parse CLI to get the config file path and all CLI arguments
-        let mut args = Self::parse();
+        // First parse CLI to get the config file path and all CLI arguments,
+        // the matches tell which arguments were given on the command line
+        let matches = Self::command().get_matches();
+        let mut args = Self::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
 
         if args.list_models {
-            let _guard = init_logging(args.log_level, &mut args.log_path)?;
+            let _guard = init_logging(
+                args.effective_log_level(),
+                args.log_format,
+                &mut args.log_path,
+            )?;
             crate::download_models::list_models();
             return Ok(None);
         }
         // Check if any download flags are set
         if args.download_all_models || args.download_rt_detr2 || args.download_yolo5 {
-            let _guard = init_logging(args.log_level, &mut args.log_path)?;
+            let _guard = init_logging(
+                args.effective_log_level(),
+                args.log_format,
+                &mut args.log_path,
+            )?;
             // Use specified path or default to current directory
-            let download_path = args.download_model_path.unwrap_or_else(|| {
-                if let Ok(exe) = std::env::current_exe()
-                    && let Some(parent) = exe.parent()
-                {
-                    parent.to_path_buf()
-                } else {
-                    std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."))
-                }
-            }); // Determine what to download based on flags
+            let download_path = args
+                .download_model_path
+                .or(args.models_dir)
+                .unwrap_or_else(|| {
+                    if let Ok(exe) = std::env::current_exe()
+                        && let Some(parent) = exe.parent()
+                    {
+                        parent.to_path_buf()
+                    } else {
+                        std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."))
+                    }
+                }); // Determine what to download based on flags
             let model_type = match (
                 args.download_all_models,
                 args.download_rt_detr2,
@@ -205,13 +574,20 @@ impl Cli {
             return Ok(None);
         } // Run the tokio runtime on the main thread
 
-        if let Some(config_path) = args.config.clone() {
+        // Printing the config only resolves it, config files are left untouched
+        let print_config_json = args.print_config_json;
+        if print_config_json {
+            args.no_config_write = true;
+        }
+        // Same for video detection, it runs with the resolved config
+        #[cfg(feature = "video")]
+        if args.video.is_some() {
+            args.no_config_write = true;
+        }
+
+        let config = if let Some(config_path) = args.config.clone() {
             let config_file = Self::load_config(&config_path)?;
-            Ok(Some(Self::merge_config_with_cli_args(
-                config_file,
-                args,
-                config_path,
-            )))
+            Self::merge_config_with_cli_args(config_file, args, &matches, config_path)
         } else {
             // No config file specified, check if default config exists
             let default_config_path = Self::get_default_config_path()?;
@@ -223,11 +599,10 @@ impl Cli {
                     default_config_path.display()
                 );
                 let config_file = Self::load_config(&default_config_path)?;
-                Ok(Some(Self::merge_config_with_cli_args(
-                    config_file,
-                    args,
-                    default_config_path,
-                )))
+                Self::merge_config_with_cli_args(config_file, args, &matches, default_config_path)
+            } else if args.no_config_write {
+                tracing::info!("No config file found, running from CLI arguments only");
+                args
             } else {
                 // Create new config file from CLI arguments
                 args.save_config(&default_config_path)?;
@@ -239,20 +614,40 @@ impl Cli {
                 // Now load it back with the config path set
                 let mut config = args;
                 config.config = Some(default_config_path);
-                Ok(Some(config))
+                config
             }
+        };
+
+        if print_config_json {
+            println!("{}", serde_json::to_string_pretty(&config)?);
+            return Ok(None);
         }
+
+        #[cfg(feature = "video")]
+        if let Some(video_path) = config.video.clone() {
+            let mut config = config;
+            let _guard = init_logging(
+                config.effective_log_level(),
+                config.log_format,
+                &mut config.log_path,
+            )?;
+            crate::video::run_video_detection(&video_path, config.fps_sample, &config)?;
+            return Ok(None);
+        }
+        Ok(Some(config))
     }
     /// Create a Cli from provided arguments with config file support
     /// CLI arguments always override config file values
     pub fn from_args_with_config(args: Vec<std::ffi::OsString>) -> anyhow::Result<Self> {
-        let cli_args = Self::try_parse_from(args)?;
+        let matches = Self::command().try_get_matches_from(args)?;
+        let cli_args = Self::from_arg_matches(&matches)?;
         if let Some(config_path) = cli_args.config.clone() {
             // If config file is specified, load it and merge with CLI args
             let config_file = Self::load_config(&config_path)?;
             Ok(Self::merge_config_with_cli_args(
                 config_file,
                 cli_args,
+                &matches,
                 config_path,
             ))
         } else {
@@ -269,8 +664,12 @@ impl Cli {
                 Ok(Self::merge_config_with_cli_args(
                     config_file,
                     cli_args,
+                    &matches,
                     default_config_path,
                 ))
+            } else if cli_args.no_config_write {
+                tracing::info!("No config file found, running from CLI arguments only");
+                Ok(cli_args)
             } else {
                 // Create new config file from CLI arguments
                 cli_args.save_config(&default_config_path)?;
@@ -287,6 +686,87 @@ impl Cli {
         }
     }
 
+    /// Build the detector configuration from the current arguments
+    pub fn detector_config(&self) -> DetectorConfig {
+        DetectorConfig {
+            object_detection_onnx_config: OnnxConfig {
+                force_cpu: self.force_cpu,
+                gpu_index: self.gpu_index,
+                gpu_name: self.gpu_name.clone(),
+                intra_threads: self.intra_threads,
+                inter_threads: self.inter_threads,
+                model: self.model.clone(),
+                graph_optimization_level: self.graph_optimization_level,
+                enable_cpu_mem_arena: self.enable_cpu_mem_arena,
+                models_dir: self.models_dir.clone(),
+                classes_yaml: self.mapped_classes_yaml(self.model.as_deref()),
+                ort_lib_path: self.ort_lib_path.clone(),
+                input_width: self.input_width,
+                input_height: self.input_height,
+            },
+            object_classes: self.object_classes.clone(),
+            object_filter: self.object_filter.clone(),
+            object_exclude: self.object_exclude.clone(),
+            strict_object_filter: self.strict_object_filter,
+            confidence_threshold: self.confidence_threshold,
+            save_image_path: self.save_image_path.clone(),
+            save_image_template: self.save_image_template.clone(),
+            save_ref_image: self.save_ref_image,
+            save_min_objects: self.effective_save_min_objects(),
+            output_image_format: self.output_image_format,
+            timeout: self.request_timeout,
+            object_detection_model: self.object_detection_model_type.clone(),
+            notify_cooldown: self.notify_cooldown_secs.map(Duration::from_secs),
+            timing_log: self.timing_log.clone(),
+            detection_cache_size: self.detection_cache_size,
+            warmup_iterations: self.warmup_iterations,
+            benchmark_on_start: self.benchmark_on_start,
+            resize_filter: self.resize_filter,
+            nms_mode: self.nms_mode,
+            center_crop: self.center_crop,
+            lenient_decode: self.lenient_decode,
+            motion_threshold: self.motion_threshold,
+            rotate: self.rotate,
+            worker_priority: self.worker_priority,
+            worker_pin_core: self.worker_pin_core,
+            adaptive_queue: self.adaptive_queue,
+            auto_timeout: self.auto_timeout,
+            profiles: self.profiles.clone(),
+        }
+    }
+
+    /// Class YAML mapped to the file name of the model in model_classes, None
+    /// selects the default model
+    fn mapped_classes_yaml(&self, model: Option<&Path>) -> Option<PathBuf> {
+        let model_file_name = model
+            .and_then(|model| model.file_name())
+            .and_then(|name| name.to_str())
+            .unwrap_or(NANO_RF_DETR_MODEL_FILE_NAME);
+        self.model_classes.get(model_file_name).cloned()
+    }
+
+    /// Build the detector configurations for the additional named models.
+    /// Everything except the model itself is shared with the default detector.
+    pub fn named_detector_configs(&self) -> Vec<(String, DetectorConfig)> {
+        self.models
+            .iter()
+            .map(|named_model| {
+                let mut detector_config = self.detector_config();
+                detector_config.object_detection_onnx_config.model =
+                    Some(named_model.model.clone());
+                detector_config.object_detection_onnx_config.classes_yaml =
+                    self.mapped_classes_yaml(Some(&named_model.model));
+                detector_config.object_detection_model =
+                    named_model.object_detection_model_type.clone();
+                detector_config.object_classes = named_model.object_classes.clone();
+                detector_config.object_detection_onnx_config.input_width = named_model.input_width;
+                detector_config.object_detection_onnx_config.input_height =
+                    named_model.input_height;
+                (named_model.name.clone(), detector_config)
+            })
+            .collect()
+    }
+
     /// Load configuration from a JSON file
     pub fn load_config(path: &PathBuf) -> anyhow::Result<Self> {
         let content = std::fs::read_to_string(path)
@@ -333,7 +813,7 @@ impl Cli {
     /// Auto-save current configuration if no config file was used
     pub fn auto_save_if_no_config(&self) -> anyhow::Result<()> {
         // Only auto-save if no config file was specified
-        if self.config.is_none() {
+        if self.config.is_none() && !self.no_config_write {
             let config_path = Self::get_default_config_path()?;
 
             // Don't overwrite if the file already exists (user might have customized it)
@@ -380,21 +860,68 @@ impl Cli {
         tracing::info!("=== Blue Onyx Configuration ===");
         tracing::info!("Server Configuration:");
         tracing::info!("  Port: {}", self.port);
+        tracing::info!("  Bind address: {}", self.bind_address);
         tracing::info!(
             "  Request timeout: {} seconds",
             self.request_timeout.as_secs()
         );
+        tracing::info!(
+            "  Auto timeout: {}",
+            if self.auto_timeout { "yes" } else { "no" }
+        );
 
         if let Some(queue_size) = self.worker_queue_size {
             tracing::info!("  Worker queue size: {}", queue_size);
         } else {
             tracing::info!("  Worker queue size: auto-determined");
         }
+        tracing::info!("  Workers: {}", self.workers);
+        if self.heartbeat_secs > 0 {
+            tracing::info!("  Heartbeat: every {} seconds", self.heartbeat_secs);
+        } else {
+            tracing::info!("  Heartbeat: disabled");
+        }
+        if let Some(metrics_push_url) = &self.metrics_push_url {
+            tracing::info!(
+                "  Metrics push: {} every {} seconds",
+                metrics_push_url,
+                self.metrics_push_interval
+            );
+        }
+        tracing::info!(
+            "  Response compression: {}",
+            if self.compression { "yes" } else { "no" }
+        );
+        tracing::info!("  Confidence scale: {:?}", self.confidence_scale);
+        if let Some(module_id) = &self.module_id {
+            tracing::info!("  Module id: {}", module_id);
+        }
+        if let Some(module_name) = &self.module_name {
+            tracing::info!("  Module name: {}", module_name);
+        }
+        tracing::info!(
+            "  Restart on worker failure: {}",
+            if self.restart_on_worker_failure {
+                "yes"
+            } else {
+                "no"
+            }
+        );
+        if self.max_connections > 0 {
+            tracing::info!("  Max connections: {}", self.max_connections);
+        } else {
+            tracing::info!("  Max connections: unlimited");
+        }
+        if self.keep_alive_secs > 0 {
+            tracing::info!("  Keep-alive timeout: {} seconds", self.keep_alive_secs);
+        } else {
+            tracing::info!("  Keep-alive timeout: none");
+        }
 
         tracing::info!("Model Configuration:");
         tracing::info!(
             "  Detection model type: {}",
-            self.object_detection_model_type
+            model_type_name(&self.object_detection_model_type)
         );
 
         if let Some(model_path) = &self.model {
@@ -403,29 +930,132 @@ impl Cli {
             tracing::info!("  Model: default (rf-detr-n.onnx)");
         }
 
-        if let Some(classes_path) = &self.object_classes {
-            tracing::info!("  Object classes: {}", classes_path.display());
+        if let Some(models_dir) = &self.models_dir {
+            tracing::info!("  Models directory: {}", models_dir.display());
         } else {
+            tracing::info!("  Models directory: executable directory");
+        }
+        if self.watch_model {
+            tracing::info!("  Watch model file: yes");
+        }
+        if let Some(ort_lib_path) = &self.ort_lib_path {
+            tracing::info!("  ONNX Runtime library: {}", ort_lib_path.display());
+        }
+        if let Some(input_width) = self.input_width {
+            tracing::info!("  Input width: {}", input_width);
+        }
+        if let Some(input_height) = self.input_height {
+            tracing::info!("  Input height: {}", input_height);
+        }
+
+        if self.object_classes.is_empty() {
             tracing::info!("  Object classes: default (coco_classes.yaml)");
+        } else {
+            let classes_paths: Vec<String> = self
+                .object_classes
+                .iter()
+                .map(|classes_path| classes_path.display().to_string())
+                .collect();
+            tracing::info!("  Object classes: {}", classes_paths.join(", "));
+        }
+
+        for named_model in &self.models {
+            tracing::info!(
+                "  Additional model '{}': {} ({})",
+                named_model.name,
+                named_model.model.display(),
+                model_type_name(&named_model.object_detection_model_type)
+            );
+        }
+        for (model, classes_yaml) in &self.model_classes {
+            tracing::info!("  Classes of {}: {}", model, classes_yaml.display());
         }
 
         tracing::info!("Detection Configuration:");
         tracing::info!("  Confidence threshold: {:.2}", self.confidence_threshold);
+        for (name, profile) in &self.profiles {
+            tracing::info!("  Profile '{}': {:?}", name, profile);
+        }
 
         if !self.object_filter.is_empty() {
             tracing::info!("  Object filter: [{}]", self.object_filter.join(", "));
         } else {
             tracing::info!("  Object filter: none (all objects)");
         }
+        if !self.object_exclude.is_empty() {
+            tracing::info!("  Object exclude: [{}]", self.object_exclude.join(", "));
+        }
+        if self.strict_object_filter {
+            tracing::info!("  Strict object filter: enabled");
+        }
+
+        if let Some(cooldown) = self.notify_cooldown_secs {
+            tracing::info!("  Notification cooldown: {} seconds", cooldown);
+        } else {
+            tracing::info!("  Notification cooldown: disabled");
+        }
+        if self.detection_cache_size > 0 {
+            tracing::info!("  Detection cache size: {}", self.detection_cache_size);
+        } else {
+            tracing::info!("  Detection cache: disabled");
+        }
 
         tracing::info!("Performance Configuration:");
         tracing::info!("  Force CPU: {}", if self.force_cpu { "yes" } else { "no" });
         tracing::info!("  GPU index: {}", self.gpu_index);
+        if let Some(gpu_name) = &self.gpu_name {
+            tracing::info!("  GPU name: {}", gpu_name);
+        }
         tracing::info!("  Intra threads: {}", self.intra_threads);
         tracing::info!("  Inter threads: {}", self.inter_threads);
+        if let Some(level) = self.graph_optimization_level {
+            tracing::info!("  Graph optimization level: {:?}", level);
+        } else {
+            tracing::info!("  Graph optimization level: default");
+        }
+        tracing::info!(
+            "  CPU memory arena: {}",
+            if self.enable_cpu_mem_arena {
+                "enabled"
+            } else {
+                "disabled"
+            }
+        );
+        tracing::info!("  Resize filter: {:?}", self.resize_filter);
+        tracing::info!("  NMS mode: {:?}", self.nms_mode);
+        tracing::info!(
+            "  Center crop: {}",
+            if self.center_crop { "yes" } else { "no" }
+        );
+        if self.lenient_decode {
+            tracing::warn!(
+                "  Lenient decode: enabled, malformed JPEGs are decoded best effort and may be of lower quality"
+            );
+        }
+        if let Some(motion_threshold) = self.motion_threshold {
+            tracing::info!("  Motion threshold: {motion_threshold}");
+        }
+        tracing::info!("  Rotation: {} degrees", self.rotate);
+        tracing::info!("  Warmup iterations: {}", self.warmup_iterations);
+        if self.benchmark_on_start > 0 {
+            tracing::info!(
+                "  Benchmark on start: {} detections",
+                self.benchmark_on_start
+            );
+        }
+        tracing::info!("  Worker priority: {:?}", self.worker_priority);
+        tracing::info!(
+            "  Worker pinned to core: {}",
+            if self.worker_pin_core { "yes" } else { "no" }
+        );
+        tracing::info!(
+            "  Adaptive queue: {}",
+            if self.adaptive_queue { "yes" } else { "no" }
+        );
 
         tracing::info!("Logging Configuration:");
-        tracing::info!("  Log level: {:?}", self.log_level);
+        tracing::info!("  Log level: {:?}", self.effective_log_level());
+        tracing::info!("  Log format: {:?}", self.log_format);
 
         if let Some(log_path) = &self.log_path {
             tracing::info!("  Log path: {}", log_path.display());
@@ -437,10 +1067,20 @@ impl Cli {
 
         if let Some(save_path) = &self.save_image_path {
             tracing::info!("  Save processed images: {}", save_path.display());
+            if let Some(template) = &self.save_image_template {
+                tracing::info!("  Save image template: {}", template);
+            }
             tracing::info!(
                 "  Save reference images: {}",
                 if self.save_ref_image { "yes" } else { "no" }
             );
+            if self.effective_save_min_objects() > 0 {
+                tracing::info!(
+                    "  Save only with at least {} detection(s)",
+                    self.effective_save_min_objects()
+                );
+            }
+            tracing::info!("  Output image format: {:?}", self.output_image_format);
         } else {
             tracing::info!("  Save processed images: disabled");
         }
@@ -451,6 +1091,12 @@ impl Cli {
             tracing::info!("  Save statistics: disabled");
         }
 
+        if let Some(timing_log) = &self.timing_log {
+            tracing::info!("  Timing log: {}", timing_log.display());
+        } else {
+            tracing::info!("  Timing log: disabled");
+        }
+
         if let Some(download_path) = &self.download_model_path {
             tracing::info!("  Download models to: {}", download_path.display());
         }
@@ -462,72 +1108,213 @@ impl Cli {
     fn merge_config_with_cli_args(
         mut config_file: Self,
         cli_args: Self,
+        matches: &ArgMatches,
         config_path: PathBuf,
     ) -> Self {
-        // Use clap's built-in logic to determine which values were explicitly set
-        // We'll create default CLI args and compare with the parsed CLI args
-        let defaults = Self::default();
-
-        // Override config file values with CLI arguments when they differ from defaults
-        // This approach assumes that if a CLI arg differs from its default, it was explicitly set
+        // Only override config file values with arguments that were explicitly
+        // given on the command line or in a BLUE_ONYX_* environment variable, a
+        // value equal to the default still counts. Clap already prefers the
+        // command line over the environment.
+        let from_command_line = |id: &str| {
+            matches!(
+                matches.value_source(id),
+                Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+            )
+        };
 
-        if cli_args.port != defaults.port {
+        if from_command_line("port") {
             config_file.port = cli_args.port;
         }
-        if cli_args.request_timeout != defaults.request_timeout {
+        if from_command_line("bind_address") {
+            config_file.bind_address = cli_args.bind_address;
+        }
+        if from_command_line("request_timeout") {
             config_file.request_timeout = cli_args.request_timeout;
         }
-        if cli_args.worker_queue_size != defaults.worker_queue_size {
+        if from_command_line("auto_timeout") {
+            config_file.auto_timeout = cli_args.auto_timeout;
+        }
+        if from_command_line("worker_queue_size") {
             config_file.worker_queue_size = cli_args.worker_queue_size;
         }
-        if cli_args.model != defaults.model {
+        if from_command_line("adaptive_queue") {
+            config_file.adaptive_queue = cli_args.adaptive_queue;
+        }
+        if from_command_line("workers") {
+            config_file.workers = cli_args.workers;
+        }
+        if from_command_line("heartbeat_secs") {
+            config_file.heartbeat_secs = cli_args.heartbeat_secs;
+        }
+        if from_command_line("metrics_push_url") {
+            config_file.metrics_push_url = cli_args.metrics_push_url;
+        }
+        if from_command_line("metrics_push_interval") {
+            config_file.metrics_push_interval = cli_args.metrics_push_interval;
+        }
+        if from_command_line("compression") {
+            config_file.compression = cli_args.compression;
+        }
+        if from_command_line("confidence_scale") {
+            config_file.confidence_scale = cli_args.confidence_scale;
+        }
+        if from_command_line("module_id") {
+            config_file.module_id = cli_args.module_id;
+        }
+        if from_command_line("module_name") {
+            config_file.module_name = cli_args.module_name;
+        }
+        if from_command_line("restart_on_worker_failure") {
+            config_file.restart_on_worker_failure = cli_args.restart_on_worker_failure;
+        }
+        if from_command_line("max_connections") {
+            config_file.max_connections = cli_args.max_connections;
+        }
+        if from_command_line("keep_alive_secs") {
+            config_file.keep_alive_secs = cli_args.keep_alive_secs;
+        }
+        if from_command_line("model") {
             config_file.model = cli_args.model;
         }
-        if cli_args.object_detection_model_type != defaults.object_detection_model_type {
+        if from_command_line("models_dir") {
+            config_file.models_dir = cli_args.models_dir;
+        }
+        if from_command_line("watch_model") {
+            config_file.watch_model = cli_args.watch_model;
+        }
+        if from_command_line("ort_lib_path") {
+            config_file.ort_lib_path = cli_args.ort_lib_path;
+        }
+        if from_command_line("input_width") {
+            config_file.input_width = cli_args.input_width;
+        }
+        if from_command_line("input_height") {
+            config_file.input_height = cli_args.input_height;
+        }
+        if from_command_line("object_detection_model_type") {
             config_file.object_detection_model_type = cli_args.object_detection_model_type;
         }
-        if cli_args.object_classes != defaults.object_classes {
+        if from_command_line("object_classes") {
             config_file.object_classes = cli_args.object_classes;
         }
-        if cli_args.object_filter != defaults.object_filter {
+        if from_command_line("object_filter") {
             config_file.object_filter = cli_args.object_filter;
         }
-        if cli_args.log_level != defaults.log_level {
+        if from_command_line("object_exclude") {
+            config_file.object_exclude = cli_args.object_exclude;
+        }
+        if from_command_line("strict_object_filter") {
+            config_file.strict_object_filter = cli_args.strict_object_filter;
+        }
+        if from_command_line("log_level") {
             config_file.log_level = cli_args.log_level;
         }
-        if cli_args.log_path != defaults.log_path {
+        if from_command_line("log_format") {
+            config_file.log_format = cli_args.log_format;
+        }
+        if from_command_line("log_path") {
             config_file.log_path = cli_args.log_path;
         }
-        if cli_args.confidence_threshold != defaults.confidence_threshold {
+        if from_command_line("confidence_threshold") {
             config_file.confidence_threshold = cli_args.confidence_threshold;
         }
-        if cli_args.save_image_path != defaults.save_image_path {
+        if from_command_line("save_image_path") {
             config_file.save_image_path = cli_args.save_image_path;
         }
-        if cli_args.save_ref_image != defaults.save_ref_image {
+        if from_command_line("save_image_template") {
+            config_file.save_image_template = cli_args.save_image_template;
+        }
+        if from_command_line("save_ref_image") {
             config_file.save_ref_image = cli_args.save_ref_image;
         }
-        if cli_args.save_stats_path != defaults.save_stats_path {
+        if from_command_line("save_only_on_detection") {
+            config_file.save_only_on_detection = cli_args.save_only_on_detection;
+        }
+        if from_command_line("save_min_objects") {
+            config_file.save_min_objects = cli_args.save_min_objects;
+        }
+        if from_command_line("output_image_format") {
+            config_file.output_image_format = cli_args.output_image_format;
+        }
+        if from_command_line("save_stats_path") {
             config_file.save_stats_path = cli_args.save_stats_path;
         }
-        if cli_args.force_cpu != defaults.force_cpu {
+        if from_command_line("notify_cooldown_secs") {
+            config_file.notify_cooldown_secs = cli_args.notify_cooldown_secs;
+        }
+        if from_command_line("timing_log") {
+            config_file.timing_log = cli_args.timing_log;
+        }
+        if from_command_line("detection_cache_size") {
+            config_file.detection_cache_size = cli_args.detection_cache_size;
+        }
+        if from_command_line("resize_filter") {
+            config_file.resize_filter = cli_args.resize_filter;
+        }
+        if from_command_line("nms_mode") {
+            config_file.nms_mode = cli_args.nms_mode;
+        }
+        if from_command_line("center_crop") {
+            config_file.center_crop = cli_args.center_crop;
+        }
+        if from_command_line("lenient_decode") {
+            config_file.lenient_decode = cli_args.lenient_decode;
+        }
+        if from_command_line("motion_threshold") {
+            config_file.motion_threshold = cli_args.motion_threshold;
+        }
+        if from_command_line("rotate") {
+            config_file.rotate = cli_args.rotate;
+        }
+        if from_command_line("warmup_iterations") {
+            config_file.warmup_iterations = cli_args.warmup_iterations;
+        }
+        if from_command_line("benchmark_on_start") {
+            config_file.benchmark_on_start = cli_args.benchmark_on_start;
+        }
+        if from_command_line("worker_priority") {
+            config_file.worker_priority = cli_args.worker_priority;
+        }
+        if from_command_line("worker_pin_core") {
+            config_file.worker_pin_core = cli_args.worker_pin_core;
+        }
+        if from_command_line("force_cpu") {
             config_file.force_cpu = cli_args.force_cpu;
         }
-        if cli_args.gpu_index != defaults.gpu_index {
+        if from_command_line("gpu_index") {
             config_file.gpu_index = cli_args.gpu_index;
         }
-        if cli_args.intra_threads != defaults.intra_threads {
+        if from_command_line("gpu_name") {
+            config_file.gpu_name = cli_args.gpu_name;
+        }
+        if from_command_line("intra_threads") {
             config_file.intra_threads = cli_args.intra_threads;
         }
-        if cli_args.inter_threads != defaults.inter_threads {
+        if from_command_line("inter_threads") {
             config_file.inter_threads = cli_args.inter_threads;
         }
+        if from_command_line("graph_optimization_level") {
+            config_file.graph_optimization_level = cli_args.graph_optimization_level;
+        }
+        if from_command_line("enable_cpu_mem_arena") {
+            config_file.enable_cpu_mem_arena = cli_args.enable_cpu_mem_arena;
+        }
 
         // Set the config path
         config_file.config = Some(config_path.clone());
+        config_file.no_config_write = cli_args.no_config_write;
+        config_file.quiet = cli_args.quiet;
+        config_file.verbose = cli_args.verbose;
+        #[cfg(feature = "video")]
+        {
+            config_file.video = cli_args.video;
+            config_file.fps_sample = cli_args.fps_sample;
+        }
 
         // Save the merged configuration back to the config file
-        if let Err(e) = config_file.save_config(&config_path) {
+        if config_file.no_config_write {
+            tracing::info!("Config file writes are disabled, not saving the merged configuration");
+        } else if let Err(e) = config_file.save_config(&config_path) {
             tracing::warn!("Failed to save merged configuration: {}", e);
         } else {
             tracing::info!("Saved merged configuration to: {}", config_path.display());
@@ -558,6 +1345,37 @@ mod duration_serde {
     }
 }
 
+// Object classes used to be a single path, older config files have a string or null
+mod object_classes_serde {
+    use serde::{Deserialize, Deserializer};
+    use std::path::PathBuf;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(PathBuf),
+        Many(Vec<PathBuf>),
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<PathBuf>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(match Option::<OneOrMany>::deserialize(deserializer)? {
+            None => vec![],
+            Some(OneOrMany::One(path)) => vec![path],
+            Some(OneOrMany::Many(paths)) => paths,
+        })
+    }
+}
+
+/// Display name of an optional model type, None is inferred from the model
+fn model_type_name(model_type: &Option<ObjectDetectionModel>) -> String {
+    model_type
+        .as_ref()
+        .map_or_else(|| "auto".to_string(), |model_type| model_type.to_string())
+}
+
 fn parse_duration(s: &str) -> anyhow::Result<Duration> {
     let secs: u64 = s.parse()?;
     Ok(Duration::from_secs(secs))
@@ -606,4 +1424,134 @@ mod tests {
             "inter_threads should not exceed 16"
         );
     }
+
+    #[test]
+    fn test_merge_does_not_write_config_with_no_config_write() {
+        let config_path = std::env::temp_dir().join(format!(
+            "blue_onyx_no_config_write_{}.json",
+            std::process::id()
+        ));
+        let matches = Cli::command()
+            .try_get_matches_from(["blue_onyx", "--no-config-write", "--port", "1337"])
+            .unwrap();
+        let cli_args = Cli::from_arg_matches(&matches).unwrap();
+
+        let merged = Cli::merge_config_with_cli_args(
+            Cli::default(),
+            cli_args,
+            &matches,
+            config_path.clone(),
+        );
+
+        assert!(merged.no_config_write);
+        assert_eq!(merged.port, 1337);
+        assert!(!config_path.exists());
+    }
+
+    #[test]
+    fn test_merge_overrides_config_with_explicit_default_value() {
+        let config_file = Cli {
+            confidence_threshold: 0.7,
+            port: 1337,
+            ..Default::default()
+        };
+        let matches = Cli::command()
+            .try_get_matches_from([
+                "blue_onyx",
+                "--no-config-write",
+                "--confidence-threshold",
+                "0.5",
+            ])
+            .unwrap();
+        let cli_args = Cli::from_arg_matches(&matches).unwrap();
+
+        let merged = Cli::merge_config_with_cli_args(
+            config_file,
+            cli_args,
+            &matches,
+            PathBuf::from("unused.json"),
+        );
+
+        assert_eq!(merged.confidence_threshold, 0.5);
+        // Arguments not given on the command line keep the config file value
+        assert_eq!(merged.port, 1337);
+    }
+    #[test]
+    fn test_save_only_on_detection_requires_one_object() {
+        let save_min_objects = |save_only_on_detection, save_min_objects| {
+            Cli {
+                save_only_on_detection,
+                save_min_objects,
+                ..Default::default()
+            }
+            .effective_save_min_objects()
+        };
+
+        assert_eq!(save_min_objects(false, 0), 0);
+        assert_eq!(save_min_objects(true, 0), 1);
+        assert_eq!(save_min_objects(true, 3), 3);
+        assert_eq!(save_min_objects(false, 2), 2);
+    }
+
+    #[test]
+    fn test_model_classes_maps_yaml_by_model_file_name() {
+        let cli: Cli = serde_json::from_str(
+            r#"{
+                "model": "models/delivery.onnx",
+                "models": [{"name": "nano", "model": "rf-detr-n.onnx"}, {"name": "bird", "model": "ipcam-bird.onnx"}],
+                "model_classes": {"delivery.onnx": "custom/delivery.yaml", "rf-detr-n.onnx": "coco.yaml"}
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            cli.detector_config()
+                .object_detection_onnx_config
+                .classes_yaml,
+            Some(PathBuf::from("custom/delivery.yaml"))
+        );
+        let named_classes_yaml: Vec<_> = cli
+            .named_detector_configs()
+            .into_iter()
+            .map(|(_, config)| config.object_detection_onnx_config.classes_yaml)
+            .collect();
+        assert_eq!(
+            named_classes_yaml,
+            vec![Some(PathBuf::from("coco.yaml")), None]
+        );
+        assert_eq!(
+            Cli::default().mapped_classes_yaml(None),
+            None,
+            "no mapping uses the YAML named like the model"
+        );
+    }
+
+    #[test]
+    fn test_quiet_and_verbose_override_log_level() {
+        let log_level = |args: &[&str]| {
+            Cli::try_parse_from(std::iter::once("blue_onyx").chain(args.iter().copied()))
+                .unwrap()
+                .effective_log_level()
+        };
+
+        assert_eq!(log_level(&["--log-level", "warn"]), LogLevel::Warn);
+        assert_eq!(log_level(&["--log-level", "warn", "-q"]), LogLevel::Error);
+        assert_eq!(log_level(&["--log-level", "warn", "-v"]), LogLevel::Debug);
+        assert_eq!(log_level(&["-vv"]), LogLevel::Trace);
+        assert!(Cli::try_parse_from(["blue_onyx", "-q", "-v"]).is_err());
+    }
+    #[test]
+    fn test_object_classes_accepts_single_path_or_list() {
+        let object_classes = |json: &str| serde_json::from_str::<Cli>(json).unwrap().object_classes;
+
+        assert!(object_classes(r#"{"object_classes": null}"#).is_empty());
+        assert_eq!(
+            object_classes(r#"{"object_classes": "a.yaml"}"#),
+            vec![PathBuf::from("a.yaml")]
+        );
+        assert_eq!(
+            object_classes(r#"{"object_classes": ["a.yaml", "b.txt"]}"#),
+            vec![PathBuf::from("a.yaml"), PathBuf::from("b.txt")]
+        );
+    }
 }
@@ -1,27 +1,32 @@
 #[cfg(windows)]
 use crate::direct_ml_available;
 use crate::{
-    api::Prediction,
-    get_object_classes,
+    api::{Prediction, Roi},
+    get_merged_object_classes, get_object_classes,
     image::{
-        Image, Resizer, create_od_image_name, decode_jpeg,
-        encode_maybe_draw_boundary_boxes_and_save_jpeg,
+        Image, OutputImageFormat, ResizeFilter, Resizer, Rotation, create_od_image_name,
+        create_saved_image_name, decode_image, encode_maybe_draw_boundary_boxes_and_save_image,
+        load_image, mean_absolute_difference, render_save_image_template,
     },
+    worker::WorkerPriority,
 };
-use anyhow::{anyhow, bail};
+use anyhow::{Context, anyhow, bail};
 use bytes::Bytes;
-use ndarray::{Array, ArrayView, Axis, s};
+use ndarray::{Array, ArrayView, ArrayViewD, Axis, s};
 #[cfg(windows)]
 use ort::execution_providers::DirectMLExecutionProvider;
 use ort::{
+    execution_providers::CPUExecutionProvider,
     inputs,
     session::{Session, SessionInputs, SessionOutputs},
     value::Value,
 };
 use smallvec::SmallVec;
 use std::{
+    borrow::Cow,
+    collections::HashMap,
     fmt::Debug,
-    path::PathBuf,
+    path::{Path, PathBuf},
     time::{Duration, Instant},
 };
 use tracing::{debug, info, warn};
@@ -36,6 +41,9 @@ pub struct DetectResult {
     pub post_processing_time: std::time::Duration,
     pub device_type: DeviceType,
     pub endpoint_provider: EndpointProvider,
+    /// Size of the image as it was sent, before any rotation
+    pub image_width: usize,
+    pub image_height: usize,
 }
 
 impl Debug for DetectResult {
@@ -49,27 +57,192 @@ impl Debug for DetectResult {
             .field("inference_time", &self.inference_time)
             .field("post_processing_time", &self.post_processing_time)
             .field("device_type", &self.device_type)
+            .field("image_width", &self.image_width)
+            .field("image_height", &self.image_height)
             .finish()
     }
 }
 
+/// Where and when processed images are saved, from the save_image_path options
+struct SaveImages {
+    path: PathBuf,
+    /// Optional path template relative to path for processed images
+    template: Option<String>,
+    ref_image: bool,
+    /// Minimum number of detections for the images of a frame to be saved
+    min_objects: usize,
+    output_image_format: OutputImageFormat,
+}
+
+/// Image of a detection that is saved once its predictions are known
+struct ImageToSave<'a> {
+    image_name: &'a str,
+    /// Timestamped name shared by the reference and processed images
+    saved_image_name: String,
+    /// The image as it was sent, only kept when the reference image is saved
+    ref_image: Option<Bytes>,
+}
+
+impl SaveImages {
+    /// Names the image before it is decoded and keeps its bytes for the
+    /// reference image
+    fn image_to_save<'a>(
+        &self,
+        image_name: &'a str,
+        image_bytes: &Bytes,
+    ) -> anyhow::Result<ImageToSave<'a>> {
+        Ok(ImageToSave {
+            image_name,
+            saved_image_name: create_saved_image_name(image_name, chrono::Utc::now())?,
+            ref_image: self.ref_image.then(|| image_bytes.clone()),
+        })
+    }
+
+    /// Saves the reference image and the image with the predictions drawn on
+    /// it, nothing is saved for frames with fewer than min_objects predictions
+    fn save(
+        &self,
+        image_to_save: &ImageToSave<'_>,
+        image: &Image,
+        predictions: &[Prediction],
+        camera: Option<&str>,
+        (base_width, base_height): (u32, u32),
+    ) -> anyhow::Result<()> {
+        if predictions.len() < self.min_objects {
+            debug!(
+                "{} prediction(s) is below save_min_objects {}, skipping image save",
+                predictions.len(),
+                self.min_objects
+            );
+            return Ok(());
+        }
+        info!(
+            "Saving detection result with {} predictions to disk",
+            predictions.len()
+        );
+        let save_image_start_time = Instant::now();
+        if let Some(ref ref_image) = image_to_save.ref_image {
+            std::fs::write(self.path.join(&image_to_save.saved_image_name), ref_image)?;
+        }
+        let output_path = if let Some(ref template) = self.template {
+            let relative_path = render_save_image_template(
+                template,
+                image_to_save.image_name,
+                camera,
+                predictions.first().map(|p| p.label.as_str()),
+                chrono::Utc::now(),
+            )?;
+            let mut output_path = self.path.join(relative_path);
+            if !self
+                .output_image_format
+                .matches_extension(&output_path.to_string_lossy())
+            {
+                output_path.set_extension(self.output_image_format.extension());
+            }
+            if let Some(parent) = output_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            output_path
+        } else {
+            self.path.join(create_od_image_name(
+                &image_to_save.saved_image_name,
+                false,
+                self.output_image_format,
+            )?)
+        };
+        let output_path = output_path.to_string_lossy().to_string();
+        info!("Output path: {}", output_path);
+
+        encode_maybe_draw_boundary_boxes_and_save_image(
+            image,
+            &output_path,
+            Some(predictions),
+            base_width,
+            base_height,
+            self.output_image_format,
+        )?;
+        debug!("Save image time: {:?}", save_image_start_time.elapsed());
+        Ok(())
+    }
+}
+
+/// Profile, min_confidence bits, ROIs and top_k_classes of a request, a frame
+/// is only reused by requests with the same settings
+type MotionSettings = (Option<String>, Option<u32>, Vec<Roi>, usize);
+
+/// Most cameras the motion gate keeps a frame for, the camera inferred least
+/// recently is dropped for a new one since clients pick the camera names
+const MAX_MOTION_FRAMES: usize = 64;
+
+/// A frame that ran inference, later frames that barely differ get its result
+struct MotionFrame {
+    settings: MotionSettings,
+    thumbnail: Vec<u8>,
+    width: usize,
+    height: usize,
+    predictions: SmallVec<[Prediction; 10]>,
+    inferred_at: Instant,
+}
+
+/// Stores the last inferred frame of a camera, dropping the camera inferred
+/// least recently when over MAX_MOTION_FRAMES. Returns the replaced or
+/// dropped frame so its thumbnail buffer can be reused.
+fn insert_motion_frame(
+    motion_frames: &mut HashMap<Option<String>, MotionFrame>,
+    camera: Option<String>,
+    frame: MotionFrame,
+) -> Option<MotionFrame> {
+    let evicted =
+        if !motion_frames.contains_key(&camera) && motion_frames.len() >= MAX_MOTION_FRAMES {
+            motion_frames
+                .iter()
+                .min_by_key(|(_, frame)| frame.inferred_at)
+                .map(|(camera, _)| camera.clone())
+                .and_then(|camera| motion_frames.remove(&camera))
+        } else {
+            None
+        };
+    motion_frames.insert(camera, frame).or(evicted)
+}
+
 pub struct Detector {
     session: Session,
     resizer: Resizer,
     decoded_image: Image,
     resized_image: Image,
+    rotation: Rotation,
+    nms_mode: NmsMode,
+    lenient_decode: bool,
+    /// Mean absolute difference below which a frame reuses the last result
+    motion_threshold: Option<f32>,
+    /// Last inferred frame per camera
+    motion_frames: HashMap<Option<String>, MotionFrame>,
+    /// Thumbnail of the current frame, reused between detections
+    motion_thumbnail: Vec<u8>,
+    /// Pixels of the previous image, reused when rotating
+    rotate_buffer: Vec<u8>,
     object_classes: Vec<String>,
     object_filter: Option<Vec<bool>>,
+    /// Detection profiles requests can select by name
+    profiles: HashMap<String, ResolvedProfile>,
     input: ndarray::ArrayBase<ndarray::OwnedRepr<f32>, ndarray::Dim<[usize; 4]>>,
     confidence_threshold: f32,
     device_type: DeviceType,
     endpoint_provider: EndpointProvider,
-    save_image_path: Option<PathBuf>,
-    save_ref_image: bool,
+    /// None if save_image_path is not set
+    save_images: Option<SaveImages>,
     model_name: String,
     object_detection_model: ObjectDetectionModel,
     input_width: usize,
     input_height: usize,
+    input_channels: usize,
+    input_layout: InputLayout,
+    gpu_index: i32,
+    warmup_min_processing_time: Option<Duration>,
+    /// Time to load the model and build the session
+    model_load_time: Duration,
+    /// Time spent on the warmup detections
+    warmup_time: Duration,
 }
 
 #[derive(Debug, Clone)]
@@ -77,8 +250,72 @@ pub struct OnnxConfig {
     pub intra_threads: usize,
     pub inter_threads: usize,
     pub gpu_index: i32,
+    /// Select the GPU by a substring of its name instead of gpu_index
+    pub gpu_name: Option<String>,
     pub force_cpu: bool,
     pub model: Option<PathBuf>,
+    /// Graph optimization level, None uses the ONNX Runtime default
+    pub graph_optimization_level: Option<GraphOptimizationLevel>,
+    /// Use the arena allocator for CPU memory, disabling it lowers steady-state memory
+    pub enable_cpu_mem_arena: bool,
+    /// Directory models are loaded from and downloaded to, None uses the executable directory
+    pub models_dir: Option<PathBuf>,
+    /// ONNX Runtime library to load, None loads the one next to the executable
+    pub ort_lib_path: Option<PathBuf>,
+    /// Class YAML of the model, relative paths are in models_dir. None uses
+    /// the YAML with the same name as the model.
+    pub classes_yaml: Option<PathBuf>,
+    /// Model input size overrides, None uses the size read from the model
+    pub input_width: Option<usize>,
+    pub input_height: Option<usize>,
+}
+
+impl OnnxConfig {
+    /// File name of the configured model, None uses the default model
+    pub fn model_file_name(&self) -> Option<String> {
+        self.model
+            .as_ref()
+            .and_then(|p| p.file_name())
+            .and_then(|n| n.to_str())
+            .map(|s| s.to_string())
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Serialize, serde::Deserialize,
+)]
+pub enum GraphOptimizationLevel {
+    Disable,
+    Level1,
+    Level2,
+    Level3,
+}
+
+impl From<GraphOptimizationLevel> for ort::session::builder::GraphOptimizationLevel {
+    fn from(value: GraphOptimizationLevel) -> Self {
+        match value {
+            GraphOptimizationLevel::Disable => Self::Disable,
+            GraphOptimizationLevel::Level1 => Self::Level1,
+            GraphOptimizationLevel::Level2 => Self::Level2,
+            GraphOptimizationLevel::Level3 => Self::Level3,
+        }
+    }
+}
+
+/// Per request settings of a detection, the defaults use the detector
+/// configuration and keep every prediction
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DetectOptions<'a> {
+    /// Camera the image is from, used in saved image names
+    pub camera: Option<&'a str>,
+    /// Takes precedence over the profile and detector confidence threshold
+    pub min_confidence: Option<f32>,
+    /// Name of the detection profile to apply
+    pub profile: Option<&'a str>,
+    /// Only keep predictions centered in one of the regions, empty keeps all
+    pub rois: &'a [Roi],
+    /// Number of best scoring classes to attach to each prediction
+    pub top_k_classes: usize,
 }
 
 #[derive(Debug, Clone)]
@@ -90,6 +327,63 @@ pub struct PostProcessParams<'a> {
     pub object_classes: &'a [String],
     pub input_width: u32,
     pub input_height: u32,
+    pub nms_mode: NmsMode,
+    /// Number of best scoring classes to attach to each prediction
+    pub top_k_classes: usize,
+}
+
+/// How overlapping boxes of the same label are suppressed for the YOLO models,
+/// the DETR models return one box per object and need no suppression.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    clap::ValueEnum,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+pub enum NmsMode {
+    /// Drop boxes overlapping a more confident box
+    #[default]
+    Hard,
+    /// Soft-NMS, scale the confidence of boxes overlapping a more confident box
+    /// by 1 - IoU
+    Linear,
+    /// Soft-NMS, decay the confidence of overlapping boxes by a Gaussian of the
+    /// IoU
+    Gaussian,
+}
+
+impl NmsMode {
+    /// Suppress overlapping predictions, soft-NMS drops boxes whose decayed
+    /// confidence falls to or below the confidence threshold
+    fn apply(
+        self,
+        predictions: SmallVec<[Prediction; 10]>,
+        confidence_threshold: f32,
+    ) -> anyhow::Result<SmallVec<[Prediction; 10]>> {
+        match self {
+            NmsMode::Hard => non_maximum_suppression(predictions, NMS_IOU_THRESHOLD),
+            NmsMode::Linear | NmsMode::Gaussian => Ok(soft_non_maximum_suppression(
+                predictions,
+                self,
+                confidence_threshold,
+            )),
+        }
+    }
+
+    /// Factor the confidence of a box overlapping a kept box by iou is scaled by
+    fn decay(self, iou: f32) -> f32 {
+        match self {
+            NmsMode::Hard if iou >= NMS_IOU_THRESHOLD => 0.0,
+            NmsMode::Linear if iou >= NMS_IOU_THRESHOLD => 1.0 - iou,
+            NmsMode::Hard | NmsMode::Linear => 1.0,
+            NmsMode::Gaussian => (-(iou * iou) / SOFT_NMS_GAUSSIAN_SIGMA).exp(),
+        }
+    }
 }
 
 #[derive(
@@ -100,6 +394,8 @@ pub enum ObjectDetectionModel {
     #[default]
     RfDetr,
     Yolo5,
+    /// YOLOv8 and newer Ultralytics exports, e.g. YOLO11
+    Yolo8,
 }
 
 impl std::fmt::Display for ObjectDetectionModel {
@@ -108,6 +404,7 @@ impl std::fmt::Display for ObjectDetectionModel {
             ObjectDetectionModel::RtDetrv2 => write!(f, "rt-detrv2"),
             ObjectDetectionModel::RfDetr => write!(f, "rf-detr"),
             ObjectDetectionModel::Yolo5 => write!(f, "yolo5"),
+            ObjectDetectionModel::Yolo8 => write!(f, "yolo8"),
         }
     }
 }
@@ -121,7 +418,7 @@ impl ObjectDetectionModel {
         match self {
             Self::RtDetrv2 => rt_detrv2_pre_process(input, orig_size),
             Self::RfDetr => rf_detr_pre_process(input, orig_size),
-            Self::Yolo5 => yolo5_pre_process(input),
+            Self::Yolo5 | Self::Yolo8 => yolo5_pre_process(input),
         }
     }
     pub fn post_process(
@@ -137,18 +434,14 @@ impl ObjectDetectionModel {
                 params.resize_factor_y,
                 params.object_filter,
                 params.object_classes,
+                params.top_k_classes,
             ),
 
             Self::RfDetr => rf_detr_post_process(outputs, params),
 
-            Self::Yolo5 => yolo5_post_process(
-                outputs,
-                params.confidence_threshold,
-                params.resize_factor_x,
-                params.resize_factor_y,
-                params.object_filter,
-                params.object_classes,
-            ),
+            Self::Yolo5 => yolo5_post_process(outputs, params),
+
+            Self::Yolo8 => yolo8_post_process(outputs, params),
         }
     }
 }
@@ -190,6 +483,7 @@ fn rt_detrv2_post_process(
     resize_factor_y: f32,
     object_filter: &Option<Vec<bool>>,
     object_classes: &[String],
+    top_k_classes: usize,
 ) -> anyhow::Result<SmallVec<[Prediction; 10]>> {
     let (labels_shape, labels_data) = outputs["labels"].try_extract_tensor::<i64>()?;
     let (bboxes_shape, bboxes_data) = outputs["boxes"].try_extract_tensor::<f32>()?;
@@ -212,21 +506,42 @@ fn rt_detrv2_post_process(
 
     for (i, bbox) in bboxes.outer_iter().enumerate() {
         if scores[i] > confidence_threshold {
+            // Skip label ids outside the class list instead of panicking the worker
+            let label = labels[i];
+            let Some(class_idx) = usize::try_from(label)
+                .ok()
+                .filter(|class_idx| *class_idx < object_classes.len())
+            else {
+                warn!(
+                    label,
+                    object_class_count = object_classes.len(),
+                    "RT-DETR label id is out of range for the object classes, skipping prediction"
+                );
+                continue;
+            };
+
             // If object filter is set, skip objects that are not in the filter
             if let Some(object_filter) = object_filter.as_ref()
-                && !object_filter[labels[i] as usize]
+                && !object_filter[class_idx]
             {
                 continue;
             }
 
-            let prediction = Prediction {
-                x_min: (bbox[0] * resize_factor_x) as usize,
-                x_max: (bbox[2] * resize_factor_x) as usize,
-                y_min: (bbox[1] * resize_factor_y) as usize,
-                y_max: (bbox[3] * resize_factor_y) as usize,
-                confidence: scores[i],
-                label: object_classes[labels[i] as usize].clone(),
-            };
+            let mut prediction = Prediction::from_float_box(
+                (
+                    bbox[0] * resize_factor_x,
+                    bbox[1] * resize_factor_y,
+                    bbox[2] * resize_factor_x,
+                    bbox[3] * resize_factor_y,
+                ),
+                f32::trunc,
+                scores[i],
+                object_classes[class_idx].clone(),
+            );
+            // RT-DETR only outputs the score of the best class
+            if top_k_classes > 0 {
+                prediction.top_classes = Some(vec![(prediction.label.clone(), scores[i])]);
+            }
 
             debug!("Prediction - {}: {:?}", predictions.len() + 1, prediction);
 
@@ -308,18 +623,20 @@ fn rf_detr_post_process(
         let y_min = (center_y - height / 2.0).max(0.0);
         let y_max = center_y + height / 2.0;
 
-        let prediction = Prediction {
-            x_min: x_min.round() as usize,
-            x_max: x_max.round() as usize,
-            y_min: y_min.round() as usize,
-            y_max: y_max.round() as usize,
-            confidence: *score,
-            label: if *class_idx < params.object_classes.len() {
-                params.object_classes[*class_idx].clone()
-            } else {
-                format!("class_{class_idx}")
-            },
-        };
+        let mut prediction = Prediction::from_float_box(
+            (x_min, y_min, x_max, y_max),
+            f32::round,
+            *score,
+            class_label(params.object_classes, *class_idx),
+        );
+        prediction.top_classes = top_classes(
+            labels
+                .index_axis(Axis(0), *query_idx)
+                .iter()
+                .map(|logit| 1.0 / (1.0 + (-logit).exp())),
+            params.top_k_classes,
+            params.object_classes,
+        );
 
         debug!(
             "RF-DETR Detection - {}: {:?}",
@@ -363,12 +680,9 @@ fn rf_detr_post_process(
 
 fn yolo5_post_process(
     outputs: SessionOutputs<'_>,
-    confidence_threshold: f32,
-    resize_factor_x: f32,
-    resize_factor_y: f32,
-    object_filter: &Option<Vec<bool>>,
-    object_classes: &[String],
+    params: &PostProcessParams,
 ) -> anyhow::Result<SmallVec<[Prediction; 10]>> {
+    let object_classes = params.object_classes;
     let output = outputs.values().next().ok_or(anyhow!("No outputs"))?;
     let (shape, data) = output.try_extract_tensor::<f32>()?;
     let shape_dims: Vec<usize> = shape.iter().map(|&dim| dim as usize).collect();
@@ -404,8 +718,6 @@ fn yolo5_post_process(
             object_classes.len()
         );
     }
-    let mut predictions = SmallVec::<[Prediction; 10]>::new();
-
     // Handle different tensor shapes
     let detections_view = if yolo_output.shape().len() == 3 {
         // Shape is [batch_size, num_detections, features] - get the first batch
@@ -415,40 +727,123 @@ fn yolo5_post_process(
         yolo_output.view()
     };
 
-    for iter in detections_view.outer_iter() {
-        if iter[4] > confidence_threshold {
-            let class_idx = iter
-                .slice(s![5..])
-                .iter()
-                .enumerate()
-                .max_by(|a, b| a.1.partial_cmp(b.1).unwrap_or(std::cmp::Ordering::Equal))
-                .map(|(idx, _)| idx)
-                .unwrap_or(0);
+    let predictions = yolo5_predictions(
+        detections_view,
+        params.confidence_threshold,
+        params.resize_factor_x,
+        params.resize_factor_y,
+        params.object_filter,
+        object_classes,
+        params.top_k_classes,
+    );
 
-            if let Some(object_filter) = object_filter
-                && !object_filter[class_idx]
-            {
-                continue;
-            }
+    // Apply non-maximum suppression (aka remove overlapping boxes)
+    let predictions = params
+        .nms_mode
+        .apply(predictions, params.confidence_threshold)?;
 
-            let x_center = iter[0] * resize_factor_x;
-            let y_center = iter[1] * resize_factor_y;
-            let width = iter[2] * resize_factor_x;
-            let height = iter[3] * resize_factor_y;
-            let prediction = Prediction {
-                x_min: (x_center - width / 2.0) as usize,
-                y_min: (y_center - height / 2.0) as usize,
-                x_max: (x_center + width / 2.0) as usize,
-                y_max: (y_center + height / 2.0) as usize,
-                confidence: iter[4],
-                label: object_classes[class_idx].clone(),
-            };
-            predictions.push(prediction);
+    for (i, prediction) in predictions.iter().enumerate() {
+        debug!("Prediction - {}: {:?}", i + 1, prediction);
+    }
+
+    Ok(predictions)
+}
+
+/// Turns YOLO5 detection rows of [x_center, y_center, width, height,
+/// objectness, class scores...] into predictions. The confidence is the
+/// objectness times the best class score like standard YOLO, the objectness
+/// alone is checked first since it is an upper bound of the confidence.
+fn yolo5_predictions(
+    detections: ArrayViewD<'_, f32>,
+    confidence_threshold: f32,
+    resize_factor_x: f32,
+    resize_factor_y: f32,
+    object_filter: &Option<Vec<bool>>,
+    object_classes: &[String],
+    top_k_classes: usize,
+) -> SmallVec<[Prediction; 10]> {
+    let mut predictions = SmallVec::<[Prediction; 10]>::new();
+
+    for iter in detections.outer_iter() {
+        let objectness = iter[4];
+        if objectness <= confidence_threshold {
+            continue;
+        }
+        let (class_idx, class_score) = iter
+            .slice(s![5..])
+            .iter()
+            .copied()
+            .enumerate()
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+            .unwrap_or((0, 0.0));
+        let confidence = objectness * class_score;
+        if confidence <= confidence_threshold {
+            continue;
+        }
+
+        if let Some(object_filter) = object_filter
+            && !object_filter[class_idx]
+        {
+            continue;
         }
+
+        let mut prediction = yolo_prediction(
+            &iter,
+            resize_factor_x,
+            resize_factor_y,
+            confidence,
+            object_classes[class_idx].clone(),
+        );
+        prediction.top_classes = top_classes(
+            iter.slice(s![5..]).iter().map(|score| objectness * score),
+            top_k_classes,
+            object_classes,
+        );
+        predictions.push(prediction);
     }
+    predictions
+}
+
+fn yolo8_post_process(
+    outputs: SessionOutputs<'_>,
+    params: &PostProcessParams,
+) -> anyhow::Result<SmallVec<[Prediction; 10]>> {
+    let object_classes = params.object_classes;
+    let output = outputs.values().next().ok_or(anyhow!("No outputs"))?;
+    let (shape, data) = output.try_extract_tensor::<f32>()?;
+    let shape_dims: Vec<usize> = shape.iter().map(|&dim| dim as usize).collect();
+    let yolo_output = ArrayView::from_shape(shape_dims.as_slice(), data)
+        .map_err(|e| anyhow!("Failed to create output array view: {}", e))?;
+    debug!("YOLOv8 output tensor shape: {:?}", yolo_output.shape());
+
+    // The YOLOv8 output is [batch_size, num_classes + 4, num_detections], the
+    // transpose of YOLO5 without the objectness feature
+    let expected_features = 4 + object_classes.len();
+    let detections = match yolo_output.shape() {
+        [_, features, _] if *features == expected_features => yolo_output.index_axis(Axis(0), 0),
+        [features, _] if *features == expected_features => yolo_output.view(),
+        actual_shape => bail!(
+            "Unexpected YOLOv8 output shape: {:?}. Expected the second to last dimension to be {} (4 + {} classes). This probably means that your classes YAML file does not match the model.",
+            actual_shape,
+            expected_features,
+            object_classes.len()
+        ),
+    };
+
+    let predictions = yolo8_predictions(
+        detections.reversed_axes(),
+        params.confidence_threshold,
+        params.resize_factor_x,
+        params.resize_factor_y,
+        params.object_filter,
+        object_classes,
+        params.top_k_classes,
+    );
 
     // Apply non-maximum suppression (aka remove overlapping boxes)
-    let predictions = non_maximum_suppression(predictions, 0.5)?;
+    let predictions = params
+        .nms_mode
+        .apply(predictions, params.confidence_threshold)?;
 
     for (i, prediction) in predictions.iter().enumerate() {
         debug!("Prediction - {}: {:?}", i + 1, prediction);
@@ -457,18 +852,133 @@ fn yolo5_post_process(
     Ok(predictions)
 }
 
+/// Turns YOLOv8 detection rows of [x_center, y_center, width, height, class
+/// scores...] into predictions. There is no objectness so the confidence is
+/// the best class score.
+fn yolo8_predictions(
+    detections: ArrayViewD<'_, f32>,
+    confidence_threshold: f32,
+    resize_factor_x: f32,
+    resize_factor_y: f32,
+    object_filter: &Option<Vec<bool>>,
+    object_classes: &[String],
+    top_k_classes: usize,
+) -> SmallVec<[Prediction; 10]> {
+    let mut predictions = SmallVec::<[Prediction; 10]>::new();
+
+    for row in detections.outer_iter() {
+        let (class_idx, confidence) = row
+            .slice(s![4..])
+            .iter()
+            .copied()
+            .enumerate()
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+            .unwrap_or((0, 0.0));
+        if confidence <= confidence_threshold {
+            continue;
+        }
+
+        if let Some(object_filter) = object_filter
+            && !object_filter[class_idx]
+        {
+            continue;
+        }
+
+        let mut prediction = yolo_prediction(
+            &row,
+            resize_factor_x,
+            resize_factor_y,
+            confidence,
+            object_classes[class_idx].clone(),
+        );
+        prediction.top_classes = top_classes(
+            row.slice(s![4..]).iter().copied(),
+            top_k_classes,
+            object_classes,
+        );
+        predictions.push(prediction);
+    }
+    predictions
+}
+
+/// Label of a class index, models can output more classes than are named
+fn class_label(object_classes: &[String], class_idx: usize) -> String {
+    object_classes
+        .get(class_idx)
+        .cloned()
+        .unwrap_or_else(|| format!("class_{class_idx}"))
+}
+
+/// The `k` best scoring classes as (label, score), highest first, None when
+/// `k` is 0
+fn top_classes(
+    scores: impl Iterator<Item = f32>,
+    k: usize,
+    object_classes: &[String],
+) -> Option<Vec<(String, f32)>> {
+    if k == 0 {
+        return None;
+    }
+    let mut scores: Vec<(usize, f32)> = scores.enumerate().collect();
+    scores.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    Some(
+        scores
+            .into_iter()
+            .take(k)
+            .map(|(class_idx, score)| (class_label(object_classes, class_idx), score))
+            .collect(),
+    )
+}
+
+/// Prediction from a YOLO detection row starting with the box as
+/// [x_center, y_center, width, height] in model input pixels
+fn yolo_prediction(
+    row: &ArrayViewD<'_, f32>,
+    resize_factor_x: f32,
+    resize_factor_y: f32,
+    confidence: f32,
+    label: String,
+) -> Prediction {
+    let x_center = row[0] * resize_factor_x;
+    let y_center = row[1] * resize_factor_y;
+    let width = row[2] * resize_factor_x;
+    let height = row[3] * resize_factor_y;
+    Prediction::from_float_box(
+        (
+            x_center - width / 2.0,
+            y_center - height / 2.0,
+            x_center + width / 2.0,
+            y_center + height / 2.0,
+        ),
+        f32::trunc,
+        confidence,
+        label,
+    )
+}
+
+/// Overlap at which boxes of the same label are treated as the same object
+const NMS_IOU_THRESHOLD: f32 = 0.5;
+/// Spread of the Gaussian soft-NMS decay, from the soft-NMS paper
+const SOFT_NMS_GAUSSIAN_SIGMA: f32 = 0.5;
+
 fn non_maximum_suppression(
     mut predictions: SmallVec<[Prediction; 10]>,
     iou_threshold: f32,
 ) -> anyhow::Result<SmallVec<[Prediction; 10]>> {
     let mut filtered_predictions = SmallVec::new();
 
+    // Break confidence ties on the box position so the surviving box is the
+    // same regardless of the order the model returned the predictions in
     predictions.sort_by(|a, b| {
-        a.label.cmp(&b.label).then(
-            b.confidence
-                .partial_cmp(&a.confidence)
-                .unwrap_or(std::cmp::Ordering::Equal),
-        )
+        a.label
+            .cmp(&b.label)
+            .then(
+                b.confidence
+                    .partial_cmp(&a.confidence)
+                    .unwrap_or(std::cmp::Ordering::Equal),
+            )
+            .then(a.x_min.cmp(&b.x_min))
+            .then(a.y_min.cmp(&b.y_min))
     });
 
     let mut current_class = None;
@@ -502,6 +1012,48 @@ fn non_maximum_suppression(
     Ok(filtered_predictions)
 }
 
+/// Soft-NMS, instead of dropping the boxes overlapping the most confident box
+/// their confidence is decayed by the overlap and the next most confident box
+/// is picked, until no box is left above the confidence threshold
+fn soft_non_maximum_suppression(
+    mut predictions: SmallVec<[Prediction; 10]>,
+    nms_mode: NmsMode,
+    confidence_threshold: f32,
+) -> SmallVec<[Prediction; 10]> {
+    let mut kept = SmallVec::new();
+
+    // Ties are broken on the box position like the hard NMS
+    predictions.sort_by(|a, b| {
+        b.confidence
+            .partial_cmp(&a.confidence)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(a.x_min.cmp(&b.x_min))
+            .then(a.y_min.cmp(&b.y_min))
+    });
+
+    while !predictions.is_empty() {
+        // The first of the most confident boxes left
+        let best_index = (1..predictions.len()).fold(0, |best, index| {
+            if predictions[index].confidence > predictions[best].confidence {
+                index
+            } else {
+                best
+            }
+        });
+        let best = predictions.remove(best_index);
+        predictions.retain(|pred| {
+            if pred.label != best.label {
+                return true;
+            }
+            pred.confidence *= nms_mode.decay(calculate_iou(pred, &best));
+            pred.confidence > confidence_threshold
+        });
+        kept.push(best);
+    }
+
+    kept
+}
+
 fn calculate_iou(a: &Prediction, b: &Prediction) -> f32 {
     let x_min = a.x_min.max(b.x_min) as f32;
     let y_min = a.y_min.max(b.y_min) as f32;
@@ -519,7 +1071,214 @@ fn calculate_iou(a: &Prediction, b: &Prediction) -> f32 {
     }
 }
 
-fn query_image_input_size(session: &Session) -> anyhow::Result<(usize, usize)> {
+/// Build the per class mask of labels to keep from the allow list in
+/// `object_filter` and the deny list in `object_exclude`. An empty allow list
+/// keeps all classes, and a label in both lists is excluded. Labels are
+/// matched ignoring case and surrounding whitespace, labels that match no
+/// class are logged and fail the build if `strict` is set.
+fn build_object_filter(
+    object_classes: &[String],
+    object_filter: &[String],
+    object_exclude: &[String],
+    strict: bool,
+) -> anyhow::Result<Option<Vec<bool>>> {
+    if object_filter.is_empty() && object_exclude.is_empty() {
+        return Ok(None);
+    }
+    let mut unmatched = Vec::new();
+    let mut class_index = |object: &String| {
+        let object = object.trim();
+        let index = object_classes
+            .iter()
+            .position(|x| x.trim().eq_ignore_ascii_case(object));
+        if index.is_none() {
+            warn!(
+                label = object,
+                "Object filter label does not match any object class"
+            );
+            unmatched.push(object.to_string());
+        }
+        index
+    };
+
+    let mut object_filter_vector = vec![object_filter.is_empty(); object_classes.len()];
+    for index in object_filter.iter().filter_map(&mut class_index) {
+        object_filter_vector[index] = true;
+    }
+    for index in object_exclude.iter().filter_map(&mut class_index) {
+        object_filter_vector[index] = false;
+    }
+    if strict && !unmatched.is_empty() {
+        bail!(
+            "Object filter labels [{}] do not match any object class",
+            unmatched.join(", ")
+        );
+    }
+    Ok(Some(object_filter_vector))
+}
+
+/// Memory layout of the model image input tensor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputLayout {
+    /// [batch_size, channels, height, width]
+    NCHW,
+    /// [batch_size, height, width, channels]
+    NHWC,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputShape {
+    pub width: usize,
+    pub height: usize,
+    pub channels: usize,
+    pub layout: InputLayout,
+}
+
+impl InputShape {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            channels: 3,
+            layout: InputLayout::NCHW,
+        }
+    }
+}
+
+/// Copies RGB pixels into the input tensor in its layout, scaled to 0..1.
+/// Single channel models get the luminance of the pixels.
+fn fill_input(
+    input: &mut ndarray::Array4<f32>,
+    pixels: &[u8],
+    input_shape: InputShape,
+) -> anyhow::Result<()> {
+    let InputShape {
+        width,
+        height,
+        channels,
+        layout,
+    } = input_shape;
+    for (index, chunk) in pixels.chunks_exact(3).enumerate() {
+        let y = index / width;
+        let x = index % width;
+
+        // Check bounds before accessing
+        if y >= height || x >= width {
+            bail!(
+                "Index out of bounds: trying to access ({}, {}) but tensor is {}x{}",
+                x,
+                y,
+                width,
+                height
+            );
+        }
+
+        let index = |c: usize| match layout {
+            InputLayout::NCHW => [0, c, y, x],
+            InputLayout::NHWC => [0, y, x, c],
+        };
+        if channels == 1 {
+            // Single channel models get the luminance of the decoded RGB image
+            let luminance =
+                0.299 * chunk[0] as f32 + 0.587 * chunk[1] as f32 + 0.114 * chunk[2] as f32;
+            input[index(0)] = luminance / 255.0;
+        } else {
+            input[index(0)] = chunk[0] as f32 / 255.0;
+            input[index(1)] = chunk[1] as f32 / 255.0;
+            input[index(2)] = chunk[2] as f32 / 255.0;
+        }
+    }
+    Ok(())
+}
+
+/// Parse the image input shape of a model from its 4 dimensions. The layout is
+/// NHWC when the last dimension is the channel count (1 or 3), otherwise NCHW.
+fn parse_input_shape(dims: &[&str]) -> Option<InputShape> {
+    if dims.len() != 4 {
+        return None;
+    }
+    let channels = |dim: &str| match dim.parse::<usize>() {
+        Ok(channels @ (1 | 3)) => Some(channels),
+        _ => None,
+    };
+    let size = |height: &str, width: &str| match (height.parse(), width.parse()) {
+        (Ok(height), Ok(width)) => Some((width, height)),
+        _ => None,
+    };
+
+    if let Some(channels) = channels(dims[1])
+        && let Some((width, height)) = size(dims[2], dims[3])
+    {
+        return Some(InputShape {
+            width,
+            height,
+            channels,
+            layout: InputLayout::NCHW,
+        });
+    }
+    if let Some(channels) = channels(dims[3])
+        && let Some((width, height)) = size(dims[1], dims[2])
+    {
+        return Some(InputShape {
+            width,
+            height,
+            channels,
+            layout: InputLayout::NHWC,
+        });
+    }
+    // Dynamic channel dimension, assume RGB in NCHW layout
+    size(dims[2], dims[3]).map(|(width, height)| InputShape::new(width, height))
+}
+
+/// Query the model image input size, channel count and layout
+fn query_image_input_size(
+    session: &Session,
+    input_width: Option<usize>,
+    input_height: Option<usize>,
+) -> anyhow::Result<InputShape> {
+    let (shape, parsed) = query_model_input_shape(session);
+    override_input_size(shape, parsed, input_width, input_height)
+}
+
+/// Apply the configured input size over the one from the model. A size parsed
+/// from the model is fixed so the override has to match it, the guessed
+/// defaults are replaced.
+fn override_input_size(
+    mut shape: InputShape,
+    parsed: bool,
+    input_width: Option<usize>,
+    input_height: Option<usize>,
+) -> anyhow::Result<InputShape> {
+    for (name, configured, model) in [
+        ("width", input_width, &mut shape.width),
+        ("height", input_height, &mut shape.height),
+    ] {
+        let Some(configured) = configured else {
+            continue;
+        };
+        if configured == 0 {
+            bail!("The input {name} must be larger than 0");
+        }
+        if parsed && configured != *model {
+            bail!(
+                "The configured input {name} {configured} does not match the model input {name} {model}"
+            );
+        }
+        if !parsed {
+            info!(
+                configured,
+                guessed = *model,
+                "Using the configured input {name}"
+            );
+        }
+        *model = configured;
+    }
+    Ok(shape)
+}
+
+/// The image input shape of the model and whether it was parsed from the
+/// model, false if it is a default guessed from the input name
+fn query_model_input_shape(session: &Session) -> (InputShape, bool) {
     let inputs = &session.inputs;
 
     info!("Model inputs:");
@@ -537,140 +1296,540 @@ fn query_image_input_size(session: &Session) -> anyhow::Result<(usize, usize)> {
             // Parse the input type string to extract dimensions
             let type_str = format!("{:?}", input.input_type);
 
-            // Look for shape pattern like "shape: [1, 3, 384, 384]"
-            if let Some(shape_start) = type_str.find("shape: [") {
-                let shape_part = &type_str[shape_start + 8..];
-                if let Some(shape_end) = shape_part.find(']') {
-                    let shape_str = &shape_part[..shape_end];
-                    let dims: Vec<&str> = shape_str.split(',').map(|s| s.trim()).collect();
-
-                    // Expect format [batch_size, channels, height, width]
-                    if dims.len() == 4
-                        && let (Ok(height), Ok(width)) =
-                            (dims[2].parse::<usize>(), dims[3].parse::<usize>())
-                    {
-                        info!(
-                            "Extracted input size from model '{}': {}x{}",
-                            input.name, width, height
-                        );
-                        return Ok((width, height));
-                    }
-                }
+            if let Some(dims) = parse_shape_dims(&type_str)
+                && let Some(shape) = parse_input_shape(&dims)
+            {
+                info!(
+                    "Extracted input size from model '{}': {}x{} with {} channel(s) in {:?} layout",
+                    input.name, shape.width, shape.height, shape.channels, shape.layout
+                );
+                return (shape, true);
             }
 
             // Fallback: use heuristic based on input name
             if input.name == "input" {
                 info!("Could not parse dimensions, using RF-DETR default: 384x384");
-                return Ok((384, 384));
+                return (InputShape::new(384, 384), false);
             } else if input.name == "images" {
                 info!("Could not parse dimensions, using RT-DETR/YOLO default: 640x640");
-                return Ok((640, 640));
+                return (InputShape::new(640, 640), false);
             }
         }
     }
 
     // Fallback to 640x640 if we can't detect the size
-    warn!("Could not detect input size from model, falling back to 640x640");
-    Ok((640, 640))
+    warn!(
+        "Could not detect input size from model, falling back to 640x640, set input_width and input_height if that is wrong"
+    );
+    (InputShape::new(640, 640), false)
 }
 
-#[derive(Debug, Clone)]
-pub struct DetectorConfig {
-    pub object_classes: Option<PathBuf>,
-    pub object_filter: Vec<String>,
-    pub confidence_threshold: f32,
-    pub save_image_path: Option<PathBuf>,
-    pub save_ref_image: bool,
+/// Extract the dimensions from the debug string of an ONNX value type by
+/// looking for a shape pattern like "shape: [1, 3, 384, 384]"
+fn parse_shape_dims(type_str: &str) -> Option<Vec<&str>> {
+    let shape_start = type_str.find("shape: [")?;
+    let shape_part = &type_str[shape_start + 8..];
+    let shape_end = shape_part.find(']')?;
+    Some(
+        shape_part[..shape_end]
+            .split(',')
+            .map(|s| s.trim())
+            .collect(),
+    )
+}
+
+/// Number of classes the model outputs, None if it can't be derived from the
+/// output shapes. RT-DETR outputs label ids so it has no class dimension.
+fn model_class_count(
+    object_detection_model: &ObjectDetectionModel,
+    outputs: &[(&str, Vec<&str>)],
+) -> Option<usize> {
+    let last_dim = |name: Option<&str>| {
+        outputs
+            .iter()
+            .find(|(output_name, _)| name.is_none_or(|name| *output_name == name))
+            .and_then(|(_, dims)| dims.last())
+            .and_then(|dim| dim.parse::<usize>().ok())
+    };
+    match object_detection_model {
+        ObjectDetectionModel::RtDetrv2 => None,
+        // The labels output holds one logit per class for every query
+        ObjectDetectionModel::RfDetr => last_dim(Some("labels")),
+        // Each detection is 4 box coordinates and an objectness score followed
+        // by one score per class
+        ObjectDetectionModel::Yolo5 => last_dim(None)?.checked_sub(5),
+        // The same without the objectness score, but the features come before
+        // the detections
+        ObjectDetectionModel::Yolo8 => outputs
+            .first()
+            .and_then(|(_, dims)| dims.iter().rev().nth(1))
+            .and_then(|dim| dim.parse::<usize>().ok())?
+            .checked_sub(4),
+    }
+}
+
+/// Output names with the debug formatted type, the shape is parsed from it
+/// with parse_shape_dims
+fn session_output_types(session: &Session) -> Vec<(&str, String)> {
+    session
+        .outputs
+        .iter()
+        .map(|output| (output.name.as_str(), format!("{:?}", output.output_type)))
+        .collect()
+}
+
+fn query_model_class_count(
+    session: &Session,
+    object_detection_model: &ObjectDetectionModel,
+) -> Option<usize> {
+    let type_strs = session_output_types(session);
+    let outputs: Vec<(&str, Vec<&str>)> = type_strs
+        .iter()
+        .filter_map(|(name, type_str)| Some((*name, parse_shape_dims(type_str)?)))
+        .collect();
+    model_class_count(object_detection_model, &outputs)
+}
+
+/// Check that the object classes YAML matches the number of classes the model
+/// outputs. YOLO needs an exact match to parse the output, RF-DETR class ids
+/// index into the YAML so it needs at least as many names as the model has classes.
+fn validate_class_count(
+    object_detection_model: &ObjectDetectionModel,
+    model_class_count: Option<usize>,
+    object_class_count: usize,
+) -> anyhow::Result<()> {
+    let Some(model_class_count) = model_class_count else {
+        return Ok(());
+    };
+    let matches = match object_detection_model {
+        ObjectDetectionModel::Yolo5 | ObjectDetectionModel::Yolo8 => {
+            object_class_count == model_class_count
+        }
+        ObjectDetectionModel::RfDetr => object_class_count >= model_class_count,
+        ObjectDetectionModel::RtDetrv2 => true,
+    };
+    if !matches {
+        bail!(
+            "The object classes files have {object_class_count} classes in total but the {object_detection_model} model outputs {model_class_count} classes, use class files that match the model"
+        );
+    }
+    Ok(())
+}
+
+/// Named overrides of the detection settings that a request can select with
+/// the `profile` field, e.g. per camera thresholds. Unset values use the
+/// server settings.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct DetectionProfile {
+    pub confidence_threshold: Option<f32>,
+    /// Labels to keep, replaces the server object_filter
+    pub object_filter: Option<Vec<String>>,
+    /// Minimum box area in pixels of the original image, smaller boxes are dropped
+    pub min_box_area: Option<usize>,
+    /// Polygons that keep or drop detections by the center of their box
+    pub zones: Vec<DetectionZone>,
+}
+
+/// A polygon in pixels of the image as it was sent. With include zones only
+/// detections centered in one of them are kept, detections centered in an
+/// exclude zone are always dropped.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct DetectionZone {
+    /// Vertices as `[x, y]` pairs, the polygon is closed implicitly
+    pub polygon: Vec<[f32; 2]>,
+    /// Drop detections in the zone instead of keeping only those in it
+    pub exclude: bool,
+}
+
+impl DetectionZone {
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.polygon.len() < 3 {
+            bail!(
+                "Zone polygon needs at least 3 vertices, got {}",
+                self.polygon.len()
+            );
+        }
+        if self.polygon.iter().flatten().any(|v| !v.is_finite()) {
+            bail!("Zone polygon vertices must be finite numbers");
+        }
+        Ok(())
+    }
+
+    /// Even-odd ray casting test
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        let mut inside = false;
+        let mut previous = match self.polygon.last() {
+            Some(vertex) => *vertex,
+            None => return false,
+        };
+        for &[xi, yi] in &self.polygon {
+            let [xj, yj] = previous;
+            if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+                inside = !inside;
+            }
+            previous = [xi, yi];
+        }
+        inside
+    }
+}
+
+/// Whether the zones keep a prediction, by the center of its box
+fn zones_keep(zones: &[DetectionZone], prediction: &Prediction) -> bool {
+    let x = (prediction.x_min + prediction.x_max) as f32 / 2.0;
+    let y = (prediction.y_min + prediction.y_max) as f32 / 2.0;
+    let mut includes = zones.iter().filter(|zone| !zone.exclude).peekable();
+    let included = includes.peek().is_none() || includes.any(|zone| zone.contains(x, y));
+    included && !zones.iter().any(|zone| zone.exclude && zone.contains(x, y))
+}
+
+/// A DetectionProfile with the object filter resolved against the classes
+#[derive(Clone)]
+struct ResolvedProfile {
+    confidence_threshold: Option<f32>,
+    /// None keeps the server object filter
+    object_filter: Option<Option<Vec<bool>>>,
+    min_box_area: Option<usize>,
+    zones: Vec<DetectionZone>,
+}
+
+/// Confidence threshold and object filter of a request. The request
+/// confidence takes precedence over the profile and the profile over the
+/// detector defaults.
+fn request_thresholds<'a>(
+    min_confidence: Option<f32>,
+    profile: Option<&'a ResolvedProfile>,
+    confidence_threshold: f32,
+    object_filter: &'a Option<Vec<bool>>,
+) -> (f32, &'a Option<Vec<bool>>) {
+    let confidence_threshold = min_confidence
+        .or(profile.and_then(|p| p.confidence_threshold))
+        .unwrap_or(confidence_threshold);
+    let object_filter = profile
+        .and_then(|p| p.object_filter.as_ref())
+        .unwrap_or(object_filter);
+    (confidence_threshold, object_filter)
+}
+
+#[derive(Debug, Clone)]
+pub struct DetectorConfig {
+    pub object_classes: Vec<PathBuf>,
+    pub object_filter: Vec<String>,
+    pub object_exclude: Vec<String>,
+    /// Fail at startup if an object_filter or object_exclude label matches no class
+    pub strict_object_filter: bool,
+    pub confidence_threshold: f32,
+    pub save_image_path: Option<PathBuf>,
+    /// Optional path template relative to save_image_path for processed images
+    pub save_image_template: Option<String>,
+    pub save_ref_image: bool,
+    /// Minimum number of detections for a processed image to be saved
+    pub save_min_objects: usize,
+    /// Format processed images are saved in
+    pub output_image_format: OutputImageFormat,
     pub timeout: Duration,
     pub object_detection_onnx_config: OnnxConfig,
-    pub object_detection_model: ObjectDetectionModel,
+    /// Model type, None infers it from the model outputs
+    pub object_detection_model: Option<ObjectDetectionModel>,
+    /// Minimum time between notifications for the same label, None fires on every detection
+    pub notify_cooldown: Option<Duration>,
+    /// CSV file that gets a row with the stage timings of every detection
+    pub timing_log: Option<PathBuf>,
+    /// Number of detection responses cached for identical images, 0 disables the cache
+    pub detection_cache_size: usize,
+    /// Number of warmup detections to run at startup, 0 skips the warmup
+    pub warmup_iterations: usize,
+    /// Number of self-benchmark detections to run and log before serving, 0 skips it
+    pub benchmark_on_start: usize,
+    pub resize_filter: ResizeFilter,
+    /// How overlapping boxes are suppressed for the YOLO models
+    pub nms_mode: NmsMode,
+    /// Crop the largest centered region with the model aspect ratio before
+    /// resizing instead of stretching the whole image
+    pub center_crop: bool,
+    /// Decode JPEGs that fail the strict decode best effort instead of failing
+    pub lenient_decode: bool,
+    /// Reuse the last result for frames whose downsampled mean absolute
+    /// difference to the last inferred frame is below this, None disables it
+    pub motion_threshold: Option<f32>,
+    /// Clockwise rotation applied to decoded images before detection
+    pub rotate: Rotation,
+    /// Scheduling priority of the worker threads
+    pub worker_priority: WorkerPriority,
+    /// Pin a single worker thread to the core it starts on
+    pub worker_pin_core: bool,
+    /// Adjust the queue limit from the recent processing times
+    pub adaptive_queue: bool,
+    /// Raise the timeout to fit the measured processing time
+    pub auto_timeout: bool,
+    /// Detection profiles requests can select by name
+    pub profiles: HashMap<String, DetectionProfile>,
 }
 
 impl Detector {
     pub fn new(detector_config: DetectorConfig) -> anyhow::Result<Self> {
-        let (device_type, model_name, session, endpoint_provider, model_yaml_path, (width, height)) =
-            initialize_onnx(&detector_config.object_detection_onnx_config)?; // Prioritize the YAML file that comes with the model over the configured one
-        let yaml_path_to_use = model_yaml_path.or(detector_config.object_classes);
+        #[cfg_attr(not(windows), allow(unused_mut))]
+        let mut onnx_config = detector_config.object_detection_onnx_config.clone();
+        #[cfg(windows)]
+        if let Some(gpu_name) = &onnx_config.gpu_name
+            && !onnx_config.force_cpu
+        {
+            onnx_config.gpu_index = crate::system_info::gpu_index_by_name(gpu_name)?;
+        }
 
-        let object_classes = if let Some(yaml_path) = &yaml_path_to_use {
-            info!("Using object classes from model YAML: {:?}", yaml_path);
-            get_object_classes(Some(yaml_path.clone()))?
-        } else {
-            bail!(
+        let model_load_start_time = Instant::now();
+        let (
+            device_type,
+            model_name,
+            session,
+            endpoint_provider,
+            model_classes,
+            input_shape,
+            inferred_model_type,
+        ) = initialize_onnx(&onnx_config)?;
+        let model_load_time = model_load_start_time.elapsed();
+        info!(?model_load_time, "Loaded model {model_name}");
+
+        let object_detection_model = match (
+            detector_config.object_detection_model,
+            inferred_model_type,
+        ) {
+            (Some(configured), Some(inferred)) if configured != inferred => {
+                warn!(
+                    %configured,
+                    %inferred,
+                    "Configured model type does not match the model outputs, using the configured type"
+                );
+                configured
+            }
+            (Some(configured), _) => configured,
+            (None, Some(inferred)) => inferred,
+            (None, None) => {
+                let fallback = ObjectDetectionModel::default();
+                warn!(
+                    %fallback,
+                    "Could not infer the model type from the model outputs, set object_detection_model_type"
+                );
+                fallback
+            }
+        };
+        info!(%object_detection_model, "Using object detection model type");
+
+        // Prioritize the classes that come with the model over the configured ones
+        let object_classes = match model_classes {
+            Some(model_classes) => model_classes,
+            None if !detector_config.object_classes.is_empty() => {
+                info!(
+                    "Using object classes from: {:?}",
+                    detector_config.object_classes
+                );
+                get_merged_object_classes(&detector_config.object_classes)?
+            }
+            None => bail!(
                 "No YAML file found with model. A YAML file containing object classes is required for the model."
-            );
+            ),
         };
 
-        let mut object_filter = None;
-        if !detector_config.object_filter.is_empty() {
-            let mut object_filter_vector = vec![false; object_classes.len()];
-            for object in detector_config.object_filter.iter() {
-                if let Some(index) = object_classes
-                    .iter()
-                    .position(|x| x.to_lowercase() == object.to_lowercase())
-                {
-                    object_filter_vector[index] = true;
+        validate_class_count(
+            &object_detection_model,
+            query_model_class_count(&session, &object_detection_model),
+            object_classes.len(),
+        )?;
+
+        let object_filter = build_object_filter(
+            &object_classes,
+            &detector_config.object_filter,
+            &detector_config.object_exclude,
+            detector_config.strict_object_filter,
+        )?;
+        let profiles = detector_config
+            .profiles
+            .iter()
+            .map(|(name, profile)| {
+                let object_filter = match &profile.object_filter {
+                    Some(object_filter) => Some(build_object_filter(
+                        &object_classes,
+                        object_filter,
+                        &detector_config.object_exclude,
+                        detector_config.strict_object_filter,
+                    )?),
+                    None => None,
+                };
+                for zone in &profile.zones {
+                    zone.validate()
+                        .with_context(|| format!("Invalid zone in profile {name}"))?;
                 }
-            }
-            object_filter = Some(object_filter_vector);
-        }
+                Ok((
+                    name.clone(),
+                    ResolvedProfile {
+                        confidence_threshold: profile.confidence_threshold,
+                        object_filter,
+                        min_box_area: profile.min_box_area,
+                        zones: profile.zones.clone(),
+                    },
+                ))
+            })
+            .collect::<anyhow::Result<_>>()?;
 
         let mut detector = Self {
             model_name,
             endpoint_provider,
             session,
-            resizer: Resizer::new(width, height)?,
+            resizer: Resizer::new(
+                input_shape.width,
+                input_shape.height,
+                detector_config.resize_filter,
+                detector_config.center_crop,
+            )?,
             decoded_image: Image::default(),
             resized_image: Image::default(),
-            input: Array::zeros((1, 3, height, width)),
+            rotation: detector_config.rotate,
+            nms_mode: detector_config.nms_mode,
+            lenient_decode: detector_config.lenient_decode,
+            // Enabled after the warmup so every warmup run is timed
+            motion_threshold: None,
+            motion_frames: HashMap::new(),
+            motion_thumbnail: Vec::new(),
+            rotate_buffer: Vec::new(),
+            input: match input_shape.layout {
+                InputLayout::NCHW => Array::zeros((
+                    1,
+                    input_shape.channels,
+                    input_shape.height,
+                    input_shape.width,
+                )),
+                InputLayout::NHWC => Array::zeros((
+                    1,
+                    input_shape.height,
+                    input_shape.width,
+                    input_shape.channels,
+                )),
+            },
             object_classes,
             object_filter,
+            profiles,
             confidence_threshold: detector_config.confidence_threshold,
             device_type,
-            save_image_path: detector_config.save_image_path,
-            save_ref_image: detector_config.save_ref_image,
-            object_detection_model: detector_config.object_detection_model,
-            input_width: width,
-            input_height: height,
+            save_images: detector_config.save_image_path.map(|path| SaveImages {
+                path,
+                template: detector_config.save_image_template,
+                ref_image: detector_config.save_ref_image,
+                min_objects: detector_config.save_min_objects,
+                output_image_format: detector_config.output_image_format,
+            }),
+            object_detection_model,
+            input_width: input_shape.width,
+            input_height: input_shape.height,
+            input_channels: input_shape.channels,
+            input_layout: input_shape.layout,
+            gpu_index: onnx_config.gpu_index,
+            warmup_min_processing_time: None,
+            model_load_time,
+            warmup_time: Duration::ZERO,
         };
 
         // Warmup
-        info!("Warming up the detector");
-        let detector_warmup_start_time = Instant::now();
-        detector.detect(Bytes::from(crate::DOG_BIKE_CAR_BYTES), None, None)?;
-        info!(
-            "Detector warmed up in: {:?}",
-            detector_warmup_start_time.elapsed()
-        );
+        let warmup_iterations = detector_config.warmup_iterations;
+        if warmup_iterations == 0 {
+            info!("Skipping detector warmup");
+        } else {
+            info!("Warming up the detector with {warmup_iterations} iterations");
+            let detector_warmup_start_time = Instant::now();
+            for _ in 0..warmup_iterations {
+                let processing_start_time = Instant::now();
+                detector.detect(
+                    Bytes::from(crate::DOG_BIKE_CAR_BYTES),
+                    None,
+                    DetectOptions::default(),
+                )?;
+                let processing_time = processing_start_time.elapsed();
+                detector.warmup_min_processing_time = Some(
+                    detector
+                        .warmup_min_processing_time
+                        .map_or(processing_time, |min| min.min(processing_time)),
+                );
+            }
+            detector.warmup_time = detector_warmup_start_time.elapsed();
+            info!("Detector warmed up in: {:?}", detector.warmup_time);
+        }
+        detector.motion_threshold = detector_config.motion_threshold;
 
         Ok(detector)
     }
 
+    /// Create a detector for the model with the same defaults as the server.
+    /// Empty object_classes uses the classes that come with the model.
+    pub fn from_model_path(
+        model: impl Into<PathBuf>,
+        object_classes: Vec<PathBuf>,
+    ) -> anyhow::Result<Self> {
+        let mut detector_config = crate::cli::Cli::default().detector_config();
+        detector_config.object_detection_onnx_config.model = Some(model.into());
+        detector_config.object_classes = object_classes;
+        Self::new(detector_config)
+    }
+
+    /// Detect objects in a JPEG, PNG, BMP or TIFF image file
+    pub fn detect_from_path(&mut self, path: &Path) -> anyhow::Result<DetectResult> {
+        let image_bytes = load_image(path)?;
+        let image_name = path.to_string_lossy().to_string();
+        self.detect(image_bytes, Some(image_name), DetectOptions::default())
+    }
+
     pub fn detect(
         &mut self,
         image_bytes: Bytes,
         image_name: Option<String>,
-        min_confidence: Option<f32>,
+        options: DetectOptions<'_>,
     ) -> anyhow::Result<DetectResult> {
-        // Save the image if save_ref_image is set
+        let DetectOptions {
+            camera,
+            min_confidence,
+            profile,
+            rois,
+            top_k_classes,
+        } = options;
+        let motion_settings = self.motion_threshold.map(|_| {
+            (
+                profile.map(str::to_string),
+                min_confidence.map(f32::to_bits),
+                rois.to_vec(),
+                top_k_classes,
+            )
+        });
+        let profile = profile
+            .map(|name| {
+                self.profiles
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("Unknown profile: {name}"))
+            })
+            .transpose()?;
+
+        // Images are only saved after inference, once it is known whether the
+        // frame has enough predictions
+        let mut image_to_save = None;
         if let Some(ref image_name_str) = image_name {
             debug!("Detecting objects in image: {}", image_name_str);
-            if let Some(ref save_image_path) = self.save_image_path
-                && self.save_ref_image
-            {
-                let save_image_path = save_image_path.to_path_buf();
-                let image_path_buf = PathBuf::from(image_name_str);
-                let image_name_ref = image_path_buf
-                    .file_name()
-                    .ok_or_else(|| anyhow::anyhow!("Failed to get file name from path"))?;
-                let save_image_path = save_image_path.join(image_name_ref);
-                std::fs::write(save_image_path, &image_bytes)?;
+            if let Some(ref save_images) = self.save_images {
+                image_to_save = Some(save_images.image_to_save(image_name_str, &image_bytes)?);
             }
         }
 
         // Process from here
         let processing_time_start = Instant::now();
-        decode_jpeg(image_name.clone(), image_bytes, &mut self.decoded_image)?;
+        decode_image(
+            image_name.clone(),
+            image_bytes,
+            &mut self.decoded_image,
+            self.lenient_decode,
+        )?;
+        // Detection runs on the upright image, predictions are mapped back to
+        // the image as it was sent after the annotated image is saved
+        let (decoded_width, decoded_height) = (self.decoded_image.width, self.decoded_image.height);
+        self.decoded_image
+            .rotate(self.rotation, &mut self.rotate_buffer);
         let decode_image_time = processing_time_start.elapsed();
 
         debug!(
@@ -678,8 +1837,45 @@ impl Detector {
             decode_image_time, self.decoded_image.width, self.decoded_image.height
         );
 
-        let resize_factor_x = self.decoded_image.width as f32 / self.input_width as f32;
-        let resize_factor_y = self.decoded_image.height as f32 / self.input_height as f32;
+        if let Some(motion_threshold) = self.motion_threshold
+            && let Some(ref motion_settings) = motion_settings
+        {
+            self.decoded_image
+                .motion_thumbnail(&mut self.motion_thumbnail);
+            if let Some(frame) = self.motion_frames.get(&camera.map(str::to_string))
+                && frame.settings == *motion_settings
+                && frame.width == decoded_width
+                && frame.height == decoded_height
+                && let Some(difference) =
+                    mean_absolute_difference(&frame.thumbnail, &self.motion_thumbnail)
+                && difference < motion_threshold
+            {
+                debug!(
+                    "Frame difference {difference:.2} is below motion_threshold {motion_threshold}, reusing the last result"
+                );
+                return Ok(DetectResult {
+                    predictions: frame.predictions.clone(),
+                    processing_time: processing_time_start.elapsed(),
+                    decode_image_time,
+                    resize_image_time: Duration::ZERO,
+                    pre_processing_time: Duration::ZERO,
+                    inference_time: Duration::ZERO,
+                    post_processing_time: Duration::ZERO,
+                    device_type: self.device_type,
+                    endpoint_provider: self.endpoint_provider,
+                    image_width: decoded_width,
+                    image_height: decoded_height,
+                });
+            }
+        }
+
+        // Predictions are mapped back to the crop and then offset into the
+        // original image, the crop is the whole image unless center cropping
+        let crop = self
+            .resizer
+            .crop_region(self.decoded_image.width, self.decoded_image.height);
+        let resize_factor_x = crop.width as f32 / self.input_width as f32;
+        let resize_factor_y = crop.height as f32 / self.input_height as f32;
         debug!(
             "Image resize factors: width_factor={:.3} ({}->{}), height_factor={:.3} ({}->{})",
             resize_factor_x,
@@ -730,8 +1926,9 @@ impl Detector {
             expected_pixels, actual_pixels
         );
         debug!(
-            "Input tensor shape: [1, 3, {}, {}]",
-            self.input_height, self.input_width
+            "Input tensor shape: {:?} ({:?})",
+            self.input.shape(),
+            self.input_layout
         );
 
         if actual_pixels != expected_pixels {
@@ -742,25 +1939,16 @@ impl Detector {
             );
         }
 
-        for (index, chunk) in self.resized_image.pixels.chunks_exact(3).enumerate() {
-            let y = index / self.input_width;
-            let x = index % self.input_width;
-
-            // Check bounds before accessing
-            if y >= self.input_height || x >= self.input_width {
-                bail!(
-                    "Index out of bounds: trying to access ({}, {}) but tensor is {}x{}",
-                    x,
-                    y,
-                    self.input_width,
-                    self.input_height
-                );
-            }
-
-            self.input[[0, 0, y, x]] = chunk[0] as f32 / 255.0;
-            self.input[[0, 1, y, x]] = chunk[1] as f32 / 255.0;
-            self.input[[0, 2, y, x]] = chunk[2] as f32 / 255.0;
-        }
+        fill_input(
+            &mut self.input,
+            &self.resized_image.pixels,
+            InputShape {
+                width: self.input_width,
+                height: self.input_height,
+                channels: self.input_channels,
+                layout: self.input_layout,
+            },
+        )?;
 
         debug!(
             "Copy pixels to input time: {:?}",
@@ -783,17 +1971,52 @@ impl Detector {
         let inference_time = start_inference_time.elapsed();
         debug!("Inference time: {:?}", inference_time);
         let post_processing_time_start = Instant::now();
-        let confidence_threshold = min_confidence.unwrap_or(self.confidence_threshold);
+        let (confidence_threshold, object_filter) = request_thresholds(
+            min_confidence,
+            profile.as_ref(),
+            self.confidence_threshold,
+            &self.object_filter,
+        );
         let params = PostProcessParams {
             confidence_threshold,
             resize_factor_x,
             resize_factor_y,
-            object_filter: &self.object_filter,
+            object_filter,
             object_classes: &self.object_classes,
             input_width: self.input_width as u32,
             input_height: self.input_height as u32,
+            nms_mode: self.nms_mode,
+            top_k_classes,
         };
-        let predictions = self.object_detection_model.post_process(outputs, &params)?;
+        let mut predictions = self.object_detection_model.post_process(outputs, &params)?;
+        for prediction in predictions.iter_mut() {
+            prediction.translate(crop.left, crop.top);
+        }
+        if let Some(min_box_area) = profile.as_ref().and_then(|p| p.min_box_area) {
+            predictions.retain(|p| {
+                p.x_max.saturating_sub(p.x_min) * p.y_max.saturating_sub(p.y_min) >= min_box_area
+            });
+        }
+        if let Some(zones) = profile.as_ref().map(|p| &p.zones)
+            && !zones.is_empty()
+        {
+            // Zones are in the coordinates of the image as sent
+            let rotation = self.rotation;
+            predictions.retain(|p| {
+                let mut unrotated = p.clone();
+                rotation.unrotate_prediction(&mut unrotated, decoded_width, decoded_height);
+                zones_keep(zones, &unrotated)
+            });
+        }
+        if !rois.is_empty() {
+            // ROIs are in the coordinates of the image as sent
+            let rotation = self.rotation;
+            predictions.retain(|p| {
+                let mut unrotated = p.clone();
+                rotation.unrotate_prediction(&mut unrotated, decoded_width, decoded_height);
+                rois.iter().any(|roi| roi.contains_center(&unrotated))
+            });
+        }
 
         let now = Instant::now();
         let post_processing_time = now.duration_since(post_processing_time_start);
@@ -807,36 +2030,38 @@ impl Detector {
 
         debug!("Processing time: {:?}", processing_time);
 
-        if let Some(ref image_name) = image_name
-            && let Some(ref save_image_path) = self.save_image_path
+        if let Some(ref image_to_save) = image_to_save
+            && let Some(ref save_images) = self.save_images
         {
-            info!(
-                "Saving detection result with {} predictions to disk",
-                predictions.len()
-            );
-            let save_image_start_time = Instant::now();
-            let save_image_path = save_image_path.to_path_buf();
-            let image_name_od = create_od_image_name(image_name, true)?;
-            let output_path = save_image_path
-                .join(&image_name_od)
-                .to_string_lossy()
-                .to_string();
-            info!("Output path: {}", output_path);
-
-            encode_maybe_draw_boundary_boxes_and_save_jpeg(
+            save_images.save(
+                image_to_save,
                 &self.decoded_image,
-                &output_path,
-                Some(predictions.as_slice()),
-                self.input_width as u32,
-                self.input_height as u32,
+                &predictions,
+                camera,
+                (self.input_width as u32, self.input_height as u32),
             )?;
-            debug!("Save image time: {:?}", save_image_start_time.elapsed());
-        } else {
-            if image_name.is_none() {
-                debug!("No image name provided, skipping image save");
-            }
-            if self.save_image_path.is_none() {
-                debug!("No save path configured, skipping image save");
+        } else if image_name.is_none() {
+            debug!("No image name provided, skipping image save");
+        } else if self.save_images.is_none() {
+            debug!("No save path configured, skipping image save");
+        }
+        for prediction in predictions.iter_mut() {
+            self.rotation
+                .unrotate_prediction(prediction, decoded_width, decoded_height);
+        }
+        if let Some(motion_settings) = motion_settings {
+            let frame = MotionFrame {
+                settings: motion_settings,
+                thumbnail: std::mem::take(&mut self.motion_thumbnail),
+                width: decoded_width,
+                height: decoded_height,
+                predictions: predictions.clone(),
+                inferred_at: Instant::now(),
+            };
+            if let Some(previous) =
+                insert_motion_frame(&mut self.motion_frames, camera.map(str::to_string), frame)
+            {
+                self.motion_thumbnail = previous.thumbnail;
             }
         }
 
@@ -850,19 +2075,38 @@ impl Detector {
             post_processing_time,
             device_type: self.device_type,
             endpoint_provider: self.endpoint_provider,
+            image_width: decoded_width,
+            image_height: decoded_height,
         })
     }
 
+    /// Get the min processing time, reusing the warmup timings if the detector
+    /// was warmed up, otherwise running the detector a few times to measure it.
     pub fn get_min_processing_time(&mut self) -> anyhow::Result<Duration> {
+        if let Some(min_processing_time) = self.warmup_min_processing_time {
+            info!(
+                ?min_processing_time,
+                "Using min processing time from warmup"
+            );
+            return Ok(min_processing_time);
+        }
         const TUNE_RUNS: usize = 10;
         info!("Running detector {TUNE_RUNS} times to get min processing time");
         let mut min_processing_time = Duration::MAX;
-        for _ in 0..TUNE_RUNS {
-            let detector_warmup_start_time = Instant::now();
-            self.detect(Bytes::from(crate::DOG_BIKE_CAR_BYTES), None, None)?;
-            let processing_time = detector_warmup_start_time.elapsed();
-            min_processing_time = min_processing_time.min(processing_time);
-        }
+        // The same image every run, the motion gate would skip the inference
+        self.without_motion_gate(|detector| {
+            for _ in 0..TUNE_RUNS {
+                let detector_warmup_start_time = Instant::now();
+                detector.detect(
+                    Bytes::from(crate::DOG_BIKE_CAR_BYTES),
+                    None,
+                    DetectOptions::default(),
+                )?;
+                let processing_time = detector_warmup_start_time.elapsed();
+                min_processing_time = min_processing_time.min(processing_time);
+            }
+            anyhow::Ok(())
+        })?;
         info!(
             ?min_processing_time,
             "Done running detector {TUNE_RUNS} times"
@@ -870,6 +2114,15 @@ impl Detector {
         Ok(min_processing_time)
     }
 
+    /// Runs `f` with the motion gate disabled, for repeated detections of the
+    /// same image that should all run inference
+    pub fn without_motion_gate<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        let motion_threshold = self.motion_threshold.take();
+        let result = f(self);
+        self.motion_threshold = motion_threshold;
+        result
+    }
+
     pub fn get_model_name(&self) -> &String {
         &self.model_name
     }
@@ -882,9 +2135,39 @@ impl Detector {
         self.device_type == DeviceType::GPU
     }
 
+    pub fn get_execution_provider(&self) -> ExecutionProvider {
+        match self.endpoint_provider {
+            EndpointProvider::CPU => ExecutionProvider::CPU,
+            #[cfg(windows)]
+            EndpointProvider::DirectML => ExecutionProvider::DirectML(self.gpu_index as usize),
+        }
+    }
+
+    pub fn get_gpu_index(&self) -> i32 {
+        self.gpu_index
+    }
+
     pub fn get_input_size(&self) -> (usize, usize) {
         (self.input_width, self.input_height)
     }
+
+    pub fn get_object_class_count(&self) -> usize {
+        self.object_classes.len()
+    }
+
+    pub fn get_object_classes(&self) -> &[String] {
+        &self.object_classes
+    }
+
+    /// Time it took to load the model and build the session
+    pub fn get_model_load_time(&self) -> Duration {
+        self.model_load_time
+    }
+
+    /// Time the warmup detections took, zero if the warmup was skipped
+    pub fn get_warmup_time(&self) -> Duration {
+        self.warmup_time
+    }
 }
 
 type InitializeOnnxResult = Result<
@@ -893,33 +2176,71 @@ type InitializeOnnxResult = Result<
         String,
         Session,
         EndpointProvider,
-        Option<PathBuf>,
-        (usize, usize), // (width, height)
+        Option<Vec<String>>,
+        InputShape,
+        Option<ObjectDetectionModel>,
     ),
     anyhow::Error,
 >;
 
+/// Caps a CPU thread count at the physical cores - 1 and at most 16 threads.
+/// On Linux more threads than physical cores makes inference much slower
+/// instead of faster, so that is warned about.
+fn cap_cpu_threads(kind: &str, requested: usize) -> usize {
+    let physical_cores = num_cpus::get_physical();
+    let max_threads = physical_cores.saturating_sub(1).clamp(1, 16);
+    if requested <= max_threads {
+        return requested;
+    }
+    if cfg!(target_os = "linux") && requested > physical_cores {
+        warn!(
+            requested,
+            physical_cores,
+            capped = max_threads,
+            "{kind} thread count is above the physical cores, this hurts performance on Linux, capping it"
+        );
+    } else {
+        info!(
+            requested,
+            capped = max_threads,
+            "Capping {kind} thread count to the physical cores - 1"
+        );
+    }
+    max_threads
+}
+
 fn initialize_onnx(onnx_config: &OnnxConfig) -> InitializeOnnxResult {
-    #[cfg_attr(not(windows), allow(unused_mut))]
+    // The library is loaded when the first session is created, so the path
+    // has to be set before that. Later calls keep the library already loaded.
+    if let Some(ort_lib_path) = &onnx_config.ort_lib_path {
+        if !ort_lib_path.is_file() {
+            bail!(
+                "ONNX Runtime library {} does not exist",
+                ort_lib_path.display()
+            );
+        }
+        info!(path = %ort_lib_path.display(), "Loading ONNX Runtime library");
+        ort::init_from(ort_lib_path.to_string_lossy())
+            .commit()
+            .with_context(|| {
+                format!(
+                    "Failed to load ONNX Runtime from {}",
+                    ort_lib_path.display()
+                )
+            })?;
+    }
     let mut providers = Vec::new();
     #[cfg_attr(not(windows), allow(unused_mut))]
     let mut device_type = DeviceType::CPU;
 
     let (num_intra_threads, num_inter_threads) = if onnx_config.force_cpu {
-        let num_intra_threads = onnx_config
-            .intra_threads
-            .min(num_cpus::get_physical() - 1)
-            .min(16);
-        let num_inter_threads = onnx_config
-            .inter_threads
-            .min(num_cpus::get_physical() - 1)
-            .min(16);
+        let num_intra_threads = cap_cpu_threads("intra", onnx_config.intra_threads);
+        let num_inter_threads = cap_cpu_threads("inter", onnx_config.inter_threads);
         info!(
             "Forcing CPU for inference with {} intra and {} inter threads",
             num_intra_threads, num_inter_threads
         );
         // When forcing CPU, ensure no other providers are used
-        // providers list will remain empty, which means CPU provider is used by default
         (num_intra_threads, num_inter_threads)
     } else {
         #[cfg(windows)]
@@ -938,14 +2259,8 @@ fn initialize_onnx(onnx_config: &OnnxConfig) -> InitializeOnnxResult {
             info!("DirectML initialization successful");
             (1, 1) // For GPU we just hardcode to 1 thread
         } else {
-            let num_intra_threads = onnx_config
-                .intra_threads
-                .min(num_cpus::get_physical() - 1)
-                .min(16);
-            let num_inter_threads = onnx_config
-                .inter_threads
-                .min(num_cpus::get_physical() - 1)
-                .min(16);
+            let num_intra_threads = cap_cpu_threads("intra", onnx_config.intra_threads);
+            let num_inter_threads = cap_cpu_threads("inter", onnx_config.inter_threads);
             #[cfg(windows)]
             warn!(
                 "DirectML not available, falling back to CPU for inference with {} intra and {} inter threads",
@@ -961,14 +2276,8 @@ fn initialize_onnx(onnx_config: &OnnxConfig) -> InitializeOnnxResult {
 
         #[cfg(not(windows))]
         {
-            let num_intra_threads = onnx_config
-                .intra_threads
-                .min(num_cpus::get_physical() - 1)
-                .min(16);
-            let num_inter_threads = onnx_config
-                .inter_threads
-                .min(num_cpus::get_physical() - 1)
-                .min(16);
+            let num_intra_threads = cap_cpu_threads("intra", onnx_config.intra_threads);
+            let num_inter_threads = cap_cpu_threads("inter", onnx_config.inter_threads);
             warn!(
                 "GPU acceleration not available on this platform, using CPU for inference with {} intra and {} inter threads",
                 num_intra_threads, num_inter_threads
@@ -977,21 +2286,37 @@ fn initialize_onnx(onnx_config: &OnnxConfig) -> InitializeOnnxResult {
         }
     };
 
+    // The CPU provider is always registered last so it is also the fallback
+    // for nodes the GPU provider can't run
+    providers.push(
+        CPUExecutionProvider::default()
+            .with_arena_allocator(onnx_config.enable_cpu_mem_arena)
+            .build(),
+    );
+
     // Simple model and yaml file handling
-    let model_filename = onnx_config
-        .model
-        .as_ref()
-        .and_then(|p| p.file_name())
-        .and_then(|n| n.to_str())
-        .map(|s| s.to_string());
-
-    let (model_path, yaml_path) = crate::ensure_model_files(model_filename)?;
-    let model_bytes = std::fs::read(&model_path)?;
-    let model_name = model_path
-        .file_name()
-        .and_then(|n| n.to_str())
-        .unwrap_or("unknown")
-        .to_string();
+    let model_file_name = onnx_config.model_file_name();
+    let (model_bytes, model_name, model_classes) =
+        if crate::is_embedded_model(model_file_name.as_deref()) {
+            load_embedded_model()?
+        } else {
+            let (model_path, yaml_path) = crate::ensure_model_files(
+                model_file_name,
+                onnx_config.models_dir.as_deref(),
+                onnx_config.classes_yaml.as_deref(),
+            )?;
+            let model_name = model_path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("unknown")
+                .to_string();
+            info!("Using object classes from model YAML: {:?}", yaml_path);
+            (
+                Cow::Owned(std::fs::read(&model_path)?),
+                model_name,
+                get_object_classes(Some(yaml_path))?,
+            )
+        };
 
     info!(
         "Initializing detector with model: {:?} and inference running on {}",
@@ -999,20 +2324,46 @@ fn initialize_onnx(onnx_config: &OnnxConfig) -> InitializeOnnxResult {
     );
 
     // Build the session with the appropriate execution providers
-    // Note: When providers list is empty (which is the case when force_cpu=true),
-    // ONNX Runtime will default to CPU execution provider
-    let session = Session::builder()?
+    // Note: When force_cpu=true only the CPU execution provider is registered
+    let mut session_builder = Session::builder()?;
+    if let Some(graph_optimization_level) = onnx_config.graph_optimization_level {
+        info!(
+            ?graph_optimization_level,
+            "Setting graph optimization level"
+        );
+        session_builder =
+            session_builder.with_optimization_level(graph_optimization_level.into())?;
+    }
+    if !onnx_config.enable_cpu_mem_arena {
+        info!("CPU memory arena disabled");
+    }
+    let session = session_builder
         .with_execution_providers(providers)?
         .with_intra_threads(num_intra_threads)?
         .with_inter_threads(num_inter_threads)?
-        .commit_from_memory(model_bytes.as_slice())?;
+        .commit_from_memory(&model_bytes)?;
 
     // Query the input size from the model
-    let (width, height) = query_image_input_size(&session)?;
+    let input_shape =
+        query_image_input_size(&session, onnx_config.input_width, onnx_config.input_height)?;
 
     info!(
         "Model '{}' configured with input size: {}x{} ({}x{} tensor)",
-        model_name, width, height, height, width
+        model_name, input_shape.width, input_shape.height, input_shape.height, input_shape.width
+    );
+
+    let type_strs = session_output_types(&session);
+    let outputs: Vec<(&str, Vec<&str>)> = type_strs
+        .iter()
+        .map(|(name, type_str)| (*name, parse_shape_dims(type_str).unwrap_or_default()))
+        .collect();
+    let inferred_model_type = infer_model_type(&outputs);
+    let output_names: Vec<&str> = outputs.iter().map(|(name, _)| *name).collect();
+    info!(
+        ?output_names,
+        ?inferred_model_type,
+        "Model '{}' model type inferred from outputs",
+        model_name
     );
 
     let endpoint_provider = match device_type {
@@ -1025,11 +2376,61 @@ fn initialize_onnx(onnx_config: &OnnxConfig) -> InitializeOnnxResult {
         model_name,
         session,
         endpoint_provider,
-        Some(yaml_path),
-        (width, height),
+        Some(model_classes),
+        input_shape,
+        inferred_model_type,
+    ))
+}
+
+/// The model and classes embedded in the executable with the embed-model feature
+#[cfg(feature = "embed-model")]
+fn load_embedded_model() -> anyhow::Result<(Cow<'static, [u8]>, String, Vec<String>)> {
+    use crate::embedded_model::{MODEL_BYTES, MODEL_FILE_NAME, object_classes};
+    info!(
+        model = MODEL_FILE_NAME,
+        "Using the model embedded in the executable"
+    );
+    Ok((
+        Cow::Borrowed(MODEL_BYTES),
+        MODEL_FILE_NAME.to_string(),
+        object_classes()?,
     ))
 }
 
+#[cfg(not(feature = "embed-model"))]
+fn load_embedded_model() -> anyhow::Result<(Cow<'static, [u8]>, String, Vec<String>)> {
+    bail!("No model is embedded, build with the embed-model feature")
+}
+
+/// Infer the model type from the session output names and shapes: RT-DETR
+/// has labels, boxes and scores, RF-DETR has dets and labels and YOLO a single
+/// output. YOLOv8 puts the features before the detections, [1, 84, 8400], so
+/// it has fewer features than detections while YOLO5 is [1, 25200, 85]. With a
+/// dynamic number of detections the dynamic dimension is the detections.
+fn infer_model_type(outputs: &[(&str, Vec<&str>)]) -> Option<ObjectDetectionModel> {
+    let has = |name: &str| outputs.iter().any(|(output_name, _)| *output_name == name);
+    if has("labels") && has("boxes") && has("scores") {
+        Some(ObjectDetectionModel::RtDetrv2)
+    } else if has("dets") && has("labels") {
+        Some(ObjectDetectionModel::RfDetr)
+    } else if let [(_, dims)] = outputs {
+        // Dynamic dimensions, -1 or a name, are None so positions are kept
+        let dims: Vec<Option<i64>> = dims
+            .iter()
+            .map(|dim| dim.parse().ok().filter(|dim| *dim > 0))
+            .collect();
+        match dims.as_slice() {
+            [_, Some(features), Some(detections)] if features < detections => {
+                Some(ObjectDetectionModel::Yolo8)
+            }
+            [_, Some(_), None] => Some(ObjectDetectionModel::Yolo8),
+            _ => Some(ObjectDetectionModel::Yolo5),
+        }
+    } else {
+        None
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum EndpointProvider {
     CPU,
@@ -1069,3 +2470,538 @@ pub enum ExecutionProvider {
     #[cfg(windows)]
     DirectML(usize), // GPU index
 }
+
+impl ExecutionProvider {
+    /// DirectML device id of the GPU, None when running on CPU
+    pub fn gpu_index(&self) -> Option<usize> {
+        match self {
+            ExecutionProvider::CPU => None,
+            #[cfg(windows)]
+            ExecutionProvider::DirectML(index) => Some(*index),
+        }
+    }
+}
+
+impl std::fmt::Display for ExecutionProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExecutionProvider::CPU => write!(f, "CPU"),
+            #[cfg(windows)]
+            ExecutionProvider::DirectML(index) => write!(f, "DirectML(GPU {index})"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_input_shape_layout() {
+        assert_eq!(
+            parse_input_shape(&["1", "3", "384", "640"]),
+            Some(InputShape {
+                width: 640,
+                height: 384,
+                channels: 3,
+                layout: InputLayout::NCHW,
+            })
+        );
+        assert_eq!(
+            parse_input_shape(&["1", "320", "256", "3"]),
+            Some(InputShape {
+                width: 256,
+                height: 320,
+                channels: 3,
+                layout: InputLayout::NHWC,
+            })
+        );
+        assert_eq!(
+            parse_input_shape(&["1", "64", "64", "1"]).map(|shape| shape.layout),
+            Some(InputLayout::NHWC)
+        );
+        assert_eq!(
+            parse_input_shape(&["batch", "-1", "640", "640"]),
+            Some(InputShape::new(640, 640))
+        );
+        assert_eq!(parse_input_shape(&["1", "3", "640"]), None);
+    }
+
+    #[test]
+    fn test_fill_input_layout() {
+        // Pixel (x, y) has the channel values 10 * (2y + x) + c + 1
+        let pixels = (0..4)
+            .flat_map(|pixel| (0..3).map(move |c| (10 * pixel + c + 1) as u8))
+            .collect::<Vec<_>>();
+        let shape = |layout| InputShape {
+            width: 2,
+            height: 2,
+            channels: 3,
+            layout,
+        };
+        let expected = |x: usize, y: usize, c: usize| (10 * (2 * y + x) + c + 1) as f32 / 255.0;
+
+        let mut nchw = Array::zeros((1, 3, 2, 2));
+        fill_input(&mut nchw, &pixels, shape(InputLayout::NCHW)).unwrap();
+        let mut nhwc = Array::zeros((1, 2, 2, 3));
+        fill_input(&mut nhwc, &pixels, shape(InputLayout::NHWC)).unwrap();
+
+        for y in 0..2 {
+            for x in 0..2 {
+                for c in 0..3 {
+                    assert_eq!(nchw[[0, c, y, x]], expected(x, y, c));
+                    assert_eq!(nhwc[[0, y, x, c]], expected(x, y, c));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_override_input_size() {
+        let guessed = InputShape::new(640, 640);
+        assert_eq!(
+            override_input_size(guessed, false, Some(512), None).unwrap(),
+            InputShape::new(512, 640)
+        );
+        assert_eq!(
+            override_input_size(guessed, false, None, None).unwrap(),
+            guessed
+        );
+
+        let parsed = InputShape::new(384, 384);
+        assert!(override_input_size(parsed, true, Some(384), Some(384)).is_ok());
+        let err = override_input_size(parsed, true, None, Some(640)).unwrap_err();
+        assert!(err.to_string().contains("input height 640"));
+        assert!(override_input_size(guessed, false, Some(0), None).is_err());
+    }
+
+    #[test]
+    fn test_infer_model_type_from_outputs() {
+        let outputs = |names: &[&'static str]| -> Vec<(&'static str, Vec<&'static str>)> {
+            names.iter().map(|name| (*name, vec![])).collect()
+        };
+        assert_eq!(
+            infer_model_type(&outputs(&["labels", "boxes", "scores"])),
+            Some(ObjectDetectionModel::RtDetrv2)
+        );
+        assert_eq!(
+            infer_model_type(&outputs(&["dets", "labels"])),
+            Some(ObjectDetectionModel::RfDetr)
+        );
+        assert_eq!(
+            infer_model_type(&outputs(&["output0"])),
+            Some(ObjectDetectionModel::Yolo5)
+        );
+        assert_eq!(
+            infer_model_type(&[("output0", vec!["1", "25200", "85"])]),
+            Some(ObjectDetectionModel::Yolo5)
+        );
+        assert_eq!(
+            infer_model_type(&[("output0", vec!["1", "84", "8400"])]),
+            Some(ObjectDetectionModel::Yolo8)
+        );
+        // Dynamic detections keep their position
+        assert_eq!(
+            infer_model_type(&[("output0", vec!["1", "-1", "85"])]),
+            Some(ObjectDetectionModel::Yolo5)
+        );
+        assert_eq!(
+            infer_model_type(&[("output0", vec!["batch", "anchors", "85"])]),
+            Some(ObjectDetectionModel::Yolo5)
+        );
+        assert_eq!(
+            infer_model_type(&[("output0", vec!["1", "84", "-1"])]),
+            Some(ObjectDetectionModel::Yolo8)
+        );
+        assert_eq!(
+            infer_model_type(&[("output0", vec!["batch", "84", "anchors"])]),
+            Some(ObjectDetectionModel::Yolo8)
+        );
+        assert_eq!(infer_model_type(&outputs(&["a", "b"])), None);
+    }
+
+    #[test]
+    fn test_yolo5_confidence_combines_objectness_and_class_score() {
+        let classes: Vec<String> = ["person", "car"].map(String::from).to_vec();
+        let detections = ndarray::arr2(&[
+            // High objectness and class score
+            [50.0, 50.0, 20.0, 20.0, 0.9, 0.9, 0.1],
+            // High objectness but low class score
+            [150.0, 150.0, 20.0, 20.0, 0.9, 0.2, 0.3],
+            // Low objectness is dropped by the prefilter
+            [250.0, 250.0, 20.0, 20.0, 0.4, 0.1, 1.0],
+        ]);
+
+        let predictions = yolo5_predictions(
+            detections.view().into_dyn(),
+            0.5,
+            1.0,
+            1.0,
+            &None,
+            &classes,
+            0,
+        );
+
+        assert_eq!(predictions.len(), 1);
+        assert_eq!(predictions[0].label, "person");
+        assert!((predictions[0].confidence - 0.81).abs() < 1e-6);
+        assert_eq!(predictions[0].x_min, 40);
+    }
+
+    #[test]
+    fn test_yolo8_transposed_output_uses_best_class_score() {
+        let classes: Vec<String> = ["person", "car"].map(String::from).to_vec();
+        // One column per detection: x_center, y_center, width, height, class scores
+        let detections = ndarray::arr2(&[
+            [50.0, 150.0],
+            [50.0, 150.0],
+            [20.0, 20.0],
+            [20.0, 20.0],
+            [0.1, 0.2],
+            [0.8, 0.3],
+        ]);
+
+        let predictions = yolo8_predictions(
+            detections.view().reversed_axes().into_dyn(),
+            0.5,
+            1.0,
+            1.0,
+            &None,
+            &classes,
+            2,
+        );
+
+        assert_eq!(predictions.len(), 1);
+        assert_eq!(predictions[0].label, "car");
+        assert!((predictions[0].confidence - 0.8).abs() < 1e-6);
+        assert_eq!((predictions[0].x_min, predictions[0].y_min), (40, 40));
+        let top_classes = predictions[0].top_classes.as_ref().unwrap();
+        assert_eq!(
+            top_classes
+                .iter()
+                .map(|(label, _)| label.as_str())
+                .collect::<Vec<_>>(),
+            ["car", "person"]
+        );
+        assert!((top_classes[1].1 - 0.1).abs() < 1e-6);
+
+        let dims = vec!["1", "6", "8400"];
+        let yolo8 = ObjectDetectionModel::Yolo8;
+        assert_eq!(model_class_count(&yolo8, &[("output0", dims)]), Some(2));
+    }
+
+    #[test]
+    fn test_build_object_filter_exclude_wins() {
+        let classes: Vec<String> = ["person", "car", "dog"].map(String::from).to_vec();
+        let labels = |labels: &[&str]| labels.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        let build = |filter: &[String], exclude: &[String]| {
+            build_object_filter(&classes, filter, exclude, false).unwrap()
+        };
+
+        assert_eq!(build(&[], &[]), None);
+        assert_eq!(
+            build(&labels(&["Person", " car "]), &[]),
+            Some(vec![true, true, false])
+        );
+        assert_eq!(build(&[], &labels(&["dog"])), Some(vec![true, true, false]));
+        assert_eq!(
+            build(&labels(&["person", "car"]), &labels(&["car"])),
+            Some(vec![true, false, false])
+        );
+    }
+
+    #[test]
+    fn test_build_object_filter_unmatched_label() {
+        let classes: Vec<String> = ["person", "car"].map(String::from).to_vec();
+        let filter = vec!["persn".to_string()];
+
+        assert_eq!(
+            build_object_filter(&classes, &filter, &[], false).unwrap(),
+            Some(vec![false, false])
+        );
+        assert!(build_object_filter(&classes, &filter, &[], true).is_err());
+    }
+
+    #[test]
+    fn test_validate_class_count_against_model_outputs() {
+        let type_str = "Tensor { ty: Float32, shape: [1, 25200, 85], dimension_symbols: [] }";
+        let dims = parse_shape_dims(type_str).unwrap();
+        assert_eq!(dims, ["1", "25200", "85"]);
+
+        let yolo5 = ObjectDetectionModel::Yolo5;
+        let model_classes = model_class_count(&yolo5, &[("output0", dims)]);
+        assert_eq!(model_classes, Some(80));
+        assert!(validate_class_count(&yolo5, model_classes, 80).is_ok());
+        let err = validate_class_count(&yolo5, model_classes, 90).unwrap_err();
+        assert!(err.to_string().contains("90 classes"));
+        assert!(err.to_string().contains("outputs 80 classes"));
+
+        let rf_detr = ObjectDetectionModel::RfDetr;
+        let outputs = [
+            ("dets", vec!["1", "300", "4"]),
+            ("labels", vec!["1", "300", "91"]),
+        ];
+        let model_classes = model_class_count(&rf_detr, &outputs);
+        assert_eq!(model_classes, Some(91));
+        assert!(validate_class_count(&rf_detr, model_classes, 91).is_ok());
+        assert!(validate_class_count(&rf_detr, model_classes, 80).is_err());
+
+        let rt_detr = ObjectDetectionModel::RtDetrv2;
+        assert_eq!(model_class_count(&rt_detr, &outputs), None);
+        assert!(validate_class_count(&rt_detr, None, 80).is_ok());
+    }
+
+    #[test]
+    fn test_non_maximum_suppression_equal_confidence_is_stable() {
+        let prediction = |x_min: usize, y_min: usize| Prediction {
+            x_min,
+            y_min,
+            x_max: x_min + 100,
+            y_max: y_min + 100,
+            confidence: 0.8,
+            label: "person".to_string(),
+            ..Default::default()
+        };
+        let boxes = [prediction(10, 10), prediction(12, 10), prediction(10, 8)];
+
+        let forward = non_maximum_suppression(boxes.iter().cloned().collect(), 0.5).unwrap();
+        let reverse = non_maximum_suppression(boxes.iter().rev().cloned().collect(), 0.5).unwrap();
+
+        assert_eq!(forward.len(), 1);
+        assert_eq!(forward, reverse);
+        assert_eq!((forward[0].x_min, forward[0].y_min), (10, 8));
+    }
+
+    #[test]
+    fn test_soft_non_maximum_suppression_decays_overlapping_boxes() {
+        let prediction = |x_min: usize, confidence: f32, label: &str| Prediction {
+            x_min,
+            y_min: 0,
+            x_max: x_min + 100,
+            y_max: 100,
+            confidence,
+            label: label.to_string(),
+            ..Default::default()
+        };
+        // IoU of the first two boxes is 80 / 120
+        let boxes: SmallVec<[Prediction; 10]> = [
+            prediction(0, 0.9, "car"),
+            prediction(20, 0.8, "car"),
+            prediction(20, 0.7, "person"),
+            prediction(500, 0.6, "car"),
+        ]
+        .into_iter()
+        .collect();
+
+        let hard = NmsMode::Hard.apply(boxes.clone(), 0.25).unwrap();
+        assert_eq!(hard.len(), 3);
+
+        let linear = NmsMode::Linear.apply(boxes.clone(), 0.25).unwrap();
+        assert_eq!(linear.len(), 4);
+        let decayed = linear
+            .iter()
+            .find(|p| p.x_min == 20 && p.label == "car")
+            .unwrap();
+        assert!((decayed.confidence - 0.8 / 3.0).abs() < 1e-5);
+        // Other labels and boxes without overlap keep their confidence
+        assert!(
+            linear
+                .iter()
+                .any(|p| p.label == "person" && p.confidence == 0.7)
+        );
+        assert!(linear.iter().any(|p| p.x_min == 500 && p.confidence == 0.6));
+
+        // Decayed below the threshold is dropped
+        let linear = NmsMode::Linear.apply(boxes.clone(), 0.3).unwrap();
+        assert_eq!(linear.len(), 3);
+
+        let gaussian = NmsMode::Gaussian.apply(boxes, 0.25).unwrap();
+        let decayed = gaussian
+            .iter()
+            .find(|p| p.x_min == 20 && p.label == "car")
+            .unwrap();
+        assert!(decayed.confidence < 0.8 && decayed.confidence > 0.25);
+    }
+
+    #[test]
+    fn test_profile_overrides_confidence_and_object_filter() {
+        let classes = ["person".to_string(), "car".to_string(), "dog".to_string()];
+        let server_filter = build_object_filter(&classes, &[], &[], false).unwrap();
+        let profile = ResolvedProfile {
+            confidence_threshold: Some(0.7),
+            object_filter: Some(
+                build_object_filter(&classes, &["car".to_string()], &[], false).unwrap(),
+            ),
+            min_box_area: None,
+            zones: vec![],
+        };
+
+        let (confidence, filter) = request_thresholds(None, None, 0.5, &server_filter);
+        assert_eq!(confidence, 0.5);
+        assert_eq!(filter, &server_filter);
+
+        let (confidence, filter) = request_thresholds(None, Some(&profile), 0.5, &server_filter);
+        assert_eq!(confidence, 0.7);
+        assert_eq!(filter, &Some(vec![false, true, false]));
+
+        // The request confidence wins over the profile, the filter stays
+        let (confidence, filter) =
+            request_thresholds(Some(0.3), Some(&profile), 0.5, &server_filter);
+        assert_eq!(confidence, 0.3);
+        assert_eq!(filter, &Some(vec![false, true, false]));
+
+        // A profile without values keeps the server defaults
+        let empty_profile = ResolvedProfile {
+            confidence_threshold: None,
+            object_filter: None,
+            min_box_area: None,
+            zones: vec![],
+        };
+        let (confidence, filter) =
+            request_thresholds(None, Some(&empty_profile), 0.5, &server_filter);
+        assert_eq!(confidence, 0.5);
+        assert_eq!(filter, &server_filter);
+    }
+
+    #[test]
+    fn test_motion_frames_are_capped_per_camera() {
+        let frame = |inferred_at: Instant| MotionFrame {
+            settings: (None, None, vec![], 0),
+            thumbnail: vec![],
+            width: 640,
+            height: 480,
+            predictions: SmallVec::new(),
+            inferred_at,
+        };
+        let start = Instant::now();
+        let mut motion_frames = HashMap::new();
+        for index in 0..MAX_MOTION_FRAMES {
+            let inferred_at = start + Duration::from_millis(index as u64);
+            assert!(
+                insert_motion_frame(
+                    &mut motion_frames,
+                    Some(format!("camera{index}")),
+                    frame(inferred_at)
+                )
+                .is_none()
+            );
+        }
+        // The same camera replaces its frame
+        let later = start + Duration::from_secs(1);
+        assert!(
+            insert_motion_frame(&mut motion_frames, Some("camera0".into()), frame(later)).is_some()
+        );
+        assert_eq!(motion_frames.len(), MAX_MOTION_FRAMES);
+
+        // A new camera drops the one inferred least recently, camera1 now
+        let evicted = insert_motion_frame(
+            &mut motion_frames,
+            None,
+            frame(later + Duration::from_secs(1)),
+        );
+        assert_eq!(
+            evicted.unwrap().inferred_at,
+            start + Duration::from_millis(1)
+        );
+        assert_eq!(motion_frames.len(), MAX_MOTION_FRAMES);
+        assert!(!motion_frames.contains_key(&Some("camera1".to_string())));
+        assert!(motion_frames.contains_key(&Some("camera0".to_string())));
+        assert!(motion_frames.contains_key(&None));
+    }
+
+    #[test]
+    fn test_zones_keep_by_box_center() {
+        let prediction = |x_min: usize, y_min: usize| Prediction {
+            x_min,
+            y_min,
+            x_max: x_min + 20,
+            y_max: y_min + 20,
+            confidence: 0.8,
+            label: "person".to_string(),
+            ..Default::default()
+        };
+        // Concave L shape, the corner (150, 50) is outside it
+        let driveway = DetectionZone {
+            polygon: vec![
+                [0.0, 0.0],
+                [100.0, 0.0],
+                [100.0, 100.0],
+                [200.0, 100.0],
+                [200.0, 200.0],
+                [0.0, 200.0],
+            ],
+            exclude: false,
+        };
+        let door = DetectionZone {
+            polygon: vec![[0.0, 150.0], [50.0, 150.0], [50.0, 200.0], [0.0, 200.0]],
+            exclude: true,
+        };
+        assert!(driveway.contains(50.0, 50.0));
+        assert!(driveway.contains(150.0, 150.0));
+        assert!(!driveway.contains(150.0, 50.0));
+
+        let zones = [driveway, door];
+        assert!(zones_keep(&zones, &prediction(40, 40)));
+        assert!(!zones_keep(&zones, &prediction(140, 40)));
+        assert!(!zones_keep(&zones, &prediction(10, 160)));
+        // Without include zones everything outside the exclude zones is kept
+        assert!(zones_keep(&zones[1..], &prediction(140, 40)));
+        assert!(zones_keep(&[], &prediction(10, 160)));
+
+        assert!(
+            DetectionZone {
+                polygon: vec![[0.0, 0.0], [1.0, 1.0]],
+                exclude: false
+            }
+            .validate()
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_save_images_skips_frames_below_save_min_objects() {
+        let path =
+            std::env::temp_dir().join(format!("blue_onyx_save_min_objects_{}", std::process::id()));
+        std::fs::create_dir_all(&path).unwrap();
+        let save_images = SaveImages {
+            path: path.clone(),
+            template: None,
+            ref_image: true,
+            min_objects: 1,
+            output_image_format: OutputImageFormat::Jpeg,
+        };
+        let image_bytes = Bytes::from_static(b"jpeg bytes as sent");
+        let image_to_save = save_images
+            .image_to_save("camera.jpg", &image_bytes)
+            .unwrap();
+        let image = Image {
+            name: None,
+            width: 2,
+            height: 2,
+            pixels: vec![128; 12],
+        };
+        let saved_files = || std::fs::read_dir(&path).unwrap().count();
+
+        save_images
+            .save(&image_to_save, &image, &[], None, (2, 2))
+            .unwrap();
+        assert_eq!(saved_files(), 0);
+
+        let prediction = Prediction {
+            x_max: 1,
+            y_max: 1,
+            confidence: 0.8,
+            label: "person".to_string(),
+            ..Default::default()
+        };
+        save_images
+            .save(&image_to_save, &image, &[prediction], None, (2, 2))
+            .unwrap();
+        let ref_image = std::fs::read(path.join(&image_to_save.saved_image_name)).unwrap();
+        assert_eq!(ref_image, image_bytes);
+        assert_eq!(saved_files(), 2);
+
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+}
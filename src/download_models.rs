@@ -1,7 +1,13 @@
-use hf_hub::api::tokio::Api;
-use std::path::{Path, PathBuf};
+use hf_hub::api::tokio::{Api, ApiRepo};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
 use tokio::fs;
-use tracing::info;
+use tracing::{info, warn};
+
+const DOWNLOAD_ATTEMPTS: u32 = 3;
+const DOWNLOAD_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
 
 pub enum Model {
     Model(String),
@@ -142,29 +148,14 @@ async fn download_repository_files(
     let mut errors = Vec::new();
 
     for filename in files.iter() {
-        match api_repo.get(filename).await {
-            Ok(cached_file) => {
-                let target_path = target_dir.join(filename);
-
-                match fs::copy(&cached_file, &target_path).await {
-                    Ok(_) => {
-                        info!("Downloaded {} to {}", filename, target_path.display());
-                        downloaded_models.push(filename.to_string());
-                    }
-                    Err(e) => {
-                        let error_msg = format!(
-                            "Failed to copy {} to {}: {}",
-                            filename,
-                            target_path.display(),
-                            e
-                        );
-                        info!("Warning: {}", error_msg);
-                        errors.push(error_msg);
-                    }
-                }
+        let target_path = target_dir.join(filename);
+        match download_with_retry(&api_repo, filename, &target_path).await {
+            Ok(()) => {
+                info!("Downloaded {} to {}", filename, target_path.display());
+                downloaded_models.push(filename.to_string());
             }
             Err(e) => {
-                let error_msg = format!("Failed to download {filename} from {repo_name}: {e}");
+                let error_msg = format!("Failed to download {filename} from {repo_name}: {e:#}");
                 info!("Warning: {}", error_msg);
                 errors.push(error_msg);
             }
@@ -196,10 +187,8 @@ pub async fn download_file_to_dir(filename: &str, target_dir: &Path) -> anyhow::
             let api_repo = api.model(repo_name.to_string());
 
             // Download the file
-            let cached_file = api_repo.get(filename).await?;
             let target_path = target_dir.join(filename);
-
-            fs::copy(&cached_file, &target_path).await?;
+            download_with_retry(&api_repo, filename, &target_path).await?;
             info!("Downloaded {} to {}", filename, target_path.display());
             return Ok(());
         }
@@ -209,3 +198,43 @@ pub async fn download_file_to_dir(filename: &str, target_dir: &Path) -> anyhow::
         filename
     ))
 }
+
+/// Fetch a file from the repository and copy it to the target path, retrying
+/// with exponential backoff so a transient network error doesn't fail startup
+async fn download_with_retry(
+    api_repo: &ApiRepo,
+    filename: &str,
+    target_path: &Path,
+) -> anyhow::Result<()> {
+    let mut backoff = DOWNLOAD_INITIAL_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        let result = async {
+            let cached_file = api_repo.get(filename).await?;
+            fs::copy(&cached_file, target_path).await?;
+            anyhow::Ok(())
+        }
+        .await;
+        match result {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt >= DOWNLOAD_ATTEMPTS => {
+                return Err(e.context(format!(
+                    "Failed to download {filename} after {DOWNLOAD_ATTEMPTS} attempts"
+                )));
+            }
+            Err(e) => {
+                warn!(
+                    attempt,
+                    max_attempts = DOWNLOAD_ATTEMPTS,
+                    ?backoff,
+                    "Failed to download {}: {}, retrying",
+                    filename,
+                    e
+                );
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+        }
+    }
+}
@@ -1,14 +1,24 @@
 use crate::api::Prediction;
 use ab_glyph::{FontArc, PxScale};
-use anyhow::bail;
+use anyhow::{Context, bail};
 use bytes::Bytes;
-use image::{DynamicImage, ImageBuffer};
+use image::{DynamicImage, ImageBuffer, RgbImage};
 use jpeg_encoder::{ColorType, Encoder};
-use std::{fmt, path::Path, time::Instant};
-use tracing::{debug, info};
+use std::{
+    borrow::Cow,
+    fmt,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+use tracing::{debug, info, warn};
 use zune_core::{bytestream::ZCursor, colorspace::ColorSpace, options::DecoderOptions};
 use zune_jpeg::JpegDecoder;
 
+/// Side of the grayscale grid frames are compared on by the motion gate
+const MOTION_THUMBNAIL_SIZE: usize = 32;
+/// Pixels sampled per side of each motion thumbnail cell
+const MOTION_THUMBNAIL_SAMPLES: usize = 4;
+
 pub struct Image {
     pub name: Option<String>,
     pub width: usize,
@@ -20,6 +30,205 @@ impl Image {
     pub fn resize(&mut self, size: usize) {
         self.pixels.resize(size, 0);
     }
+
+    /// Rotates the RGB pixels clockwise, `buffer` holds the previous pixels
+    /// afterwards so it can be reused for the next image
+    pub fn rotate(&mut self, rotation: Rotation, buffer: &mut Vec<u8>) {
+        if rotation == Rotation::None {
+            return;
+        }
+        let (width, height) = (self.width, self.height);
+        buffer.clear();
+        buffer.resize(width * height * 3, 0);
+        let (rotated_width, rotated_height) = rotation.rotated_size(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let (rotated_x, rotated_y) = match rotation {
+                    Rotation::None => (x, y),
+                    Rotation::Rotate90 => (height - 1 - y, x),
+                    Rotation::Rotate180 => (width - 1 - x, height - 1 - y),
+                    Rotation::Rotate270 => (y, width - 1 - x),
+                };
+                let source = (y * width + x) * 3;
+                let target = (rotated_y * rotated_width + rotated_x) * 3;
+                buffer[target..target + 3].copy_from_slice(&self.pixels[source..source + 3]);
+            }
+        }
+        std::mem::swap(&mut self.pixels, buffer);
+        self.width = rotated_width;
+        self.height = rotated_height;
+    }
+
+    /// Downsamples the RGB pixels to a small grayscale grid that is cheap to
+    /// compare with [`mean_absolute_difference`], each cell is the mean luma
+    /// of a few pixels sampled in it
+    pub fn motion_thumbnail(&self, thumbnail: &mut Vec<u8>) {
+        thumbnail.clear();
+        if self.width == 0 || self.height == 0 {
+            return;
+        }
+        let samples = MOTION_THUMBNAIL_SIZE * MOTION_THUMBNAIL_SAMPLES;
+        // Center of the sample in pixels, always inside the image
+        let sample_position = |index: usize, size: usize| (2 * index + 1) * size / (2 * samples);
+        for cell_y in 0..MOTION_THUMBNAIL_SIZE {
+            for cell_x in 0..MOTION_THUMBNAIL_SIZE {
+                let mut sum = 0;
+                for sample_y in 0..MOTION_THUMBNAIL_SAMPLES {
+                    let y =
+                        sample_position(cell_y * MOTION_THUMBNAIL_SAMPLES + sample_y, self.height);
+                    for sample_x in 0..MOTION_THUMBNAIL_SAMPLES {
+                        let x = sample_position(
+                            cell_x * MOTION_THUMBNAIL_SAMPLES + sample_x,
+                            self.width,
+                        );
+                        let pixel = &self.pixels[(y * self.width + x) * 3..][..3];
+                        sum +=
+                            (77 * pixel[0] as u32 + 150 * pixel[1] as u32 + 29 * pixel[2] as u32)
+                                >> 8;
+                    }
+                }
+                thumbnail.push(
+                    (sum / (MOTION_THUMBNAIL_SAMPLES * MOTION_THUMBNAIL_SAMPLES) as u32) as u8,
+                );
+            }
+        }
+    }
+}
+
+/// Mean absolute difference of two motion thumbnails from 0 to 255, None if
+/// there is nothing to compare
+pub fn mean_absolute_difference(a: &[u8], b: &[u8]) -> Option<f32> {
+    if a.len() != b.len() || a.is_empty() {
+        return None;
+    }
+    let sum: u64 = a.iter().zip(b).map(|(a, b)| a.abs_diff(*b) as u64).sum();
+    Some(sum as f32 / a.len() as f32)
+}
+
+/// Fixed clockwise rotation of decoded images, for cameras mounted sideways
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "u16", into = "u16")]
+pub enum Rotation {
+    #[default]
+    None,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+
+impl Rotation {
+    /// Size of an image with the given size after the rotation
+    pub fn rotated_size(self, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            Rotation::None | Rotation::Rotate180 => (width, height),
+            Rotation::Rotate90 | Rotation::Rotate270 => (height, width),
+        }
+    }
+
+    /// Maps a prediction on the rotated image back to the image as it was
+    /// decoded, `width` and `height` are the size before the rotation
+    pub fn unrotate_prediction(self, prediction: &mut Prediction, width: usize, height: usize) {
+        (
+            prediction.x_min,
+            prediction.x_max,
+            prediction.y_min,
+            prediction.y_max,
+        ) = self.unrotate_box(
+            (
+                prediction.x_min,
+                prediction.x_max,
+                prediction.y_min,
+                prediction.y_max,
+            ),
+            width,
+            height,
+            usize::saturating_sub,
+        );
+        if let (Some(x_min), Some(x_max), Some(y_min), Some(y_max)) = (
+            prediction.x_min_f,
+            prediction.x_max_f,
+            prediction.y_min_f,
+            prediction.y_max_f,
+        ) {
+            let (x_min, x_max, y_min, y_max) = self.unrotate_box(
+                (x_min, x_max, y_min, y_max),
+                width as f32,
+                height as f32,
+                |a: f32, b: f32| (a - b).max(0.0),
+            );
+            prediction.x_min_f = Some(x_min);
+            prediction.x_max_f = Some(x_max);
+            prediction.y_min_f = Some(y_min);
+            prediction.y_max_f = Some(y_max);
+        }
+    }
+
+    /// Maps an (x_min, x_max, y_min, y_max) box on the rotated image back,
+    /// `sub` is a subtraction that does not go below 0
+    fn unrotate_box<T: Copy>(
+        self,
+        (x_min, x_max, y_min, y_max): (T, T, T, T),
+        width: T,
+        height: T,
+        sub: impl Fn(T, T) -> T,
+    ) -> (T, T, T, T) {
+        match self {
+            Rotation::None => (x_min, x_max, y_min, y_max),
+            Rotation::Rotate90 => (y_min, y_max, sub(height, x_max), sub(height, x_min)),
+            Rotation::Rotate180 => (
+                sub(width, x_max),
+                sub(width, x_min),
+                sub(height, y_max),
+                sub(height, y_min),
+            ),
+            Rotation::Rotate270 => (sub(width, y_max), sub(width, y_min), x_min, x_max),
+        }
+    }
+}
+
+impl TryFrom<u16> for Rotation {
+    type Error = String;
+
+    fn try_from(degrees: u16) -> Result<Self, Self::Error> {
+        match degrees {
+            0 => Ok(Rotation::None),
+            90 => Ok(Rotation::Rotate90),
+            180 => Ok(Rotation::Rotate180),
+            270 => Ok(Rotation::Rotate270),
+            _ => Err(format!(
+                "Unsupported rotation {degrees}, use 0, 90, 180 or 270"
+            )),
+        }
+    }
+}
+
+impl From<Rotation> for u16 {
+    fn from(rotation: Rotation) -> Self {
+        match rotation {
+            Rotation::None => 0,
+            Rotation::Rotate90 => 90,
+            Rotation::Rotate180 => 180,
+            Rotation::Rotate270 => 270,
+        }
+    }
+}
+
+impl std::str::FromStr for Rotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let degrees: u16 = s
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid rotation {s}, use 0, 90, 180 or 270"))?;
+        Rotation::try_from(degrees)
+    }
+}
+
+impl fmt::Display for Rotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", u16::from(*self))
+    }
 }
 
 impl fmt::Display for Image {
@@ -43,9 +252,90 @@ impl Default for Image {
     }
 }
 
-pub fn decode_jpeg(name: Option<String>, jpeg: Bytes, image: &mut Image) -> anyhow::Result<()> {
+/// The format of a JPEG, PNG, BMP or TIFF image from its leading magic bytes,
+/// None for anything else
+pub fn sniff_image_format(data: &[u8]) -> Option<image::ImageFormat> {
+    match data {
+        [0xFF, 0xD8, ..] => Some(image::ImageFormat::Jpeg),
+        [0x89, b'P', b'N', b'G', ..] => Some(image::ImageFormat::Png),
+        [b'B', b'M', ..] => Some(image::ImageFormat::Bmp),
+        [b'I', b'I', 0x2A, 0x00, ..] | [b'M', b'M', 0x00, 0x2A, ..] => {
+            Some(image::ImageFormat::Tiff)
+        }
+        _ => None,
+    }
+}
+
+/// Error context when an image is in an unsupported format or could not be
+/// decoded, the sender is at fault. Callers can tell it apart from other
+/// errors with `error.downcast_ref::<InvalidImage>()`.
+#[derive(Debug)]
+pub struct InvalidImage;
+
+impl fmt::Display for InvalidImage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid image")
+    }
+}
+
+/// Decode a JPEG, PNG, BMP or TIFF image into RGB pixels, the format is detected
+/// from the leading magic bytes. With lenient set JPEGs that fail the strict
+/// decode are decoded best effort.
+pub fn decode_image(
+    name: Option<String>,
+    data: Bytes,
+    image: &mut Image,
+    lenient: bool,
+) -> anyhow::Result<()> {
+    decode_image_data(name, data, image, lenient).context(InvalidImage)
+}
+
+fn decode_image_data(
+    name: Option<String>,
+    data: Bytes,
+    image: &mut Image,
+    lenient: bool,
+) -> anyhow::Result<()> {
+    let format = match sniff_image_format(&data) {
+        Some(image::ImageFormat::Jpeg) => return decode_jpeg(name, data, image, lenient),
+        Some(format) => format,
+        None => bail!("Unsupported image format, only JPEG, PNG, BMP and TIFF are supported"),
+    };
+    let rgb_image = image::load_from_memory_with_format(&data, format)?.into_rgb8();
+    image.width = rgb_image.width() as usize;
+    image.height = rgb_image.height() as usize;
+    image.pixels.clear();
+    image.pixels.extend_from_slice(rgb_image.as_raw());
+    image.name = name;
+    Ok(())
+}
+
+pub fn decode_jpeg(
+    name: Option<String>,
+    jpeg: Bytes,
+    image: &mut Image,
+    lenient: bool,
+) -> anyhow::Result<()> {
+    match decode_jpeg_pixels(&jpeg, image, true) {
+        Ok(()) => {}
+        Err(err) if lenient => {
+            // Slightly malformed JPEGs from cheap cameras fail the strict decode
+            warn!(
+                %err,
+                image_name = name.as_deref().unwrap_or_default(),
+                "Strict JPEG decode failed, decoding leniently, image quality may be affected"
+            );
+            decode_jpeg_pixels(&jpeg, image, false)?;
+        }
+        Err(err) => return Err(err),
+    }
+    image.name = name;
+    Ok(())
+}
+
+fn decode_jpeg_pixels(jpeg: &Bytes, image: &mut Image, strict: bool) -> anyhow::Result<()> {
     let options = DecoderOptions::default()
-        .set_strict_mode(true)
+        .set_strict_mode(strict)
         .set_use_unsafe(true)
         .jpeg_set_out_colorspace(ColorSpace::RGB);
     let cursor = ZCursor::new(jpeg.to_vec());
@@ -64,7 +354,6 @@ pub fn decode_jpeg(name: Option<String>, jpeg: Bytes, image: &mut Image) -> anyh
         .ok_or_else(|| anyhow::anyhow!("Failed to get image dimensions"))?;
     image.width = width;
     image.height = height;
-    image.name = name;
     Ok(())
 }
 
@@ -72,36 +361,94 @@ pub fn load_image(jpeg_file: &Path) -> anyhow::Result<Bytes> {
     let path_str = jpeg_file
         .to_str()
         .ok_or_else(|| anyhow::anyhow!("Failed to get image path"))?;
-    if !is_jpeg(path_str) {
-        bail!("Image is not a JPEG file")
+    if !is_supported_image(path_str) {
+        bail!("Image is not a JPEG, PNG, BMP or TIFF file")
     }
     Ok(Bytes::from(std::fs::read(jpeg_file)?))
 }
 
-pub fn encode_maybe_draw_boundary_boxes_and_save_jpeg(
+/// Format processed images are saved and annotated images are returned in
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    clap::ValueEnum,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+pub enum OutputImageFormat {
+    #[default]
+    Jpeg,
+    /// Lossless, larger files
+    Png,
+}
+
+impl OutputImageFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputImageFormat::Jpeg => "jpg",
+            OutputImageFormat::Png => "png",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            OutputImageFormat::Jpeg => "image/jpeg",
+            OutputImageFormat::Png => "image/png",
+        }
+    }
+
+    /// True if the file name has an extension of this format
+    pub fn matches_extension(self, image_name: &str) -> bool {
+        match self {
+            OutputImageFormat::Jpeg => is_jpeg(image_name),
+            OutputImageFormat::Png => image_name.to_lowercase().ends_with(".png"),
+        }
+    }
+
+    /// Encodes the image, JPEGs at quality 100
+    pub fn encode(self, image: &DynamicImage) -> anyhow::Result<Vec<u8>> {
+        let mut encoded_image = Vec::new();
+        match self {
+            OutputImageFormat::Jpeg => {
+                let image = to_jpeg_rgb8(image);
+                let encoder = Encoder::new(&mut encoded_image, 100);
+                encoder.encode(
+                    &image,
+                    image.width() as u16,
+                    image.height() as u16,
+                    ColorType::Rgb,
+                )?;
+            }
+            OutputImageFormat::Png => image.write_to(
+                &mut std::io::Cursor::new(&mut encoded_image),
+                image::ImageFormat::Png,
+            )?,
+        }
+        Ok(encoded_image)
+    }
+}
+
+pub fn encode_maybe_draw_boundary_boxes_and_save_image(
     image: &Image,
-    jpeg_file: &String,
+    image_file: &String,
     predictions: Option<&[Prediction]>,
     base_width: u32,
     base_height: u32,
+    format: OutputImageFormat,
 ) -> anyhow::Result<()> {
     let encode_image_start_time = Instant::now();
 
     let image =
         create_dynamic_image_maybe_with_boundary_box(predictions, image, base_width, base_height)?;
 
-    let encoder = Encoder::new_file(jpeg_file, 100)?;
-    encoder.encode(
-        image
-            .as_rgb8()
-            .ok_or_else(|| anyhow::anyhow!("Failed to convert image to RGB8"))?,
-        image.width() as u16,
-        image.height() as u16,
-        ColorType::Rgb,
-    )?;
+    std::fs::write(image_file, format.encode(&image)?)?;
     let encode_image_time = Instant::now().duration_since(encode_image_start_time);
     debug!(?encode_image_time, "Encode image time");
-    info!(?jpeg_file, "Image saved");
+    info!(?image_file, "Image saved");
     Ok(())
 }
 
@@ -109,13 +456,26 @@ pub fn is_jpeg(image_name: &str) -> bool {
     image_name.to_lowercase().ends_with(".jpg") || image_name.to_lowercase().ends_with(".jpeg")
 }
 
+/// True for image names with an extension decode_image can handle
+pub fn is_supported_image(image_name: &str) -> bool {
+    let image_name = image_name.to_lowercase();
+    is_jpeg(&image_name)
+        || [".png", ".bmp", ".tif", ".tiff"]
+            .iter()
+            .any(|ext| image_name.ends_with(ext))
+}
+
 pub fn create_random_jpeg_name() -> String {
     format!("image_{}.jpg", uuid::Uuid::new_v4())
 }
 
-pub fn create_od_image_name(image_name: &str, strip_path: bool) -> anyhow::Result<String> {
-    if !is_jpeg(image_name) {
-        bail!("Image is not a JPEG file");
+pub fn create_od_image_name(
+    image_name: &str,
+    strip_path: bool,
+    format: OutputImageFormat,
+) -> anyhow::Result<String> {
+    if !is_supported_image(image_name) {
+        bail!("Image is not a JPEG, PNG, BMP or TIFF file");
     }
 
     let image_name = if strip_path {
@@ -145,10 +505,87 @@ pub fn create_od_image_name(image_name: &str, strip_path: bool) -> anyhow::Resul
     };
 
     od_image_name.push_str("_od.");
-    od_image_name.push_str(&ext);
+    // Keep the extension when the image already is in the output format
+    if format.matches_extension(&image_name) {
+        od_image_name.push_str(&ext);
+    } else {
+        od_image_name.push_str(format.extension());
+    }
     Ok(od_image_name)
 }
 
+/// Name the reference image of a detection is saved under, the file name
+/// prefixed with the time so images sent with the same name don't overwrite
+/// each other. The processed image is saved under the same name with an `_od`
+/// suffix so both are easy to pair up.
+pub fn create_saved_image_name(
+    image_name: &str,
+    now: chrono::DateTime<chrono::Utc>,
+) -> anyhow::Result<String> {
+    let file_name = Path::new(image_name)
+        .file_name()
+        .and_then(|s| s.to_str())
+        .ok_or_else(|| anyhow::anyhow!("Failed to get file name from {image_name}"))?;
+    Ok(format!("{}_{file_name}", now.format("%Y%m%d_%H%M%S_%3f")))
+}
+
+/// Render a save image path template relative to save_image_path.
+///
+/// Supported tokens are `{date}` (YYYY-MM-DD), `{time}` (HHMMSS), `{label}`
+/// (label of the first detection or "none"), `{camera}` (camera name, falls
+/// back to the image name up to the first dot) and `{name}` (image file stem).
+pub fn render_save_image_template(
+    template: &str,
+    image_name: &str,
+    camera: Option<&str>,
+    label: Option<&str>,
+    now: chrono::DateTime<chrono::Utc>,
+) -> anyhow::Result<PathBuf> {
+    let name = Path::new(image_name)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .ok_or_else(|| anyhow::anyhow!("Failed to get file name from {image_name}"))?;
+    let camera = camera
+        .filter(|camera| !camera.is_empty())
+        .unwrap_or_else(|| name.split('.').next().unwrap_or(name));
+
+    let rendered = template
+        .replace("{date}", &now.format("%Y-%m-%d").to_string())
+        .replace("{time}", &now.format("%H%M%S").to_string())
+        .replace("{label}", &sanitize_path_component(label.unwrap_or("none")))
+        .replace("{camera}", &sanitize_path_component(camera))
+        .replace("{name}", &sanitize_path_component(name));
+
+    let path = PathBuf::from(rendered);
+    if path.is_absolute()
+        || path
+            .components()
+            .any(|c| matches!(c, std::path::Component::ParentDir))
+    {
+        bail!("Save image template must render to a relative path inside save_image_path");
+    }
+    Ok(path)
+}
+
+/// Replace characters that are not safe in a single path component
+fn sanitize_path_component(value: &str) -> String {
+    let sanitized: String = value
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | ' ') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if sanitized.chars().all(|c| c == '.') {
+        "_".to_string()
+    } else {
+        sanitized
+    }
+}
+
 pub fn create_dynamic_image_maybe_with_boundary_box(
     predictions: Option<&[Prediction]>,
     decoded_image: &Image,
@@ -236,22 +673,118 @@ fn boundary_box_config(width: u32, height: u32, base_width: u32, base_height: u3
     (thickness.max(1), fontsize.max(12))
 }
 
+/// Filter used when resizing images to the model input size.
+/// Nearest is the fastest, Lanczos3 gives the best quality.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    clap::ValueEnum,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+pub enum ResizeFilter {
+    Nearest,
+    Bilinear,
+    #[default]
+    Lanczos3,
+}
+
+impl From<ResizeFilter> for fast_image_resize::ResizeAlg {
+    fn from(value: ResizeFilter) -> Self {
+        use fast_image_resize::{FilterType, ResizeAlg};
+        match value {
+            ResizeFilter::Nearest => ResizeAlg::Nearest,
+            ResizeFilter::Bilinear => ResizeAlg::Convolution(FilterType::Bilinear),
+            ResizeFilter::Lanczos3 => ResizeAlg::Convolution(FilterType::Lanczos3),
+        }
+    }
+}
+
+/// Region of the source image that is resized to the model input size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CropRegion {
+    pub left: usize,
+    pub top: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl CropRegion {
+    pub fn full(width: usize, height: usize) -> Self {
+        Self {
+            left: 0,
+            top: 0,
+            width,
+            height,
+        }
+    }
+
+    /// The largest region centered in the source image with the aspect ratio
+    /// of the target, a square for square model inputs
+    pub fn centered(
+        width: usize,
+        height: usize,
+        target_width: usize,
+        target_height: usize,
+    ) -> Self {
+        if width * target_height > height * target_width {
+            let crop_width = height * target_width / target_height;
+            Self {
+                left: (width - crop_width) / 2,
+                top: 0,
+                width: crop_width,
+                height,
+            }
+        } else {
+            let crop_height = width * target_height / target_width;
+            Self {
+                left: 0,
+                top: (height - crop_height) / 2,
+                width,
+                height: crop_height,
+            }
+        }
+    }
+}
+
 pub struct Resizer {
     resizer: fast_image_resize::Resizer,
+    resize_filter: ResizeFilter,
     target_width: usize,
     target_height: usize,
+    center_crop: bool,
 }
 
 impl Resizer {
-    pub fn new(target_width: usize, target_height: usize) -> anyhow::Result<Self> {
+    pub fn new(
+        target_width: usize,
+        target_height: usize,
+        resize_filter: ResizeFilter,
+        center_crop: bool,
+    ) -> anyhow::Result<Self> {
         let resizer = fast_image_resize::Resizer::new();
         Ok(Self {
             resizer,
+            resize_filter,
             target_width,
             target_height,
+            center_crop,
         })
     }
 
+    /// The region of an image with the given size that resize_image uses
+    pub fn crop_region(&self, width: usize, height: usize) -> CropRegion {
+        if self.center_crop {
+            CropRegion::centered(width, height, self.target_width, self.target_height)
+        } else {
+            CropRegion::full(width, height)
+        }
+    }
+
     pub fn resize_image(
         &mut self,
         original_image: &mut Image,
@@ -285,7 +818,17 @@ impl Resizer {
             fast_image_resize::PixelType::U8x3,
         )?;
 
-        self.resizer.resize(&src_image, &mut dst_image, None)?;
+        let crop = self.crop_region(original_image.width, original_image.height);
+        let resize_options = fast_image_resize::ResizeOptions::new()
+            .resize_alg(self.resize_filter.into())
+            .crop(
+                crop.left as f64,
+                crop.top as f64,
+                crop.width as f64,
+                crop.height as f64,
+            );
+        self.resizer
+            .resize(&src_image, &mut dst_image, &resize_options)?;
 
         Ok(())
     }
@@ -296,24 +839,186 @@ pub fn draw_boundary_boxes_on_encoded_image(
     predictions: &[Prediction],
     base_width: u32,
     base_height: u32,
+    format: OutputImageFormat,
 ) -> anyhow::Result<Bytes> {
     let mut image = Image::default();
-    decode_jpeg(None, data, &mut image)?;
+    decode_image(None, data, &mut image, false)?;
     let dynamic_image_with_boundary_box = create_dynamic_image_maybe_with_boundary_box(
         Some(predictions),
         &image,
         base_width,
         base_height,
     )?;
-    let mut encoded_image = Vec::new();
-    let encoder = Encoder::new(&mut encoded_image, 100);
-    encoder.encode(
-        dynamic_image_with_boundary_box
-            .as_rgb8()
-            .ok_or_else(|| anyhow::anyhow!("Failed to convert image to RGB8"))?,
-        dynamic_image_with_boundary_box.width() as u16,
-        dynamic_image_with_boundary_box.height() as u16,
-        ColorType::Rgb,
-    )?;
-    Ok(Bytes::from(encoded_image))
+    Ok(Bytes::from(
+        format.encode(&dynamic_image_with_boundary_box)?,
+    ))
+}
+
+/// The image as RGB8 for the JPEG encoder, other formats are converted and
+/// transparency is flattened onto white since JPEG has no alpha channel
+fn to_jpeg_rgb8(image: &DynamicImage) -> Cow<'_, RgbImage> {
+    if let Some(rgb_image) = image.as_rgb8() {
+        return Cow::Borrowed(rgb_image);
+    }
+    if !image.color().has_alpha() {
+        return Cow::Owned(image.to_rgb8());
+    }
+    let rgba_image = image.to_rgba8();
+    let mut rgb_image = RgbImage::new(rgba_image.width(), rgba_image.height());
+    for (rgb, rgba) in rgb_image.pixels_mut().zip(rgba_image.pixels()) {
+        let alpha = rgba[3] as u32;
+        *rgb = image::Rgb(std::array::from_fn(|channel| {
+            ((rgba[channel] as u32 * alpha + 255 * (255 - alpha) + 127) / 255) as u8
+        }));
+    }
+    Cow::Owned(rgb_image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_image_rejects_invalid_images() {
+        let mut image = Image::default();
+        for data in [&b"not an image"[..], &[0xFF, 0xD8, 0xFF, 0xE0][..]] {
+            let err =
+                decode_image(None, Bytes::copy_from_slice(data), &mut image, false).unwrap_err();
+            assert!(err.downcast_ref::<InvalidImage>().is_some());
+        }
+    }
+
+    #[test]
+    fn test_to_jpeg_rgb8_flattens_alpha_onto_white() {
+        let rgba =
+            image::RgbaImage::from_raw(3, 1, vec![10, 20, 30, 255, 10, 20, 30, 0, 0, 0, 0, 128])
+                .unwrap();
+        let rgba_image = DynamicImage::ImageRgba8(rgba);
+        let rgb = to_jpeg_rgb8(&rgba_image);
+        assert_eq!(
+            rgb.as_raw(),
+            &vec![10, 20, 30, 255, 255, 255, 127, 127, 127]
+        );
+
+        let rgb_image = RgbImage::from_raw(1, 1, vec![1, 2, 3]).unwrap();
+        let dynamic_image = DynamicImage::ImageRgb8(rgb_image);
+        assert!(matches!(to_jpeg_rgb8(&dynamic_image), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_saved_image_names_pair_up() {
+        let now = chrono::DateTime::parse_from_rfc3339("2025-01-02T03:04:05.678Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let saved_image_name = create_saved_image_name("uploads/image.jpg", now).unwrap();
+        assert_eq!(saved_image_name, "20250102_030405_678_image.jpg");
+        assert_eq!(
+            create_od_image_name(&saved_image_name, false, OutputImageFormat::Jpeg).unwrap(),
+            "20250102_030405_678_image_od.jpg"
+        );
+        assert_eq!(
+            create_od_image_name(&saved_image_name, false, OutputImageFormat::Png).unwrap(),
+            "20250102_030405_678_image_od.png"
+        );
+        assert_eq!(
+            create_od_image_name("camera.png", false, OutputImageFormat::Jpeg).unwrap(),
+            "camera_od.jpg"
+        );
+    }
+
+    #[test]
+    fn test_centered_crop_region() {
+        assert_eq!(
+            CropRegion::centered(1920, 1080, 640, 640),
+            CropRegion {
+                left: 420,
+                top: 0,
+                width: 1080,
+                height: 1080,
+            }
+        );
+        assert_eq!(
+            CropRegion::centered(480, 640, 640, 640),
+            CropRegion {
+                left: 0,
+                top: 80,
+                width: 480,
+                height: 480,
+            }
+        );
+        assert_eq!(
+            CropRegion::centered(640, 640, 640, 640),
+            CropRegion::full(640, 640)
+        );
+    }
+
+    #[test]
+    fn test_rotate_image_and_unrotate_prediction() {
+        // 3x2 image where every pixel is its index
+        let mut image = Image {
+            name: None,
+            width: 3,
+            height: 2,
+            pixels: (0..6u8).flat_map(|i| [i, i, i]).collect(),
+        };
+        let mut buffer = Vec::new();
+        image.rotate(Rotation::Rotate90, &mut buffer);
+        assert_eq!((image.width, image.height), (2, 3));
+        let first_channel: Vec<u8> = image.pixels.chunks_exact(3).map(|p| p[0]).collect();
+        assert_eq!(first_channel, vec![3, 0, 4, 1, 5, 2]);
+
+        // The top left pixel of the rotated image in a 4x2 original image
+        for (rotation, expected) in [
+            (Rotation::None, (0, 1, 0, 1)),
+            (Rotation::Rotate90, (0, 1, 1, 2)),
+            (Rotation::Rotate180, (3, 4, 1, 2)),
+            (Rotation::Rotate270, (3, 4, 0, 1)),
+        ] {
+            let mut prediction = Prediction {
+                x_min: 0,
+                x_max: 1,
+                y_min: 0,
+                y_max: 1,
+                confidence: 1.0,
+                label: String::new(),
+                ..Default::default()
+            };
+            rotation.unrotate_prediction(&mut prediction, 4, 2);
+            assert_eq!(
+                (
+                    prediction.x_min,
+                    prediction.x_max,
+                    prediction.y_min,
+                    prediction.y_max
+                ),
+                expected,
+                "{rotation}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_motion_thumbnail_difference() {
+        let image = |width: usize, height: usize, pixel: [u8; 3]| Image {
+            name: None,
+            width,
+            height,
+            pixels: pixel.repeat(width * height),
+        };
+        let mut gray = Vec::new();
+        image(640, 360, [100, 100, 100]).motion_thumbnail(&mut gray);
+        assert_eq!(gray.len(), MOTION_THUMBNAIL_SIZE * MOTION_THUMBNAIL_SIZE);
+        assert!(gray.iter().all(|&luma| luma == 100));
+
+        let mut brighter = Vec::new();
+        image(640, 360, [110, 110, 110]).motion_thumbnail(&mut brighter);
+        assert_eq!(mean_absolute_difference(&gray, &gray), Some(0.0));
+        assert_eq!(mean_absolute_difference(&gray, &brighter), Some(10.0));
+
+        // Images smaller than the sample grid still sample inside the image
+        let mut small = Vec::new();
+        image(3, 2, [0, 0, 0]).motion_thumbnail(&mut small);
+        assert_eq!(small.len(), gray.len());
+        assert_eq!(mean_absolute_difference(&gray, &[]), None);
+    }
 }
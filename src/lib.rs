@@ -1,13 +1,17 @@
+use anyhow::Context;
 use clap::ValueEnum;
 use cli::Cli;
-use detector::OnnxConfig;
 use serde::Deserialize;
 use server::run_server;
 use startup_coordinator::spawn_detector_initialization;
-use std::{future::Future, path::PathBuf};
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+};
 use tokio_util::sync::CancellationToken;
-use tracing::{Level, info};
+use tracing::{Level, error, info};
 pub mod api;
+pub mod benchmark;
 pub mod cli;
 pub mod detector;
 pub mod download_models;
@@ -15,6 +19,8 @@ pub mod image;
 pub mod server;
 pub mod startup_coordinator;
 pub mod system_info;
+#[cfg(feature = "video")]
+pub mod video;
 pub mod worker;
 
 pub static DOG_BIKE_CAR_BYTES: &[u8] = include_bytes!("../assets/dog_bike_car.jpg");
@@ -27,8 +33,57 @@ struct CocoClasses {
     NAMES: Vec<String>,
 }
 
-/// Type alias for the service result containing restart flag and optional worker thread handle
-pub type ServiceResult = anyhow::Result<(bool, Option<std::thread::JoinHandle<()>>)>;
+/// Model and YAML embedded at build time with the embed-model feature, from
+/// the files in the BLUE_ONYX_EMBED_MODEL and BLUE_ONYX_EMBED_MODEL_YAML
+/// environment variables (see build.rs)
+#[cfg(feature = "embed-model")]
+pub mod embedded_model {
+    pub static MODEL_FILE_NAME: &str = env!("BLUE_ONYX_EMBEDDED_MODEL_FILE_NAME");
+    pub static MODEL_BYTES: &[u8] = include_bytes!(env!("BLUE_ONYX_EMBEDDED_MODEL"));
+    pub static MODEL_YAML_STR: &str = include_str!(env!("BLUE_ONYX_EMBEDDED_MODEL_YAML"));
+
+    pub fn object_classes() -> anyhow::Result<Vec<String>> {
+        Ok(serde_yaml::from_str::<super::CocoClasses>(MODEL_YAML_STR)?.NAMES)
+    }
+}
+
+/// True if the model file name selects the model embedded with the
+/// embed-model feature, no configured model also uses the embedded model
+pub fn is_embedded_model(model_file_name: Option<&str>) -> bool {
+    #[cfg(feature = "embed-model")]
+    {
+        model_file_name.is_none_or(|name| name == embedded_model::MODEL_FILE_NAME)
+    }
+    #[cfg(not(feature = "embed-model"))]
+    {
+        let _ = model_file_name;
+        false
+    }
+}
+
+/// Type alias for the service result containing restart flag and the worker thread handles
+pub type ServiceResult = anyhow::Result<(bool, Vec<std::thread::JoinHandle<()>>)>;
+
+/// Waits for the worker threads of a finished service, returns true if a
+/// restart was requested
+pub fn service_finished(result: ServiceResult) -> bool {
+    match result {
+        Ok((restart_requested, worker_handles)) => {
+            // Wait for worker threads to complete if available
+            for handle in worker_handles {
+                info!("Waiting for worker thread to complete...");
+                if let Err(e) = handle.join() {
+                    error!("Worker thread panicked: {:?}", e);
+                }
+            }
+            restart_requested
+        }
+        Err(e) => {
+            error!("Service failed: {}", e);
+            false // Don't restart on error
+        }
+    }
+}
 
 pub fn blue_onyx_service(
     args: Cli,
@@ -40,21 +95,15 @@ pub fn blue_onyx_service(
     // Get the config path for the server
     let config_path = args.get_current_config_path()?;
 
-    let detector_config = detector::DetectorConfig {
-        object_detection_onnx_config: OnnxConfig {
-            force_cpu: args.force_cpu,
-            gpu_index: args.gpu_index,
-            intra_threads: args.intra_threads,
-            inter_threads: args.inter_threads,
-            model: args.model,
-        },
-        object_classes: args.object_classes,
-        object_filter: args.object_filter,
-        confidence_threshold: args.confidence_threshold,
-        save_image_path: args.save_image_path,
-        save_ref_image: args.save_ref_image,
-        timeout: args.request_timeout,
-        object_detection_model: args.object_detection_model_type,
+    let detector_config = args.detector_config();
+    let watched_model = if args.watch_model {
+        let watched_model = watched_model_path(&args)?;
+        if watched_model.is_none() {
+            tracing::warn!("The embedded model has no file to watch, ignoring watch_model");
+        }
+        watched_model
+    } else {
+        None
     };
 
     // Log available GPU information
@@ -62,17 +111,54 @@ pub fn blue_onyx_service(
 
     // Start the detector initialization in the background
     let detector_init_receiver =
-        spawn_detector_initialization(detector_config, args.worker_queue_size); // Create placeholder metrics (will be updated when detector is ready)
+        spawn_detector_initialization(detector_config, args.worker_queue_size, args.workers);
+    let named_detector_init_receivers = args
+        .named_detector_configs()
+        .into_iter()
+        .map(|(name, detector_config)| {
+            let receiver = spawn_detector_initialization(
+                detector_config,
+                args.worker_queue_size,
+                args.workers,
+            );
+            (name, receiver)
+        })
+        .collect();
+    // Create placeholder metrics (will be updated when detector is ready)
     let metrics =
         server::Metrics::new("Initializing...".to_string(), "Initializing...".to_string());
 
     let cancel_token = CancellationToken::new();
     let restart_token = CancellationToken::new();
+    let server_options = server::ServerOptions {
+        port: args.port,
+        bind_address: args.bind_address,
+        heartbeat_interval: (args.heartbeat_secs > 0)
+            .then(|| std::time::Duration::from_secs(args.heartbeat_secs)),
+        compression: args.compression,
+        max_connections: (args.max_connections > 0).then_some(args.max_connections),
+        keep_alive_timeout: (args.keep_alive_secs > 0)
+            .then(|| std::time::Duration::from_secs(args.keep_alive_secs)),
+        confidence_scale: args.confidence_scale,
+        module_id: args.module_id.clone(),
+        module_name: args.module_name.clone(),
+        restart_on_worker_failure: args.restart_on_worker_failure,
+        models_dir: args.models_dir.clone(),
+        watched_model,
+        output_image_format: args.output_image_format,
+        metrics_push: args.metrics_push_url.clone().map(|url| {
+            (
+                url,
+                std::time::Duration::from_secs(args.metrics_push_interval.max(1)),
+            )
+        }),
+    };
     let server_future = run_server(
-        args.port,
+        server_options,
         cancel_token.clone(),
         restart_token.clone(),
         detector_init_receiver,
+        named_detector_init_receivers,
         metrics,
         config_path,
     );
@@ -81,11 +167,54 @@ pub fn blue_onyx_service(
 }
 
 pub fn get_object_classes(yaml_file: Option<PathBuf>) -> anyhow::Result<Vec<String>> {
-    let yaml_data = match yaml_file {
+    let yaml_data = match &yaml_file {
         Some(yaml_file) => std::fs::read_to_string(yaml_file)?,
         None => COCO_CLASSES_STR.to_string(),
     };
-    Ok(serde_yaml::from_str::<CocoClasses>(&yaml_data)?.NAMES)
+    // Plain .txt class files have one class per line, everything else is YAML
+    let is_txt = yaml_file.as_ref().is_some_and(|yaml_file| {
+        yaml_file
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("txt"))
+    });
+    let object_classes = if is_txt {
+        yaml_data
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect()
+    } else {
+        serde_yaml::from_str::<CocoClasses>(&yaml_data)?.NAMES
+    };
+    // Every detection indexes the class list, so an empty list can't work
+    if object_classes.is_empty() {
+        let yaml_name = yaml_file
+            .map(|yaml_file| yaml_file.display().to_string())
+            .unwrap_or_else(|| "built-in COCO classes".to_string());
+        if is_txt {
+            anyhow::bail!(
+                "The object classes file {yaml_name} has no classes, add one class name per line"
+            );
+        }
+        anyhow::bail!(
+            "The object classes YAML {yaml_name} has no classes, add the class names to its NAMES list"
+        );
+    }
+    Ok(object_classes)
+}
+
+/// Concatenates the classes of several class files in order, for models that
+/// combine detection heads with different label sets
+pub fn get_merged_object_classes(yaml_files: &[PathBuf]) -> anyhow::Result<Vec<String>> {
+    let mut object_classes = Vec::new();
+    for yaml_file in yaml_files {
+        let classes = get_object_classes(Some(yaml_file.clone())).with_context(|| {
+            format!("Failed to read object classes from {}", yaml_file.display())
+        })?;
+        object_classes.extend(classes);
+    }
+    Ok(object_classes)
 }
 
 pub fn direct_ml_available() -> bool {
@@ -143,6 +272,7 @@ static SERVICE_LOG_RELOAD_HANDLE: OnceLock<
 
 pub fn init_logging(
     log_level: LogLevel,
+    log_format: LogFormat,
     log_path: &mut Option<PathBuf>,
 ) -> anyhow::Result<Option<tracing_appender::non_blocking::WorkerGuard>> {
     use tracing_subscriber::layer::SubscriberExt;
@@ -181,9 +311,7 @@ pub fn init_logging(
         let file_appender = tracing_appender::rolling::daily(&log_directory, "blue_onyx.log");
         let (non_blocking, guard) = tracing_appender::non_blocking(file_appender);
 
-        let file_layer = tracing_subscriber::fmt::layer()
-            .with_writer(non_blocking)
-            .with_ansi(false);
+        let file_layer = fmt_layer(log_format, non_blocking, false);
 
         tracing_subscriber::registry()
             .with(env_filter)
@@ -195,7 +323,7 @@ pub fn init_logging(
     } else {
         tracing_subscriber::registry()
             .with(env_filter)
-            .with(tracing_subscriber::fmt::layer())
+            .with(fmt_layer(log_format, std::io::stdout, true))
             .try_init()
             .map_err(|_| anyhow::anyhow!("Logging already initialized"))?;
 
@@ -204,11 +332,37 @@ pub fn init_logging(
 
     info!(
         ?log_level,
+        ?log_format,
         "Logging initialized with dynamic filtering support"
     );
     Ok(guard)
 }
 
+/// Create the formatting layer for the given log format and writer
+fn fmt_layer<S, W>(
+    log_format: LogFormat,
+    writer: W,
+    ansi: bool,
+) -> Box<dyn tracing_subscriber::Layer<S> + Send + Sync>
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+    W: for<'w> tracing_subscriber::fmt::MakeWriter<'w> + Send + Sync + 'static,
+{
+    use tracing_subscriber::Layer;
+
+    match log_format {
+        LogFormat::Text => tracing_subscriber::fmt::layer()
+            .with_writer(writer)
+            .with_ansi(ansi)
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_writer(writer)
+            .with_ansi(false)
+            .boxed(),
+    }
+}
+
 pub fn update_log_level(new_log_level: LogLevel) -> anyhow::Result<()> {
     use tracing_subscriber::EnvFilter;
 
@@ -315,6 +469,17 @@ pub enum LogLevel {
     Error,
 }
 
+#[derive(
+    Copy, Clone, Default, PartialEq, Eq, ValueEnum, Debug, serde::Serialize, serde::Deserialize,
+)]
+pub enum LogFormat {
+    /// Human readable text output
+    #[default]
+    Text,
+    /// One JSON object per line, for log ingestion such as Loki or ELK
+    Json,
+}
+
 impl From<LogLevel> for Level {
     fn from(value: LogLevel) -> Self {
         match value {
@@ -327,17 +492,100 @@ impl From<LogLevel> for Level {
     }
 }
 
+/// Error context when a model or its YAML file is missing and could not be
+/// downloaded, e.g. when offline. Callers can tell it apart from other errors
+/// with `error.downcast_ref::<ModelUnavailable>()`.
+#[derive(Debug)]
+pub struct ModelUnavailable {
+    pub file_name: String,
+}
+
+impl std::fmt::Display for ModelUnavailable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} is required but could not be found or downloaded",
+            self.file_name
+        )
+    }
+}
+
+/// Model file of the default detector that is watched for changes, None for
+/// the embedded model that has no file
+fn watched_model_path(args: &Cli) -> anyhow::Result<Option<PathBuf>> {
+    let model_file_name = args
+        .model
+        .as_deref()
+        .and_then(Path::file_name)
+        .and_then(|name| name.to_str());
+    if is_embedded_model(model_file_name) {
+        return Ok(None);
+    }
+    Ok(Some(resolve_models_dir(args.models_dir.as_deref())?.join(
+        model_file_name.unwrap_or(NANO_RF_DETR_MODEL_FILE_NAME),
+    )))
+}
+
+/// Directory models are stored in, models_dir or the directory of the
+/// executable if not set
+pub fn resolve_models_dir(models_dir: Option<&Path>) -> anyhow::Result<PathBuf> {
+    match models_dir {
+        Some(models_dir) => Ok(models_dir.to_path_buf()),
+        None => {
+            // Default to the directory of the executable
+            let exe_path = std::env::current_exe()?;
+            Ok(exe_path
+                .parent()
+                .ok_or_else(|| anyhow::anyhow!("Failed to get parent directory of executable"))?
+                .to_path_buf())
+        }
+    }
+}
+
+/// Names of the .onnx models in the models directory without the extension,
+/// sorted. A models directory that does not exist yet has no models.
+pub fn list_local_models(models_dir: Option<&Path>) -> anyhow::Result<Vec<String>> {
+    let models_dir = resolve_models_dir(models_dir)?;
+    if !models_dir.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut models = std::fs::read_dir(&models_dir)
+        .with_context(|| format!("Failed to read models directory {}", models_dir.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.is_file()
+                && path
+                    .extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("onnx"))
+        })
+        .filter_map(|path| {
+            path.file_stem()
+                .and_then(|stem| stem.to_str())
+                .map(str::to_string)
+        })
+        .collect::<Vec<_>>();
+    models.sort();
+    Ok(models)
+}
+
 /// Ensures model and yaml files exist, downloading them if needed
 /// Returns the paths to the model and yaml files
-pub fn ensure_model_files(model_name: Option<String>) -> anyhow::Result<(PathBuf, PathBuf)> {
+/// Models are stored in models_dir, or next to the executable if not set
+pub fn ensure_model_files(
+    model_name: Option<String>,
+    models_dir: Option<&Path>,
+    classes_yaml: Option<&Path>,
+) -> anyhow::Result<(PathBuf, PathBuf)> {
     // Use default model if none provided
     let model_filename = model_name.unwrap_or_else(|| NANO_RF_DETR_MODEL_FILE_NAME.to_string());
 
-    // Get the directory where models are stored (next to the executable)
-    let exe_path = std::env::current_exe()?;
-    let models_dir = exe_path
-        .parent()
-        .ok_or_else(|| anyhow::anyhow!("Failed to get parent directory of executable"))?;
+    if let Some(models_dir) = models_dir {
+        std::fs::create_dir_all(models_dir).with_context(|| {
+            format!("Failed to create models directory {}", models_dir.display())
+        })?;
+    }
+    let models_dir = resolve_models_dir(models_dir)?;
+    let models_dir = models_dir.as_path();
 
     let model_path = models_dir.join(&model_filename);
     let yaml_filename = model_filename.replace(".onnx", ".yaml");
@@ -349,15 +597,35 @@ pub fn ensure_model_files(model_name: Option<String>) -> anyhow::Result<(PathBuf
             .build()?;
         rt.block_on(async {
             download_models::download_file_to_dir(&model_filename, models_dir).await
+        })
+        .with_context(|| ModelUnavailable {
+            file_name: model_filename.clone(),
         })?;
     }
 
     // Verify model file exists after download
     if !model_path.exists() {
-        return Err(anyhow::anyhow!(
-            "Model file {} is required but could not be found or downloaded",
-            model_filename
-        ));
+        anyhow::bail!(ModelUnavailable {
+            file_name: model_filename,
+        });
+    }
+
+    // A YAML mapped to the model is used as is, it is not downloaded
+    if let Some(classes_yaml) = classes_yaml {
+        let yaml_path = models_dir.join(classes_yaml);
+        if !yaml_path.exists() {
+            anyhow::bail!(
+                "Class YAML {} for model {} does not exist",
+                yaml_path.display(),
+                model_filename
+            );
+        }
+        info!(
+            "Model and YAML files ready: {} and {}",
+            model_path.display(),
+            yaml_path.display()
+        );
+        return Ok((model_path, yaml_path));
     }
 
     // Check if yaml exists, download if not (MANDATORY)
@@ -368,15 +636,17 @@ pub fn ensure_model_files(model_name: Option<String>) -> anyhow::Result<(PathBuf
             .build()?;
         rt.block_on(async {
             download_models::download_file_to_dir(&yaml_filename, models_dir).await
+        })
+        .with_context(|| ModelUnavailable {
+            file_name: yaml_filename.clone(),
         })?;
     }
 
     // Verify yaml file exists after download
     if !yaml_path.exists() {
-        return Err(anyhow::anyhow!(
-            "YAML file {} is required but could not be found or downloaded",
-            yaml_filename
-        ));
+        anyhow::bail!(ModelUnavailable {
+            file_name: yaml_filename,
+        });
     }
 
     info!(
@@ -1,139 +1,270 @@
 use crate::{
     api::{
+        ConfidenceScale, DetectionEvent, MAX_TOP_K_CLASSES, OPENAPI_JSON, Prediction, Roi,
         StatusUpdateResponse, VersionInfo, VisionCustomListResponse, VisionDetectionRequest,
-        VisionDetectionResponse,
+        VisionDetectionResponse, VisionInfoResponse,
+    },
+    image::{
+        InvalidImage, OutputImageFormat, draw_boundary_boxes_on_encoded_image, sniff_image_format,
     },
-    detector::ExecutionProvider,
     startup_coordinator::{DetectorInfo, InitResult},
+    system_info::{GpuMemoryInfo, gpu_memory_info},
+    worker::REQUEST_EXPIRED_IN_QUEUE_ERROR,
 };
 use askama::Template;
 use axum::{
     Json, Router,
     body::{self, Body},
-    extract::{DefaultBodyLimit, Multipart, State},
-    http::{Request, StatusCode, header::CACHE_CONTROL},
-    response::{IntoResponse, Response},
+    extract::{
+        DefaultBodyLimit, FromRequest, Multipart, Path as UrlPath, Query, State,
+        multipart::MultipartError,
+    },
+    http::{
+        HeaderValue, Request, StatusCode,
+        header::{CACHE_CONTROL, CONTENT_TYPE, RETRY_AFTER},
+    },
+    response::{
+        IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
     routing::{get, post},
 };
 use base64::{Engine as _, engine::general_purpose};
 use bytes::Bytes;
 use chrono::Utc;
-use crossbeam::channel::Sender;
-use mime::IMAGE_JPEG;
+use crossbeam::channel::{Sender, TrySendError};
+use futures::Stream;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::{conn::auto, graceful::GracefulShutdown},
+    service::TowerToHyperService,
+};
 use reqwest;
 use serde::Deserialize;
 use std::{
-    net::{Ipv4Addr, SocketAddr},
+    collections::{BTreeMap, HashMap},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
     path::{Path, PathBuf},
     sync::Arc,
     time::Instant,
 };
 use tokio::{
-    sync::{Mutex, oneshot},
+    io::AsyncWriteExt,
+    sync::{Mutex, Semaphore, broadcast, oneshot},
+    task::JoinSet,
     time::{Duration, timeout},
 };
 use tokio_util::sync::CancellationToken;
+use tower_http::compression::CompressionLayer;
 use tracing::{debug, error, info, warn};
 
 const MEGABYTE: usize = 1024 * 1024; // 1 MB = 1024 * 1024 bytes
 const THIRTY_MEGABYTES: usize = 30 * MEGABYTE; // 30 MB in bytes
+const QUEUE_FULL_RETRY_AFTER_SECS: u64 = 1;
+/// Detection timings as headers for proxies that log headers but not bodies
+const INFERENCE_MS_HEADER: &str = "x-inference-ms";
+const PROCESS_MS_HEADER: &str = "x-process-ms";
+const PROMETHEUS_TEXT_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+const LATEST_RELEASE_INFO_TIMEOUT: Duration = Duration::from_secs(5);
+const IMAGE_URL_FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+const INVALID_MIN_CONFIDENCE: &str = "min_confidence must be a float between 0 and 1";
+const INVALID_ROI: &str = "roi must be one or more x,y,w,h rectangles separated by ;";
+/// Content types accepted for uploaded images
+const SUPPORTED_IMAGE_CONTENT_TYPES: [&str; 4] =
+    ["image/jpeg", "image/png", "image/bmp", "image/tiff"];
 
 enum DetectorReady {
     NotReady,
     Ready {
         sender: Sender<(
             VisionDetectionRequest,
-            oneshot::Sender<VisionDetectionResponse>,
+            oneshot::Sender<anyhow::Result<VisionDetectionResponse>>,
             Instant,
         )>,
         #[allow(dead_code)]
         detector_info: DetectorInfo,
-        worker_thread_handle: Option<std::thread::JoinHandle<()>>,
+        worker_thread_handles: Vec<std::thread::JoinHandle<()>>,
     },
     Failed(String),
 }
 
 struct ServerState {
     detector_ready: Mutex<DetectorReady>,
+    /// Additional detectors that requests can be routed to by model name
+    named_detectors: HashMap<String, Mutex<DetectorReady>>,
     metrics: Mutex<Metrics>,
     restart_token: CancellationToken,
     config_path: PathBuf,
+    /// Finished detections, forwarded to the clients of /events
+    detection_events: broadcast::Sender<Arc<DetectionEvent>>,
+    cancellation_token: CancellationToken,
+    confidence_scale: ConfidenceScale,
+    module_id: Option<String>,
+    module_name: Option<String>,
+    restart_on_worker_failure: bool,
+    models_dir: Option<PathBuf>,
+    port: u16,
+    output_image_format: OutputImageFormat,
 }
 
+/// Interval the watchdog checks that the detector worker threads are alive at
+const WORKER_WATCHDOG_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Number of detection events buffered per /events client, a client that
+/// falls further behind skips the oldest events
+const DETECTION_EVENT_BUFFER_SIZE: usize = 64;
+
+/// Options for the HTTP server itself, the detectors are configured separately
+pub struct ServerOptions {
+    pub port: u16,
+    /// Address to listen on, IPv6 addresses accept IPv4 clients as well
+    pub bind_address: IpAddr,
+    /// Interval between heartbeat logs, None disables the heartbeat
+    pub heartbeat_interval: Option<Duration>,
+    /// Compress responses for clients that accept gzip or deflate
+    pub compression: bool,
+    /// Maximum number of open connections, None accepts any number
+    pub max_connections: Option<usize>,
+    /// Close keep-alive connections that send no request within this time,
+    /// None keeps idle connections open
+    pub keep_alive_timeout: Option<Duration>,
+    /// Scale of the prediction confidences in detection responses
+    pub confidence_scale: ConfidenceScale,
+    /// Module id reported in the responses instead of the model name
+    pub module_id: Option<String>,
+    /// Module name reported in the responses, None reports no name
+    pub module_name: Option<String>,
+    /// Restart the server when a detector worker thread dies
+    pub restart_on_worker_failure: bool,
+    /// Directory the models are stored in, listed by /v1/vision/custom/list
+    pub models_dir: Option<PathBuf>,
+    /// Model file of the default detector that restarts the server when it
+    /// changes, None disables watching
+    pub watched_model: Option<PathBuf>,
+    /// Format of the annotated images of the test page
+    pub output_image_format: OutputImageFormat,
+    /// Prometheus push gateway URL and the interval the metrics are pushed at,
+    /// None disables pushing
+    pub metrics_push: Option<(String, Duration)>,
+}
+
+/// Time without changes to the watched model file before it is loaded
+const MODEL_WATCH_DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// Timeout of a single metrics push to the push gateway
+const METRICS_PUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time open connections get to finish their requests on shutdown before they
+/// are closed
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+/// Minimum time a request waits for the detector, longer when the request
+/// timeout of the detector is longer
+const MIN_DETECTION_WAIT: Duration = Duration::from_secs(30);
+
+/// Time to wait before accepting again after a failed accept, so running out
+/// of file descriptors doesn't spin the accept loop
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Response written to connections rejected for going over max_connections
+const TOO_MANY_CONNECTIONS_RESPONSE: &[u8] = b"HTTP/1.1 503 Service Unavailable\r\nRetry-After: 1\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
 pub async fn run_server(
-    port: u16,
+    options: ServerOptions,
     cancellation_token: CancellationToken,
     restart_token: CancellationToken,
     detector_init_receiver: tokio::sync::oneshot::Receiver<InitResult>,
+    named_detector_init_receivers: Vec<(String, tokio::sync::oneshot::Receiver<InitResult>)>,
     metrics: Metrics,
     config_path: PathBuf,
-) -> anyhow::Result<(bool, Option<std::thread::JoinHandle<()>>)> {
+) -> anyhow::Result<(bool, Vec<std::thread::JoinHandle<()>>)> {
     // Return bool to indicate if restart was requested
     let server_state = Arc::new(ServerState {
         detector_ready: Mutex::new(DetectorReady::NotReady),
+        named_detectors: named_detector_init_receivers
+            .iter()
+            .map(|(name, _)| (name.clone(), Mutex::new(DetectorReady::NotReady)))
+            .collect(),
         metrics: Mutex::new(metrics),
         restart_token: restart_token.clone(),
         config_path,
+        detection_events: broadcast::channel(DETECTION_EVENT_BUFFER_SIZE).0,
+        cancellation_token: cancellation_token.clone(),
+        confidence_scale: options.confidence_scale,
+        module_id: options.module_id.clone(),
+        module_name: options.module_name.clone(),
+        restart_on_worker_failure: options.restart_on_worker_failure,
+        models_dir: options.models_dir.clone(),
+        port: options.port,
+        output_image_format: options.output_image_format,
     });
 
-    // Spawn a task to wait for detector initialization and update the server state
-    let state_clone = server_state.clone();
-    tokio::spawn(async move {
-        match detector_init_receiver.await {
-            Ok(InitResult::Success {
-                sender,
-                detector_info,
-                worker_thread_handle,
-            }) => {
-                info!(
-                    model_name = %detector_info.model_name,
-                    execution_provider = ?detector_info.execution_provider,
-                    "Detector ready - server can now handle requests"
-                );
-
-                // Update metrics with real detector info
-                {
-                    let mut metrics = state_clone.metrics.lock().await;
-                    metrics.update_detector_info(&detector_info);
-                }
-
-                // Update detector ready state
-                {
-                    let mut detector_ready = state_clone.detector_ready.lock().await;
-                    *detector_ready = DetectorReady::Ready {
-                        sender,
-                        detector_info,
-                        worker_thread_handle: Some(worker_thread_handle),
-                    };
-                }
-            }
-            Ok(InitResult::Failed(error)) => {
-                error!(error = %error, "Detector initialization failed");
-                let mut detector_ready = state_clone.detector_ready.lock().await;
-                *detector_ready = DetectorReady::Failed(error);
-            }
-            Err(_) => {
-                error!("Detector initialization channel was dropped");
-                let mut detector_ready = state_clone.detector_ready.lock().await;
-                *detector_ready =
-                    DetectorReady::Failed("Initialization channel dropped".to_string());
-            }
-        }
-    });
+    // Spawn tasks to wait for detector initialization and update the server state
+    tokio::spawn(wait_for_detector(
+        server_state.clone(),
+        None,
+        detector_init_receiver,
+    ));
+    for (model_name, receiver) in named_detector_init_receivers {
+        tokio::spawn(wait_for_detector(
+            server_state.clone(),
+            Some(model_name),
+            receiver,
+        ));
+    }
+    if let Some(heartbeat_interval) = options.heartbeat_interval {
+        tokio::spawn(heartbeat(
+            server_state.clone(),
+            heartbeat_interval,
+            cancellation_token.clone(),
+            restart_token.clone(),
+        ));
+    }
+    if let Some(model_path) = options.watched_model.clone() {
+        tokio::spawn(watch_model_file(
+            model_path,
+            cancellation_token.clone(),
+            restart_token.clone(),
+        ));
+    }
+    if let Some((push_url, push_interval)) = options.metrics_push.clone() {
+        tokio::spawn(push_metrics(
+            server_state.clone(),
+            push_url,
+            push_interval,
+            cancellation_token.clone(),
+            restart_token.clone(),
+        ));
+    }
     let blue_onyx = Router::new()
         .route("/", get(welcome_handler))
         .route(
             "/v1/status/updateavailable",
             get(v1_status_update_available),
         )
-        .route("/v1/vision/detection", post(v1_vision_detection))
+        .route(
+            "/v1/vision/detection",
+            get(detection_form_handler).post(v1_vision_detection),
+        )
+        .route(
+            "/v1/vision/detection/{model_name}",
+            post(v1_vision_detection_model),
+        )
         .route("/v1/vision/custom/list", post(v1_vision_custom_list))
+        .route("/v1/vision/info", get(v1_vision_info))
+        .route("/v1/vision/classes", get(v1_vision_classes))
         .route("/stats", get(stats_handler))
+        .route("/metrics", get(metrics_handler))
+        .route("/events", get(events_handler))
         .route("/test", get(show_form).post(handle_upload))
+        .route("/test/annotated", post(annotated_image_handler))
         .route("/config", get(config_get_handler).post(config_post_handler))
         .route("/config/restart", post(config_restart_handler))
         .route("/config/loglevel", post(config_loglevel_handler))
+        .route("/admin/restart", post(admin_restart_handler))
         .route("/favicon.ico", get(favicon_handler))
+        .route("/openapi.json", get(openapi_handler))
+        .route("/static/logo.png", get(logo_handler))
         .route(
             "/static/css/bootstrap-icons.css",
             get(bootstrap_icons_css_handler),
@@ -141,11 +272,23 @@ pub async fn run_server(
         .fallback(fallback_handler)
         .with_state(server_state.clone())
         .layer(DefaultBodyLimit::max(THIRTY_MEGABYTES));
+    let blue_onyx = if options.compression {
+        blue_onyx.layer(CompressionLayer::new())
+    } else {
+        blue_onyx
+    };
+
+    let port = options.port;
 
-    let addr = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port);
+    let addr = SocketAddr::new(options.bind_address, port);
     info!("Starting server, listening on {}", addr);
-    info!("Welcome page, http://127.0.0.1:{}", port);
-    let listener = match tokio::net::TcpListener::bind(addr).await {
+    let welcome_addr = if addr.ip().is_unspecified() {
+        SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port)
+    } else {
+        addr
+    };
+    info!("Welcome page, http://{}", welcome_addr);
+    let listener = match bind_listener(addr) {
         Ok(listener) => listener,
         Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
             error!(
@@ -157,35 +300,447 @@ pub async fn run_server(
     };
 
     let restart_check = restart_token.clone();
-    axum::serve(listener, blue_onyx.into_make_service())
-        .with_graceful_shutdown(async move {
-            tokio::select! {
-                _ = cancellation_token.cancelled() => {},
-                _ = restart_check.cancelled() => {},
+    serve(listener, blue_onyx, &options, async move {
+        tokio::select! {
+            _ = cancellation_token.cancelled() => {},
+            _ = restart_check.cancelled() => {},
+        }
+    })
+    .await; // Return true if restart was requested, false if normal shutdown
+    // Also return the worker thread handles if available for clean shutdown
+    let worker_handles = server_state.take_worker_thread_handles().await;
+    Ok((restart_token.is_cancelled(), worker_handles))
+}
+
+/// Binds the listening socket, IPv6 sockets are dual-stack so they accept
+/// IPv4 clients as IPv4-mapped addresses regardless of the OS default
+fn bind_listener(addr: SocketAddr) -> std::io::Result<tokio::net::TcpListener> {
+    let socket = socket2::Socket::new(
+        socket2::Domain::for_address(addr),
+        socket2::Type::STREAM,
+        Some(socket2::Protocol::TCP),
+    )?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(false)?;
+    }
+    // Same as tokio::net::TcpListener::bind, on Windows SO_REUSEADDR would let
+    // another process bind the same port
+    #[cfg(not(windows))]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    tokio::net::TcpListener::from_std(socket.into())
+}
+
+/// Accepts connections until shutdown completes and serves them with the
+/// router, rejecting connections over max_connections with a 503. Open
+/// connections get SHUTDOWN_GRACE_PERIOD to finish their requests, including
+/// the ones still waiting in the worker queue, before they are closed.
+async fn serve(
+    listener: tokio::net::TcpListener,
+    router: Router,
+    options: &ServerOptions,
+    shutdown: impl Future<Output = ()>,
+) {
+    let connection_limit = options.max_connections.map(|n| Arc::new(Semaphore::new(n)));
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    if let Some(keep_alive_timeout) = options.keep_alive_timeout {
+        builder
+            .http1()
+            .timer(TokioTimer::new())
+            .header_read_timeout(keep_alive_timeout);
+    }
+    let graceful = GracefulShutdown::new();
+    let mut connections = JoinSet::new();
+    tokio::pin!(shutdown);
+
+    loop {
+        let (mut stream, remote_addr) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    warn!(?err, "Failed to accept connection");
+                    tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                    continue;
+                }
+            },
+            // Reap finished connections so the set doesn't grow
+            Some(_) = connections.join_next(), if !connections.is_empty() => continue,
+            _ = &mut shutdown => break,
+        };
+
+        let permit = match &connection_limit {
+            Some(limit) => match limit.clone().try_acquire_owned() {
+                Ok(permit) => Some(permit),
+                Err(_) => {
+                    warn!(%remote_addr, "Too many open connections, rejecting connection");
+                    tokio::spawn(async move {
+                        let _ = stream.write_all(TOO_MANY_CONNECTIONS_RESPONSE).await;
+                        let _ = stream.shutdown().await;
+                    });
+                    continue;
+                }
+            },
+            None => None,
+        };
+
+        let connection = builder
+            .serve_connection(
+                TokioIo::new(stream),
+                TowerToHyperService::new(router.clone()),
+            )
+            .into_owned();
+        let connection = graceful.watch(connection);
+        connections.spawn(async move {
+            if let Err(err) = connection.await {
+                debug!(?err, %remote_addr, "Connection closed with error");
             }
-        })
-        .await?; // Return true if restart was requested, false if normal shutdown
-    // Also return the worker thread handle if available for clean shutdown
-    let worker_handle = server_state.take_worker_thread_handle().await;
-    Ok((restart_token.is_cancelled(), worker_handle))
+            drop(permit);
+        });
+    }
+
+    info!(
+        connections = connections.len(),
+        "Waiting for open connections to finish their requests"
+    );
+    if timeout(SHUTDOWN_GRACE_PERIOD, graceful.shutdown())
+        .await
+        .is_err()
+    {
+        warn!(
+            grace_period = ?SHUTDOWN_GRACE_PERIOD,
+            "Connections did not finish within the grace period, closing them"
+        );
+        connections.abort_all();
+    }
+    while connections.join_next().await.is_some() {}
+}
+
+/// Logs a throughput summary of the default detector every interval until the
+/// server shuts down or restarts.
+async fn heartbeat(
+    server_state: Arc<ServerState>,
+    interval: Duration,
+    cancellation_token: CancellationToken,
+    restart_token: CancellationToken,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    // The first tick completes immediately
+    ticker.tick().await;
+    let mut previous = server_state.metrics.lock().await.clone();
+    let mut previous_time = Instant::now();
+    loop {
+        tokio::select! {
+            _ = cancellation_token.cancelled() => break,
+            _ = restart_token.cancelled() => break,
+            _ = ticker.tick() => {}
+        }
+        let current = server_state.metrics.lock().await.clone();
+        let elapsed = previous_time.elapsed();
+        previous_time = Instant::now();
+
+        let requests = current
+            .number_of_requests
+            .wrapping_sub(previous.number_of_requests);
+        let dropped = current
+            .dropped_requests
+            .wrapping_sub(previous.dropped_requests);
+        let inference_ms = current
+            .total_inference_ms
+            .wrapping_sub(previous.total_inference_ms);
+        let requests_per_sec = requests as f64 / elapsed.as_secs_f64();
+        let avg_inference_ms = if requests > 0 {
+            inference_ms as f64 / requests as f64
+        } else {
+            0.0
+        };
+        info!(
+            "Heartbeat: {requests_per_sec:.2} requests/s, {avg_inference_ms:.1} ms average inference, {dropped} dropped requests in the last {:.0}s",
+            elapsed.as_secs_f64()
+        );
+        if let Some(gpu_memory) = current.gpu_memory() {
+            info!("Heartbeat: GPU memory {gpu_memory}");
+        }
+        previous = current;
+    }
+}
+
+/// Periodically pushes the metrics in the Prometheus text format to a push
+/// gateway, for deployments that can't be scraped. A failed push is logged
+/// and retried at the next interval.
+async fn push_metrics(
+    server_state: Arc<ServerState>,
+    push_url: String,
+    interval: Duration,
+    cancellation_token: CancellationToken,
+    restart_token: CancellationToken,
+) {
+    let client = match reqwest::Client::builder()
+        .timeout(METRICS_PUSH_TIMEOUT)
+        .build()
+    {
+        Ok(client) => client,
+        Err(err) => {
+            error!(?err, "Failed to create the metrics push client");
+            return;
+        }
+    };
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = cancellation_token.cancelled() => break,
+            _ = restart_token.cancelled() => break,
+            _ = ticker.tick() => {}
+        }
+        let body = server_state.metrics.lock().await.to_prometheus_text();
+        let result = client
+            .post(&push_url)
+            .header(reqwest::header::CONTENT_TYPE, PROMETHEUS_TEXT_CONTENT_TYPE)
+            .body(body)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        match result {
+            Ok(_) => debug!("Pushed metrics to {push_url}"),
+            Err(err) => warn!(%err, "Failed to push metrics to {push_url}"),
+        }
+    }
+}
+
+/// Restarts the server when the model file is created, written or replaced,
+/// once no change was seen for MODEL_WATCH_DEBOUNCE so a model that is still
+/// being copied is not loaded. The directory is watched since replacing the
+/// file, e.g. by a rename, ends a watch on the file itself.
+async fn watch_model_file(
+    model_path: PathBuf,
+    cancellation_token: CancellationToken,
+    restart_token: CancellationToken,
+) {
+    let (Some(model_dir), Some(model_file_name)) = (model_path.parent(), model_path.file_name())
+    else {
+        warn!("Can't watch model file {}", model_path.display());
+        return;
+    };
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let _ = sender.send(event);
+    })
+    .and_then(|mut watcher| {
+        notify::Watcher::watch(&mut watcher, model_dir, notify::RecursiveMode::NonRecursive)?;
+        Ok(watcher)
+    });
+    // Dropping the watcher stops watching
+    let _watcher = match watcher {
+        Ok(watcher) => watcher,
+        Err(err) => {
+            error!(%err, "Failed to watch model file {}", model_path.display());
+            return;
+        }
+    };
+    info!("Watching model file {} for changes", model_path.display());
+
+    let mut reload_at = None;
+    loop {
+        let debounce = async move {
+            match reload_at {
+                Some(reload_at) => tokio::time::sleep_until(reload_at).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            _ = cancellation_token.cancelled() => break,
+            _ = restart_token.cancelled() => break,
+            _ = debounce => {
+                if model_path.is_file() {
+                    info!("Model file {} changed, restarting to load it", model_path.display());
+                    restart_token.cancel();
+                    break;
+                }
+                // Removed and not replaced (yet), keep the loaded model
+                reload_at = None;
+            }
+            event = receiver.recv() => match event {
+                Some(Ok(event)) => {
+                    // Reads of the model, e.g. by the detector loading it, are
+                    // access or metadata events and don't count as changes
+                    let changed = matches!(
+                        event.kind,
+                        notify::EventKind::Create(_)
+                            | notify::EventKind::Modify(
+                                notify::event::ModifyKind::Data(_)
+                                    | notify::event::ModifyKind::Name(_)
+                                    | notify::event::ModifyKind::Any
+                            )
+                    );
+                    if changed
+                        && event
+                            .paths
+                            .iter()
+                            .any(|path| path.file_name() == Some(model_file_name))
+                    {
+                        debug!(?event, "Model file changed");
+                        reload_at = Some(tokio::time::Instant::now() + MODEL_WATCH_DEBOUNCE);
+                    }
+                }
+                Some(Err(err)) => warn!(%err, "Error watching model file {}", model_path.display()),
+                None => break,
+            },
+        }
+    }
+}
+
+/// Waits for a detector to finish initializing and updates its ready state.
+/// `model_name` is None for the default detector.
+async fn wait_for_detector(
+    server_state: Arc<ServerState>,
+    model_name: Option<String>,
+    detector_init_receiver: tokio::sync::oneshot::Receiver<InitResult>,
+) {
+    let Some(detector) = server_state.detector(model_name.as_deref()) else {
+        return;
+    };
+    let route = model_name.as_deref().unwrap_or("default");
+    let ready = match detector_init_receiver.await {
+        Ok(InitResult::Success {
+            sender,
+            detector_info,
+            worker_thread_handles,
+        }) => {
+            info!(
+                route,
+                model_name = %detector_info.model_name,
+                execution_provider = ?detector_info.execution_provider,
+                "Detector ready - server can now handle requests"
+            );
+            log_startup_banner(route, server_state.port, &detector_info, &sender);
+
+            // Update metrics with real detector info for the default detector
+            if model_name.is_none() {
+                let mut metrics = server_state.metrics.lock().await;
+                metrics.update_detector_info(&detector_info);
+            }
+
+            DetectorReady::Ready {
+                sender,
+                detector_info,
+                worker_thread_handles,
+            }
+        }
+        Ok(InitResult::Failed(error)) => {
+            error!(route, error = %error, "Detector initialization failed");
+            DetectorReady::Failed(error)
+        }
+        Err(_) => {
+            error!(route, "Detector initialization channel was dropped");
+            DetectorReady::Failed("Initialization channel dropped".to_string())
+        }
+    };
+    let is_ready = matches!(ready, DetectorReady::Ready { .. });
+    *detector.lock().await = ready;
+    if is_ready {
+        watch_detector_workers(&server_state, route, detector).await;
+    }
+}
+
+/// Logs the resolved detector and server settings in one block once the
+/// detector is ready, the summary to share when asking for help
+fn log_startup_banner(
+    route: &str,
+    port: u16,
+    detector_info: &DetectorInfo,
+    sender: &Sender<(
+        VisionDetectionRequest,
+        oneshot::Sender<anyhow::Result<VisionDetectionResponse>>,
+        Instant,
+    )>,
+) {
+    let (input_width, input_height) = detector_info.input_size;
+    let queue_size = match (sender.capacity(), &detector_info.queue_limit) {
+        (Some(capacity), Some(queue_limit)) => {
+            format!("{capacity} (adaptive, currently {})", queue_limit.limit())
+        }
+        (Some(capacity), None) => capacity.to_string(),
+        (None, _) => "unbounded".to_string(),
+    };
+    info!("=== Blue Onyx {} ready ===", env!("CARGO_PKG_VERSION"));
+    info!("  Route: {route}");
+    info!("  Model: {}", detector_info.model_name);
+    info!("  Execution provider: {}", detector_info.execution_provider);
+    info!("  Input size: {input_width}x{input_height}");
+    info!("  Object classes: {}", detector_info.object_classes.len());
+    info!("  Port: {port}");
+    info!("  Worker queue size: {queue_size}");
+    info!("==============================");
+}
+
+/// Watches the worker threads of a ready detector. The workers only exit on
+/// their own once the queue is closed on shutdown, so a worker that finished
+/// while the detector is ready has panicked. The detector is then marked as
+/// failed so requests get an error instead of timing out, and the server is
+/// restarted if configured to.
+async fn watch_detector_workers(
+    server_state: &ServerState,
+    route: &str,
+    detector: &Mutex<DetectorReady>,
+) {
+    let mut ticker = tokio::time::interval(WORKER_WATCHDOG_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = server_state.cancellation_token.cancelled() => return,
+            _ = server_state.restart_token.cancelled() => return,
+            _ = ticker.tick() => {}
+        }
+        let mut detector_ready = detector.lock().await;
+        let DetectorReady::Ready {
+            worker_thread_handles,
+            ..
+        } = &mut *detector_ready
+        else {
+            // The worker threads were taken for shutdown
+            return;
+        };
+        let Some(finished) = worker_thread_handles
+            .iter()
+            .position(|handle| handle.is_finished())
+        else {
+            continue;
+        };
+        let reason = match worker_thread_handles.swap_remove(finished).join() {
+            Ok(()) => "exited unexpectedly".to_string(),
+            Err(panic) => panic
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .map_or_else(
+                    || "panicked".to_string(),
+                    |message| format!("panicked: {message}"),
+                ),
+        };
+        let error = format!("Detector worker thread {reason}");
+        error!(route, %error, "Detector worker thread died, marking the detector as failed");
+        // Dropping the queue sender lets any other workers drain the queue and exit
+        *detector_ready = DetectorReady::Failed(error);
+        if server_state.restart_on_worker_failure {
+            warn!(route, "Restarting the server to recover the detector");
+            server_state.restart_token.cancel();
+        }
+        return;
+    }
 }
 
 #[derive(Template)]
 #[template(path = "welcome.html")]
 struct WelcomeTemplate {
-    logo_data: String,
     metrics: Metrics,
 }
 
 async fn welcome_handler(State(server_state): State<Arc<ServerState>>) -> impl IntoResponse {
-    const LOGO: &[u8] = include_bytes!("../assets/logo_large.png");
-    let encoded_logo = general_purpose::STANDARD.encode(LOGO);
-    let logo_data = format!("data:image/png;base64,{encoded_logo}");
     let metrics = {
         let metrics_guard = server_state.metrics.lock().await;
         metrics_guard.clone()
     };
-    let template = WelcomeTemplate { logo_data, metrics };
+    let template = WelcomeTemplate { metrics };
     match template.render() {
         Ok(body) => (
             [
@@ -203,99 +758,437 @@ async fn welcome_handler(State(server_state): State<Arc<ServerState>>) -> impl I
     }
 }
 
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct VisionDetectionQuery {
+    /// Include the detailed per stage timings in the response
+    timings: bool,
+    /// Include the sub-pixel prediction coordinates in the response
+    float_coordinates: bool,
+    /// Attach the best scoring classes to each prediction
+    top_k_classes: usize,
+}
+
 async fn v1_vision_detection(
     State(server_state): State<Arc<ServerState>>,
-    mut multipart: Multipart, // Note multipart needs to be last
-) -> Result<Json<VisionDetectionResponse>, BlueOnyxError> {
+    Query(query): Query<VisionDetectionQuery>,
+    request: Request<Body>, // Note the request needs to be last
+) -> Result<Response, BlueOnyxError> {
+    vision_detection(server_state, None, query, request).await
+}
+
+async fn v1_vision_detection_model(
+    State(server_state): State<Arc<ServerState>>,
+    UrlPath(model_name): UrlPath<String>,
+    Query(query): Query<VisionDetectionQuery>,
+    request: Request<Body>, // Note the request needs to be last
+) -> Result<Response, BlueOnyxError> {
+    vision_detection(server_state, Some(model_name), query, request).await
+}
+
+/// True if the request body is the raw image instead of a multipart form,
+/// sent as application/octet-stream or an image content type
+fn is_raw_image_body(request: &Request<Body>) -> bool {
+    let Some(content_type) = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+    else {
+        return false;
+    };
+    // Ignore parameters like charset
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    mime == "application/octet-stream" || mime.starts_with("image/")
+}
+
+async fn vision_detection(
+    server_state: Arc<ServerState>,
+    mut model_name: Option<String>,
+    query: VisionDetectionQuery,
+    request: Request<Body>,
+) -> Result<Response, BlueOnyxError> {
     let request_start_time = Instant::now();
-    let mut vision_request = VisionDetectionRequest::default();
+    let float_coordinates = query.float_coordinates;
+    let mut vision_request = VisionDetectionRequest {
+        timings: query.timings,
+        top_k_classes: query.top_k_classes.min(MAX_TOP_K_CLASSES),
+        ..Default::default()
+    };
+    let mut image_url = None;
 
-    while let Some(field) = multipart.next_field().await? {
-        match field.name() {
-            Some("min_confidence") => {
-                vision_request.min_confidence = field.text().await?.parse::<f32>()?;
-            }
-            Some("image") => {
-                if let Some(image_name) = field.file_name().map(|s| s.to_string()) {
-                    vision_request.image_name = image_name;
+    if is_raw_image_body(&request) {
+        // Minimal clients post the image bytes as the whole body
+        match Bytes::from_request(request, &()).await {
+            Ok(image_data) => vision_request.image_data = image_data,
+            Err(rejection) => return Ok(rejection.into_response()),
+        }
+    } else {
+        let mut multipart = match Multipart::from_request(request, &()).await {
+            Ok(multipart) => multipart,
+            Err(rejection) => return Ok(rejection.into_response()),
+        };
+        while let Some(field) = multipart.next_field().await? {
+            match field.name() {
+                Some("min_confidence") => {
+                    let Some(min_confidence) = parse_min_confidence(&field.text().await?) else {
+                        return Err(BlueOnyxError::BadRequest(INVALID_MIN_CONFIDENCE.into()));
+                    };
+                    vision_request.min_confidence = min_confidence;
+                }
+                Some("roi") => {
+                    // Several roi fields and ; separated rectangles both add up
+                    let Some(rois) = Roi::parse_list(&field.text().await?) else {
+                        return Err(BlueOnyxError::BadRequest(INVALID_ROI.into()));
+                    };
+                    vision_request.rois.extend(rois);
+                }
+                Some("camera") => {
+                    let camera = field.text().await?;
+                    if !camera.is_empty() {
+                        vision_request.camera = Some(camera);
+                    }
                 }
-                vision_request.image_data = field.bytes().await?;
+                Some("profile") => {
+                    let profile = field.text().await?;
+                    if !profile.is_empty() {
+                        vision_request.profile = Some(profile);
+                    }
+                }
+                Some("model") => {
+                    // The model in the path takes precedence over the multipart field
+                    let model = field.text().await?;
+                    if model_name.is_none() && !model.is_empty() {
+                        model_name = Some(model);
+                    }
+                }
+                Some("image") => {
+                    if let Some(image_name) = field.file_name().map(|s| s.to_string()) {
+                        vision_request.image_name = image_name;
+                    }
+                    vision_request.image_data = field.bytes().await?;
+                }
+                Some("image_url") => {
+                    let url = field.text().await?;
+                    if !url.is_empty() {
+                        image_url = Some(url);
+                    }
+                }
+                Some(&_) => {}
+                None => {}
             }
-            Some(&_) => {}
-            None => {}
         }
     }
 
+    // Uploaded image bytes take precedence over the image url
+    if let Some(url) = image_url
+        && vision_request.image_data.is_empty()
+    {
+        let (image_name, image_data) = fetch_image_from_url(&url)
+            .await
+            .map_err(|err| BlueOnyxError::BadRequest(err.to_string()))?;
+        vision_request.image_name = image_name;
+        vision_request.image_data = image_data;
+    }
+
+    // Metrics only track the default detector
+    let is_default_detector = model_name.is_none();
+    if is_default_detector {
+        server_state
+            .metrics
+            .lock()
+            .await
+            .update_image_metrics(&vision_request.image_data);
+    }
+
+    let image_name = vision_request.image_name.clone();
+    let camera = vision_request.camera.clone();
+
     // Check detector state first
-    let detector_ready = server_state.detector_ready.lock().await;
+    let detector = server_state
+        .detector(model_name.as_deref())
+        .ok_or_else(|| {
+            BlueOnyxError::BadRequest(format!(
+                "Unknown model: {}",
+                model_name.as_deref().unwrap_or_default()
+            ))
+        })?;
+    let detector_ready = detector.lock().await;
     match &*detector_ready {
         DetectorReady::NotReady => {
             // Detector is still initializing, return not ready
-            Err(BlueOnyxError(anyhow::anyhow!(
-                "Server not ready yet, detector is still initializing"
-            )))
+            Err(BlueOnyxError::NotReady(
+                "Server not ready yet, detector is still initializing".into(),
+            ))
         }
         DetectorReady::Failed(error_msg) => {
             // Detector initialization failed
-            Err(BlueOnyxError(anyhow::anyhow!(
-                "Detector initialization failed: {}",
-                error_msg
+            Err(BlueOnyxError::NotReady(format!(
+                "Detector initialization failed: {error_msg}"
             )))
         }
         DetectorReady::Ready {
             sender,
-            detector_info: _,
-            worker_thread_handle: _,
+            detector_info,
+            worker_thread_handles: _,
         } => {
-            // Detector is ready, proceed with request
-            let (response_sender, receiver) = tokio::sync::oneshot::channel();
-
-            if sender.is_full() {
-                warn!("Worker queue is full server is overloaded, rejecting request");
+            check_profile(vision_request.profile.as_deref(), &detector_info.profiles)?;
+            if let Some(queue_limit) = &detector_info.queue_limit
+                && sender.len() >= queue_limit.limit()
+            {
+                warn!(
+                    limit = queue_limit.limit(),
+                    "Worker queue is over the adaptive limit server is overloaded, rejecting request"
+                );
                 drop(detector_ready); // Release the lock
-                update_dropped_requests(server_state).await;
-                return Err(BlueOnyxError(anyhow::anyhow!("Worker queue is full")));
+                update_dropped_requests(server_state, is_default_detector).await;
+                return Err(BlueOnyxError::QueueFull);
             }
 
-            if let Err(err) = sender.send((vision_request, response_sender, request_start_time)) {
-                warn!(?err, "Failed to send request to detection worker");
-                drop(detector_ready); // Release the lock
-                update_dropped_requests(server_state).await;
-                return Err(BlueOnyxError(anyhow::anyhow!("Worker queue is full")));
+            // Detector is ready, proceed with request
+            let detection_wait = detector_info.request_timeout.max(MIN_DETECTION_WAIT);
+            let (response_sender, receiver) = tokio::sync::oneshot::channel();
+
+            match sender.try_send((vision_request, response_sender, request_start_time)) {
+                Ok(()) => {
+                    // Sample the queue length after enqueueing
+                    if is_default_detector {
+                        let (queue_length, queue_capacity) =
+                            (sender.len(), sender.capacity().unwrap_or_default());
+                        drop(detector_ready); // Release the lock
+                        let mut metrics = server_state.metrics.lock().await;
+                        metrics.update_queue_length(queue_length, queue_capacity);
+                    } else {
+                        drop(detector_ready); // Release the lock
+                    }
+                }
+                Err(TrySendError::Full(_)) => {
+                    warn!("Worker queue is full server is overloaded, rejecting request");
+                    drop(detector_ready); // Release the lock
+                    update_dropped_requests(server_state, is_default_detector).await;
+                    return Err(BlueOnyxError::QueueFull);
+                }
+                Err(TrySendError::Disconnected(_)) => {
+                    warn!("Failed to send request to detection worker, worker is gone");
+                    drop(detector_ready); // Release the lock
+                    update_dropped_requests(server_state, is_default_detector).await;
+                    return Err(BlueOnyxError::Internal(anyhow::anyhow!(
+                        "Detection worker is not running"
+                    )));
+                }
             }
 
-            drop(detector_ready); // Release the lock before waiting
-            let result = timeout(Duration::from_secs(30), receiver).await;
+            let result = timeout(detection_wait, receiver).await;
 
             let mut vision_response = match result {
-                Ok(Ok(response)) => response,
+                Ok(Ok(Ok(response))) => response,
+                Ok(Ok(Err(err))) => {
+                    warn!(?err, "Detection failed");
+                    return Err(BlueOnyxError::from(err));
+                }
                 Ok(Err(err)) => {
                     warn!("Failed to receive vision detection response: {:?}", err);
-                    update_dropped_requests(server_state).await;
+                    update_dropped_requests(server_state, is_default_detector).await;
                     return Err(BlueOnyxError::from(err));
                 }
                 Err(_) => {
                     warn!("Timeout while waiting for vision detection response");
-                    update_dropped_requests(server_state).await;
-                    return Err(BlueOnyxError::from(anyhow::anyhow!("Operation timed out")));
+                    update_dropped_requests(server_state, is_default_detector).await;
+                    return Err(BlueOnyxError::Timeout);
                 }
             };
+            if let Some(module_id) = &server_state.module_id {
+                vision_response.moduleId = module_id.clone();
+            }
+            if let Some(module_name) = &server_state.module_name {
+                vision_response.moduleName = module_name.clone();
+            }
+            if vision_response.error.as_deref() == Some(REQUEST_EXPIRED_IN_QUEUE_ERROR) {
+                update_dropped_requests(server_state, is_default_detector).await;
+                return Ok((StatusCode::SERVICE_UNAVAILABLE, Json(vision_response)).into_response());
+            }
             vision_response.analysisRoundTripMs = request_start_time.elapsed().as_millis() as i32;
+            if !float_coordinates {
+                vision_response
+                    .predictions
+                    .iter_mut()
+                    .for_each(Prediction::clear_float_coordinates);
+            }
 
-            {
+            if is_default_detector {
                 let mut metrics = server_state.metrics.lock().await;
                 metrics.update_metrics(&vision_response);
             }
+            publish_detection_event(
+                &server_state,
+                model_name,
+                camera,
+                image_name,
+                &vision_response,
+            );
+
+            server_state.confidence_scale.apply(&mut vision_response);
+            Ok(detection_response(vision_response))
+        }
+    }
+}
+
+/// Publishes a successful detection to the /events clients, if there are any
+fn publish_detection_event(
+    server_state: &ServerState,
+    model: Option<String>,
+    camera: Option<String>,
+    image_name: String,
+    vision_response: &VisionDetectionResponse,
+) {
+    if !vision_response.success || server_state.detection_events.receiver_count() == 0 {
+        return;
+    }
+    let event = DetectionEvent {
+        timestamp: Utc::now().to_rfc3339(),
+        model,
+        camera,
+        image_name,
+        labels: vision_response
+            .predictions
+            .iter()
+            .map(|prediction| prediction.label.clone())
+            .collect(),
+        predictions: vision_response.predictions.clone(),
+        count: vision_response.count,
+        inference_ms: vision_response.inferenceMs,
+        process_ms: vision_response.processMs,
+        analysis_round_trip_ms: vision_response.analysisRoundTripMs,
+    };
+    // Sending only fails if every client disconnected in the meantime
+    let _ = server_state.detection_events.send(Arc::new(event));
+}
+
+/// Streams every finished detection as a `detection` Server-Sent Event with a
+/// JSON DetectionEvent, the stream ends when the server shuts down
+async fn events_handler(
+    State(server_state): State<Arc<ServerState>>,
+) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    let receiver = server_state.detection_events.subscribe();
+    let stream = futures::stream::unfold(receiver, move |mut receiver| {
+        let server_state = server_state.clone();
+        async move {
+            loop {
+                let received = tokio::select! {
+                    _ = server_state.cancellation_token.cancelled() => return None,
+                    _ = server_state.restart_token.cancelled() => return None,
+                    received = receiver.recv() => received,
+                };
+                match received {
+                    Ok(detection_event) => {
+                        let event = Event::default()
+                            .event("detection")
+                            .json_data(&*detection_event);
+                        return Some((event, receiver));
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        debug!(skipped, "Events client fell behind, skipping events");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        }
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+/// Parses the min_confidence form field, values outside 0-1 are clamped and an
+/// empty value leaves the server default in place.
+fn parse_min_confidence(text: &str) -> Option<f32> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Some(0.);
+    }
+    let min_confidence = text.parse::<f32>().ok().filter(|value| !value.is_nan())?;
+    Some(min_confidence.clamp(0., 1.))
+}
+
+/// Downloads the image for the `image_url` field, the content type must be a
+/// supported image type and the size is capped to the request body limit.
+async fn fetch_image_from_url(url: &str) -> anyhow::Result<(String, Bytes)> {
+    let client = reqwest::Client::builder()
+        .timeout(IMAGE_URL_FETCH_TIMEOUT)
+        .build()?;
+    let mut response = client.get(url).send().await?.error_for_status()?;
+
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|value| value.trim().to_lowercase())
+        .unwrap_or_default();
+    if !SUPPORTED_IMAGE_CONTENT_TYPES.contains(&content_type.as_str()) {
+        anyhow::bail!("Unsupported content type {content_type:?} for image url {url}");
+    }
+    if response
+        .content_length()
+        .is_some_and(|length| length > THIRTY_MEGABYTES as u64)
+    {
+        anyhow::bail!("Image at {url} is larger than {THIRTY_MEGABYTES} bytes");
+    }
 
-            Ok(Json(vision_response))
+    let mut image_data = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if image_data.len() + chunk.len() > THIRTY_MEGABYTES {
+            anyhow::bail!("Image at {url} is larger than {THIRTY_MEGABYTES} bytes");
         }
+        image_data.extend_from_slice(&chunk);
     }
+
+    let image_name = response
+        .url()
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|name| !name.is_empty())
+        .unwrap_or("image_url")
+        .to_string();
+    Ok((image_name, Bytes::from(image_data)))
+}
+
+/// Successful detection response with the inferenceMs and processMs timings
+/// also set as X-Inference-Ms and X-Process-Ms headers
+fn detection_response(vision_response: VisionDetectionResponse) -> Response {
+    (
+        [
+            (INFERENCE_MS_HEADER, vision_response.inferenceMs.to_string()),
+            (PROCESS_MS_HEADER, vision_response.processMs.to_string()),
+        ],
+        Json(vision_response),
+    )
+        .into_response()
 }
 
 async fn v1_status_update_available() -> Result<Json<StatusUpdateResponse>, BlueOnyxError> {
-    let (latest_release_version_str, release_notes_url) = get_latest_release_info().await?;
-    let latest = VersionInfo::parse(latest_release_version_str.as_str(), Some(release_notes_url))?;
     let current = VersionInfo::parse(env!("CARGO_PKG_VERSION"), None)?;
+    let latest = match get_latest_release_info().await.and_then(
+        |(latest_release_version_str, release_notes_url)| {
+            VersionInfo::parse(latest_release_version_str.as_str(), Some(release_notes_url))
+        },
+    ) {
+        Ok(latest) => latest,
+        Err(err) => {
+            // Don't fail the request if GitHub is slow or unreachable
+            warn!(?err, "Failed to get latest release info");
+            return Ok(Json(StatusUpdateResponse {
+                success: false,
+                message: "Latest version unknown".to_string(),
+                version: None, // Deprecated field
+                current,
+                latest: VersionInfo::default(),
+                updateAvailable: false,
+            }));
+        }
+    };
     let updates_available = latest > current;
     let response = StatusUpdateResponse {
         success: true,
@@ -308,12 +1201,62 @@ async fn v1_status_update_available() -> Result<Json<StatusUpdateResponse>, Blue
     Ok(Json(response))
 }
 
-async fn v1_vision_custom_list() -> Result<Json<VisionCustomListResponse>, BlueOnyxError> {
+async fn v1_vision_info(
+    State(server_state): State<Arc<ServerState>>,
+) -> Result<Json<VisionInfoResponse>, BlueOnyxError> {
+    let detector_ready = server_state.detector_ready.lock().await;
+    match &*detector_ready {
+        DetectorReady::NotReady => Err(BlueOnyxError::NotReady(
+            "Server not ready yet, detector is still initializing".into(),
+        )),
+        DetectorReady::Failed(error_msg) => Err(BlueOnyxError::NotReady(format!(
+            "Detector initialization failed: {error_msg}"
+        ))),
+        DetectorReady::Ready { detector_info, .. } => {
+            let (input_width, input_height) = detector_info.input_size;
+            Ok(Json(VisionInfoResponse {
+                success: true,
+                model_name: detector_info.model_name.clone(),
+                execution_provider: detector_info.execution_provider.to_string(),
+                input_width,
+                input_height,
+                object_class_count: detector_info.object_classes.len(),
+                model_load_ms: detector_info.model_load_time.as_millis() as u64,
+                warmup_ms: detector_info.warmup_time.as_millis() as u64,
+            }))
+        }
+    }
+}
+
+/// The labels the default model can produce, for object filter pickers
+async fn v1_vision_classes(
+    State(server_state): State<Arc<ServerState>>,
+) -> Result<Json<Vec<String>>, BlueOnyxError> {
+    let detector_ready = server_state.detector_ready.lock().await;
+    match &*detector_ready {
+        DetectorReady::NotReady => Err(BlueOnyxError::NotReady(
+            "Server not ready yet, detector is still initializing".into(),
+        )),
+        DetectorReady::Failed(error_msg) => Err(BlueOnyxError::NotReady(format!(
+            "Detector initialization failed: {error_msg}"
+        ))),
+        DetectorReady::Ready { detector_info, .. } => {
+            Ok(Json(detector_info.object_classes.clone()))
+        }
+    }
+}
+
+/// Lists the models in the models directory like the CodeProject.AI custom
+/// model list, used by Blue Iris to discover the models
+async fn v1_vision_custom_list(
+    State(server_state): State<Arc<ServerState>>,
+) -> Result<Json<VisionCustomListResponse>, BlueOnyxError> {
+    let models = crate::list_local_models(server_state.models_dir.as_deref())?;
     let response = VisionCustomListResponse {
         success: true,
-        models: vec![],
-        moduleId: "".to_string(),
-        moduleName: "".to_string(),
+        models,
+        moduleId: server_state.module_id.clone().unwrap_or_default(),
+        moduleName: server_state.module_name.clone().unwrap_or_default(),
         command: "list".to_string(),
         statusData: None,
         inferenceDevice: "CPU".to_string(),
@@ -325,6 +1268,12 @@ async fn v1_vision_custom_list() -> Result<Json<VisionCustomListResponse>, BlueO
     Ok(Json(response))
 }
 
+/// The stats in the Prometheus text format for scraping
+async fn metrics_handler(State(server_state): State<Arc<ServerState>>) -> impl IntoResponse {
+    let text = server_state.metrics.lock().await.to_prometheus_text();
+    ([(CONTENT_TYPE, PROMETHEUS_TEXT_CONTENT_TYPE)], text)
+}
+
 #[derive(Template)]
 #[template(path = "stats.html")]
 struct StatsTemplate {
@@ -354,23 +1303,13 @@ async fn stats_handler(State(server_state): State<Arc<ServerState>>) -> impl Int
     }
 }
 
-async fn show_form() -> impl IntoResponse {
-    let template = TestTemplate;
-    match template.render() {
-        Ok(body) => (
-            [
-                (CACHE_CONTROL, "no-store, no-cache, must-revalidate"),
-                (axum::http::header::CONTENT_TYPE, "text/html; charset=utf-8"),
-            ],
-            body,
-        )
-            .into_response(),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Template error: {e}"),
-        )
-            .into_response(),
-    }
+async fn show_form(State(server_state): State<Arc<ServerState>>) -> impl IntoResponse {
+    render_test_template(TestTemplate {
+        annotated_image: String::new(),
+        image_url: String::new(),
+        min_confidence: DEFAULT_FORM_MIN_CONFIDENCE,
+        confidence_percent: server_state.confidence_scale == ConfidenceScale::Percent,
+    })
 }
 
 async fn favicon_handler() -> impl IntoResponse {
@@ -382,20 +1321,60 @@ async fn favicon_handler() -> impl IntoResponse {
         .into_response()
 }
 
+/// The logo rarely changes so browsers may keep it for a week
+const LOGO_CACHE_CONTROL: &str = "public, max-age=604800";
+
+async fn logo_handler() -> impl IntoResponse {
+    const LOGO: &[u8] = include_bytes!("../assets/logo_large.png");
+    (
+        [
+            (CACHE_CONTROL, LOGO_CACHE_CONTROL),
+            (axum::http::header::CONTENT_TYPE, "image/png"),
+        ],
+        LOGO,
+    )
+        .into_response()
+}
+
+async fn openapi_handler() -> impl IntoResponse {
+    (
+        [(axum::http::header::CONTENT_TYPE, "application/json")],
+        OPENAPI_JSON,
+    )
+        .into_response()
+}
+
 #[derive(Template)]
 #[template(path = "config.html")]
 struct ConfigTemplate {
-    logo_data: String,
     config: ConfigTemplateData,
     config_path: String,
     success_message: String,
     error_message: String,
+    rejected_fields: Vec<RejectedField>,
+}
+
+impl ConfigTemplate {
+    /// Submitted value of a field that was rejected, shown next to the field
+    fn rejected_value(&self, name: &str) -> Option<&str> {
+        self.rejected_fields
+            .iter()
+            .find(|field| field.name == name)
+            .map(|field| field.value.as_str())
+    }
+}
+
+/// Form field whose submitted value could not be parsed
+#[derive(Debug, PartialEq)]
+struct RejectedField {
+    name: String,
+    value: String,
 }
 
 #[derive(Debug)]
 struct ConfigTemplateData {
-    port: u16,
-    request_timeout: u64,
+    port: String,
+    request_timeout: String,
     worker_queue_size: String,
     model_selection_type: String,
     builtin_model: String,
@@ -403,13 +1382,14 @@ struct ConfigTemplateData {
     custom_model_type: String,
     custom_object_classes: String,
     object_filter_str: String,
-    confidence_threshold: f32,
+    object_exclude_str: String,
+    confidence_threshold: String,
     log_level: String,
     log_path: String,
     force_cpu: bool,
-    gpu_index: i32,
-    intra_threads: usize,
-    inter_threads: usize,
+    gpu_index: String,
+    intra_threads: String,
+    inter_threads: String,
     save_image_path: String,
     save_ref_image: bool,
     save_stats_path: String,
@@ -441,27 +1421,37 @@ async fn config_post_handler(
 
     let mut config = crate::cli::Cli::load_config(current_config_path).unwrap_or_default();
 
-    // Update configuration from form data
-    update_config_from_form_data(&mut config, &form_data);
+    // Update configuration from form data, nothing is saved if a field is invalid
+    let rejected_fields = update_config_from_form_data(&mut config, &form_data);
+    if !rejected_fields.is_empty() {
+        // Re-render what was submitted so the values can be corrected in place
+        let error_message = rejected_fields_message(&rejected_fields);
+        return render_config_template(config_template(
+            &config,
+            current_config_path,
+            "".to_string(),
+            error_message,
+            rejected_fields,
+        ))
+        .into_response();
+    }
 
     // Save the updated configuration
     match config.save_config(current_config_path) {
-        Ok(()) => {
-            show_config_form(
-                "Configuration saved successfully!".to_string(),
-                "".to_string(),
-                current_config_path,
-            )
-            .await
-        }
-        Err(e) => {
-            show_config_form(
-                "".to_string(),
-                format!("Failed to save configuration: {e}"),
-                current_config_path,
-            )
-            .await
-        }
+        Ok(()) => show_config_form(
+            "Configuration saved successfully!".to_string(),
+            "".to_string(),
+            current_config_path,
+        )
+        .await
+        .into_response(),
+        Err(e) => show_config_form(
+            "".to_string(),
+            format!("Failed to save configuration: {e}"),
+            current_config_path,
+        )
+        .await
+        .into_response(),
     }
 }
 
@@ -486,7 +1476,16 @@ async fn config_restart_handler(
     let mut config = crate::cli::Cli::load_config(current_config_path).unwrap_or_default();
 
     // Apply all form updates (complete parsing logic from config_post_handler)
-    update_config_from_form_data(&mut config, &form_data); // Save configuration
+    let rejected_fields = update_config_from_form_data(&mut config, &form_data);
+    if !rejected_fields.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            rejected_fields_message(&rejected_fields),
+        )
+            .into_response();
+    }
+
+    // Save configuration
     match config.save_config(current_config_path) {
         Ok(()) => {
             info!("Configuration saved, triggering server restart...");
@@ -570,6 +1569,20 @@ async fn config_restart_handler(
     }
 }
 
+/// Restart the server and reload the detector without changing the configuration,
+/// e.g. after dropping in a new model file.
+async fn admin_restart_handler(State(state): State<Arc<ServerState>>) -> impl IntoResponse {
+    info!("Restart requested via admin endpoint");
+    state.restart_token.cancel();
+    (
+        StatusCode::OK,
+        Json(serde_json::json!({
+            "success": true,
+            "message": "Server restart initiated. Please wait...",
+        })),
+    )
+}
+
 #[derive(Deserialize)]
 struct LogLevelRequest {
     log_level: crate::LogLevel,
@@ -608,14 +1621,26 @@ async fn show_config_form(
     error_message: String,
     config_path: &Path,
 ) -> impl IntoResponse + use<> {
-    const LOGO: &[u8] = include_bytes!("../assets/logo_large.png");
-    let encoded_logo = general_purpose::STANDARD.encode(LOGO);
-    let logo_data = format!("data:image/png;base64,{encoded_logo}");
-
     // Use the provided config path instead of trying to get the default
-    let current_config_path = config_path.to_path_buf();
-    let config = crate::cli::Cli::load_config(&current_config_path).unwrap_or_default();
+    let config = crate::cli::Cli::load_config(&config_path.to_path_buf()).unwrap_or_default();
+    render_config_template(config_template(
+        &config,
+        config_path,
+        success_message,
+        error_message,
+        Vec::new(),
+    ))
+}
 
+/// Builds the config page for `config`, rejected fields keep their submitted
+/// values so they can be corrected
+fn config_template(
+    config: &crate::cli::Cli,
+    config_path: &Path,
+    success_message: String,
+    error_message: String,
+    rejected_fields: Vec<RejectedField>,
+) -> ConfigTemplate {
     // Determine if using builtin or custom model
     let (
         model_selection_type,
@@ -648,12 +1673,16 @@ async fn show_config_form(
                     "custom".to_string(),
                     String::new(),
                     model_path.to_string_lossy().to_string(),
-                    config.object_detection_model_type.to_string(),
                     config
-                        .object_classes
+                        .object_detection_model_type
                         .as_ref()
+                        .map_or_else(|| "Auto".to_string(), |t| format!("{t:?}")),
+                    config
+                        .object_classes
+                        .iter()
                         .map(|p| p.to_string_lossy().to_string())
-                        .unwrap_or_default(),
+                        .collect::<Vec<_>>()
+                        .join(","),
                 )
             }
         } else {
@@ -676,9 +1705,9 @@ async fn show_config_form(
             String::new(),
         )
     };
-    let config_data = ConfigTemplateData {
-        port: config.port,
-        request_timeout: config.request_timeout.as_secs(),
+    let mut config_data = ConfigTemplateData {
+        port: config.port.to_string(),
+        request_timeout: config.request_timeout.as_secs().to_string(),
         worker_queue_size: config
             .worker_queue_size
             .map(|v| v.to_string())
@@ -689,7 +1718,8 @@ async fn show_config_form(
         custom_model_type,
         custom_object_classes,
         object_filter_str: config.object_filter.join(", "),
-        confidence_threshold: config.confidence_threshold,
+        object_exclude_str: config.object_exclude.join(", "),
+        confidence_threshold: config.confidence_threshold.to_string(),
         log_level: format!("{:?}", config.log_level),
         log_path: config
             .log_path
@@ -697,9 +1727,9 @@ async fn show_config_form(
             .map(|p| p.to_string_lossy().to_string())
             .unwrap_or_default(),
         force_cpu: config.force_cpu,
-        gpu_index: config.gpu_index,
-        intra_threads: config.intra_threads,
-        inter_threads: config.inter_threads,
+        gpu_index: config.gpu_index.to_string(),
+        intra_threads: config.intra_threads.to_string(),
+        inter_threads: config.inter_threads.to_string(),
         save_image_path: config
             .save_image_path
             .as_ref()
@@ -714,15 +1744,27 @@ async fn show_config_form(
         is_windows: cfg!(target_os = "windows"),
     };
 
-    let template = ConfigTemplate {
-        logo_data,
+    for field in &rejected_fields {
+        let value = field.value.clone();
+        match field.name.as_str() {
+            "port" => config_data.port = value,
+            "request_timeout" => config_data.request_timeout = value,
+            "worker_queue_size" => config_data.worker_queue_size = value,
+            "confidence_threshold" => config_data.confidence_threshold = value,
+            "gpu_index" => config_data.gpu_index = value,
+            "intra_threads" => config_data.intra_threads = value,
+            "inter_threads" => config_data.inter_threads = value,
+            _ => {}
+        }
+    }
+
+    ConfigTemplate {
         config: config_data,
-        config_path: current_config_path.to_string_lossy().to_string(),
+        config_path: config_path.to_string_lossy().to_string(),
         success_message,
         error_message,
-    };
-
-    render_config_template(template)
+        rejected_fields,
+    }
 }
 
 fn render_config_template(template: ConfigTemplate) -> impl IntoResponse {
@@ -768,9 +1810,14 @@ struct VersionJson {
 }
 
 pub async fn get_latest_release_info() -> anyhow::Result<(String, String)> {
-    let response =
-        reqwest::get("https://github.com/xnorpx/blue-onyx/releases/latest/download/version.json")
-            .await?;
+    let client = reqwest::Client::builder()
+        .timeout(LATEST_RELEASE_INFO_TIMEOUT)
+        .build()?;
+    let response = client
+        .get("https://github.com/xnorpx/blue-onyx/releases/latest/download/version.json")
+        .send()
+        .await?
+        .error_for_status()?;
     let version_info: VersionJson = response.json().await?;
     let latest_release_version_str = version_info.version;
     let release_notes_url =
@@ -786,6 +1833,9 @@ pub struct Metrics {
     execution_provider_name: String,
     number_of_requests: u128,
     dropped_requests: u128,
+    queue_length: usize,
+    peak_queue_length: usize,
+    queue_capacity: usize,
     total_inference_ms: u128,
     min_inference_ms: i32,
     max_inference_ms: i32,
@@ -795,6 +1845,14 @@ pub struct Metrics {
     total_analysis_round_trip_ms: u128,
     min_analysis_round_trip_ms: i32,
     max_analysis_round_trip_ms: i32,
+    /// DirectML device id of the GPU the default detector runs on
+    gpu_index: Option<usize>,
+    /// Images received by the detection handlers, for all models
+    number_of_images: u64,
+    total_image_bytes: u128,
+    peak_image_bytes: usize,
+    /// Received images per format, e.g. jpeg
+    image_formats: BTreeMap<&'static str, u64>,
 }
 
 impl Metrics {
@@ -806,6 +1864,9 @@ impl Metrics {
             execution_provider_name: execution_provider,
             number_of_requests: 0,
             dropped_requests: 0,
+            queue_length: 0,
+            peak_queue_length: 0,
+            queue_capacity: 0,
             total_inference_ms: 0,
             min_inference_ms: i32::MAX,
             max_inference_ms: i32::MIN,
@@ -815,6 +1876,11 @@ impl Metrics {
             total_analysis_round_trip_ms: 0,
             min_analysis_round_trip_ms: i32::MAX,
             max_analysis_round_trip_ms: i32::MIN,
+            gpu_index: None,
+            number_of_images: 0,
+            total_image_bytes: 0,
+            peak_image_bytes: 0,
+            image_formats: BTreeMap::new(),
         }
     }
 
@@ -849,10 +1915,61 @@ impl Metrics {
             .max(response.analysisRoundTripMs);
     }
 
+    /// Counts an image received by a detection handler, requests without an
+    /// image are not counted
+    fn update_image_metrics(&mut self, image_data: &[u8]) {
+        if image_data.is_empty() {
+            return;
+        }
+        self.number_of_images = self.number_of_images.wrapping_add(1);
+        self.total_image_bytes = self
+            .total_image_bytes
+            .wrapping_add(image_data.len() as u128);
+        self.peak_image_bytes = self.peak_image_bytes.max(image_data.len());
+        let format = match sniff_image_format(image_data) {
+            Some(image::ImageFormat::Jpeg) => "jpeg",
+            Some(image::ImageFormat::Png) => "png",
+            Some(image::ImageFormat::Bmp) => "bmp",
+            Some(image::ImageFormat::Tiff) => "tiff",
+            _ => "unknown",
+        };
+        *self.image_formats.entry(format).or_default() += 1;
+    }
+
+    fn avg_image_kb(&self) -> u64 {
+        if self.number_of_images == 0 {
+            0
+        } else {
+            (self.total_image_bytes / self.number_of_images as u128 / 1024) as u64
+        }
+    }
+
+    fn peak_image_kb(&self) -> usize {
+        self.peak_image_bytes / 1024
+    }
+
+    /// Received images per format, e.g. "jpeg 120, png 3"
+    fn image_formats_summary(&self) -> String {
+        if self.image_formats.is_empty() {
+            return "none".to_string();
+        }
+        self.image_formats
+            .iter()
+            .map(|(format, count)| format!("{format} {count}"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
     fn update_dropped_requests(&mut self) {
         self.dropped_requests = self.dropped_requests.wrapping_add(1);
     }
 
+    fn update_queue_length(&mut self, queue_length: usize, queue_capacity: usize) {
+        self.queue_length = queue_length;
+        self.peak_queue_length = self.peak_queue_length.max(queue_length);
+        self.queue_capacity = queue_capacity;
+    }
+
     fn avg_ms(&self, total_ms: u128) -> i32 {
         if self.number_of_requests == 0 {
             0
@@ -874,52 +1991,315 @@ impl Metrics {
     }
     pub fn update_detector_info(&mut self, detector_info: &DetectorInfo) {
         self.model_name = detector_info.model_name.clone();
-        self.execution_provider_name = match &detector_info.execution_provider {
-            ExecutionProvider::CPU => "CPU".to_string(),
-            #[cfg(windows)]
-            ExecutionProvider::DirectML(index) => format!("DirectML(GPU {index})"),
+        self.execution_provider_name = detector_info.execution_provider.to_string();
+        self.gpu_index = detector_info.execution_provider.gpu_index();
+    }
+
+    /// Current video memory usage of the GPU, None when running on CPU
+    fn gpu_memory(&self) -> Option<GpuMemoryInfo> {
+        let gpu_index = self.gpu_index?;
+        gpu_memory_info(gpu_index)
+            .inspect_err(|err| debug!(?err, "Failed to query GPU memory"))
+            .ok()
+    }
+
+    /// The metrics in the Prometheus text exposition format
+    fn to_prometheus_text(&self) -> String {
+        use std::fmt::Write;
+
+        let escape = |value: &str| {
+            value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n")
         };
+        // Writing to a String can't fail
+        let mut text = String::new();
+        // The info metric carries its values as labels
+        let _ = writeln!(
+            text,
+            "# HELP blue_onyx_info Version, model and execution provider of the default detector"
+        );
+        let _ = writeln!(text, "# TYPE blue_onyx_info gauge");
+        let _ = writeln!(
+            text,
+            "blue_onyx_info{{version=\"{}\",model=\"{}\",execution_provider=\"{}\"}} 1",
+            escape(&self.version),
+            escape(&self.model_name),
+            escape(&self.execution_provider_name)
+        );
+        let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+            let _ = writeln!(text, "# HELP {name} {help}");
+            let _ = writeln!(text, "# TYPE {name} {kind}");
+            let _ = writeln!(text, "{name} {value}");
+        };
+        metric(
+            "blue_onyx_uptime_seconds",
+            "gauge",
+            "Time since the server started",
+            self.start_time.elapsed().as_secs().to_string(),
+        );
+        metric(
+            "blue_onyx_requests_total",
+            "counter",
+            "Detection requests handled by the default detector",
+            self.number_of_requests.to_string(),
+        );
+        metric(
+            "blue_onyx_dropped_requests_total",
+            "counter",
+            "Detection requests dropped because of overload or timeouts",
+            self.dropped_requests.to_string(),
+        );
+        metric(
+            "blue_onyx_queue_length",
+            "gauge",
+            "Requests waiting in the worker queue",
+            self.queue_length.to_string(),
+        );
+        metric(
+            "blue_onyx_queue_peak_length",
+            "gauge",
+            "Most requests waiting in the worker queue since the server started",
+            self.peak_queue_length.to_string(),
+        );
+        metric(
+            "blue_onyx_queue_capacity",
+            "gauge",
+            "Capacity of the worker queue",
+            self.queue_capacity.to_string(),
+        );
+        metric(
+            "blue_onyx_inference_milliseconds_total",
+            "counter",
+            "Total time spent on inference",
+            self.total_inference_ms.to_string(),
+        );
+        metric(
+            "blue_onyx_processing_milliseconds_total",
+            "counter",
+            "Total time spent processing images, including inference",
+            self.total_processing_ms.to_string(),
+        );
+        metric(
+            "blue_onyx_analysis_round_trip_milliseconds_total",
+            "counter",
+            "Total time from receiving requests until their detection finished",
+            self.total_analysis_round_trip_ms.to_string(),
+        );
+        metric(
+            "blue_onyx_image_bytes_total",
+            "counter",
+            "Total size of the images received for detection",
+            self.total_image_bytes.to_string(),
+        );
+        metric(
+            "blue_onyx_image_peak_bytes",
+            "gauge",
+            "Largest image received for detection since the server started",
+            self.peak_image_bytes.to_string(),
+        );
+        let _ = writeln!(
+            text,
+            "# HELP blue_onyx_images_total Images received for detection by format"
+        );
+        let _ = writeln!(text, "# TYPE blue_onyx_images_total counter");
+        for (format, count) in &self.image_formats {
+            let _ = writeln!(
+                text,
+                "blue_onyx_images_total{{format=\"{format}\"}} {count}"
+            );
+        }
+        text
     }
 }
 
 impl ServerState {
-    /// Extract the worker thread handle for clean shutdown
-    /// Returns the handle if the detector is ready, None otherwise
-    pub async fn take_worker_thread_handle(&self) -> Option<std::thread::JoinHandle<()>> {
-        let mut detector_ready = self.detector_ready.lock().await;
-        match &mut *detector_ready {
-            DetectorReady::Ready {
-                worker_thread_handle,
+    /// Get the detector for a model name, None selects the default detector
+    fn detector(&self, model_name: Option<&str>) -> Option<&Mutex<DetectorReady>> {
+        match model_name {
+            None => Some(&self.detector_ready),
+            Some(name) => self.named_detectors.get(name),
+        }
+    }
+
+    /// Extract the worker thread handles of all detectors for clean shutdown.
+    /// The queue senders are dropped as well, so the workers finish the
+    /// requests still in the queue and then exit.
+    pub async fn take_worker_thread_handles(&self) -> Vec<std::thread::JoinHandle<()>> {
+        let mut handles = Vec::new();
+        for detector in std::iter::once(&self.detector_ready).chain(self.named_detectors.values()) {
+            let mut detector_ready = detector.lock().await;
+            if let DetectorReady::Ready {
+                worker_thread_handles,
                 ..
-            } => worker_thread_handle.take(),
-            _ => None,
+            } = std::mem::replace(&mut *detector_ready, DetectorReady::NotReady)
+            {
+                handles.extend(worker_thread_handles);
+            }
         }
+        handles
     }
 }
 
-async fn update_dropped_requests(server_state: Arc<ServerState>) {
+async fn update_dropped_requests(server_state: Arc<ServerState>, is_default_detector: bool) {
     warn!(
         "If you see this message spamming you should reduce the number of requests or upgrade your service to be faster."
     );
-    let mut metrics = server_state.metrics.lock().await;
-    metrics.update_dropped_requests();
+    if is_default_detector {
+        let mut metrics = server_state.metrics.lock().await;
+        metrics.update_dropped_requests();
+    }
 }
 
 #[derive(Template)]
 #[template(path = "test.html")]
-struct TestTemplate;
+struct TestTemplate {
+    /// Data URL of the server side annotated image, empty if there is none
+    annotated_image: String,
+    /// Image URL to prefill the detect from URL form with
+    image_url: String,
+    /// Minimum confidence to prefill the detect from URL form with
+    min_confidence: f32,
+    /// Detection responses have 0-100 confidences instead of 0-1
+    confidence_percent: bool,
+}
+
+/// Minimum confidence the detect from URL form starts out with
+const DEFAULT_FORM_MIN_CONFIDENCE: f32 = 0.5;
+
+fn render_test_template(template: TestTemplate) -> Response {
+    match template.render() {
+        Ok(body) => (
+            [
+                (CACHE_CONTROL, "no-store, no-cache, must-revalidate"),
+                (axum::http::header::CONTENT_TYPE, "text/html; charset=utf-8"),
+            ],
+            body,
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Template error: {e}"),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct DetectionFormQuery {
+    image_url: String,
+    min_confidence: String,
+}
+
+/// GET form for quick manual tests from a browser, fetches the image from
+/// image_url and renders the annotated result on the test page
+async fn detection_form_handler(
+    State(server_state): State<Arc<ServerState>>,
+    Query(query): Query<DetectionFormQuery>,
+) -> Response {
+    let request_start_time = Instant::now();
+    if query.image_url.is_empty() {
+        return render_test_template(TestTemplate {
+            annotated_image: String::new(),
+            image_url: String::new(),
+            min_confidence: DEFAULT_FORM_MIN_CONFIDENCE,
+            confidence_percent: server_state.confidence_scale == ConfidenceScale::Percent,
+        });
+    }
+    let Some(min_confidence) = parse_min_confidence(&query.min_confidence) else {
+        return (StatusCode::BAD_REQUEST, INVALID_MIN_CONFIDENCE).into_response();
+    };
+    let (image_name, image_data) = match fetch_image_from_url(&query.image_url).await {
+        Ok(image) => image,
+        Err(err) => {
+            warn!(
+                ?err,
+                image_url = query.image_url,
+                "Failed to fetch image url"
+            );
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("Failed to fetch image url: {err}"),
+            )
+                .into_response();
+        }
+    };
+    let vision_request = VisionDetectionRequest {
+        // Values at or below 0.01 use the server default
+        min_confidence,
+        image_data,
+        image_name,
+        camera: None,
+        timings: false,
+        profile: None,
+        rois: vec![],
+        top_k_classes: 0,
+    };
+    annotated_test_page(
+        &server_state,
+        vision_request,
+        request_start_time,
+        query.image_url,
+    )
+    .await
+}
 
 async fn handle_upload(
     State(server_state): State<Arc<ServerState>>,
     mut multipart: Multipart,
 ) -> impl IntoResponse {
     let request_start_time = Instant::now();
+    let vision_request = match read_test_upload(&mut multipart).await {
+        Ok(vision_request) => vision_request,
+        Err(response) => return response,
+    };
+    annotated_test_page(
+        &server_state,
+        vision_request,
+        request_start_time,
+        String::new(),
+    )
+    .await
+}
+
+/// Same upload as the test page but returns the annotated image, e.g.
+/// `curl -F image=@photo.jpg http://localhost:32168/test/annotated -o annotated.jpg`
+async fn annotated_image_handler(
+    State(server_state): State<Arc<ServerState>>,
+    mut multipart: Multipart,
+) -> Response {
+    let request_start_time = Instant::now();
+    let vision_request = match read_test_upload(&mut multipart).await {
+        Ok(vision_request) => vision_request,
+        Err(response) => return response,
+    };
+    match annotate_image(&server_state, vision_request, request_start_time).await {
+        Ok(annotated) => (
+            [(
+                axum::http::header::CONTENT_TYPE,
+                server_state.output_image_format.content_type(),
+            )],
+            annotated,
+        )
+            .into_response(),
+        Err(response) => response,
+    }
+}
+
+/// Reads the image and min_confidence fields of a test page upload
+async fn read_test_upload(multipart: &mut Multipart) -> Result<VisionDetectionRequest, Response> {
+    let mut image_data = None;
+    let mut min_confidence = 0.;
     loop {
         let field_result = multipart.next_field().await;
         let field = match field_result {
             Ok(Some(f)) => f,
             Ok(None) => break,
-            Err(_) => return (StatusCode::BAD_REQUEST, "Invalid multipart field").into_response(),
+            Err(_) => {
+                return Err((StatusCode::BAD_REQUEST, "Invalid multipart field").into_response());
+            }
         };
         let name = field.name().unwrap_or("").to_string();
         if name == "image" {
@@ -927,130 +2307,222 @@ async fn handle_upload(
                 .content_type()
                 .map(|ct| ct.to_string())
                 .unwrap_or_default();
-            if content_type != IMAGE_JPEG.to_string() {
-                return (StatusCode::BAD_REQUEST, "Invalid content type").into_response();
+            if !SUPPORTED_IMAGE_CONTENT_TYPES.contains(&content_type.as_str()) {
+                return Err((StatusCode::BAD_REQUEST, "Invalid content type").into_response());
             }
 
-            let data: Bytes = match field.bytes().await {
-                Ok(d) => d,
+            match field.bytes().await {
+                Ok(d) => image_data = Some(d),
                 Err(_) => {
-                    return (StatusCode::BAD_REQUEST, "Failed to read image bytes").into_response();
+                    return Err(
+                        (StatusCode::BAD_REQUEST, "Failed to read image bytes").into_response()
+                    );
                 }
             };
-            let vision_request = VisionDetectionRequest {
-                min_confidence: 0., // This will be set to None and will use server default
-                image_data: data.clone(),
-                image_name: "image.jpg".to_string(),
+        } else if name == "min_confidence" {
+            min_confidence = match field.text().await.map(|text| parse_min_confidence(&text)) {
+                Ok(Some(confidence)) => confidence,
+                _ => {
+                    return Err((StatusCode::BAD_REQUEST, INVALID_MIN_CONFIDENCE).into_response());
+                }
             };
+        }
+    }
 
-            // Check detector state first
-            let detector_ready = server_state.detector_ready.lock().await;
+    let Some(data) = image_data else {
+        return Err((StatusCode::BAD_REQUEST, "No image field found").into_response());
+    };
+    Ok(VisionDetectionRequest {
+        // Values at or below 0.01 use the server default
+        min_confidence,
+        image_data: data,
+        image_name: "image.jpg".to_string(),
+        camera: None,
+        timings: false,
+        profile: None,
+        rois: vec![],
+        top_k_classes: 0,
+    })
+}
 
-            match &*detector_ready {
-                DetectorReady::NotReady => {
-                    return (
-                        StatusCode::SERVICE_UNAVAILABLE,
-                        "Server not ready yet, detector is still initializing",
-                    )
-                        .into_response();
+/// Runs the detection on the default detector and renders the test page with
+/// the annotated image
+async fn annotated_test_page(
+    server_state: &ServerState,
+    vision_request: VisionDetectionRequest,
+    request_start_time: Instant,
+    image_url: String,
+) -> Response {
+    let min_confidence = vision_request.min_confidence;
+    let annotated_image =
+        match annotate_image(server_state, vision_request, request_start_time).await {
+            Ok(annotated) => format!(
+                "data:{};base64,{}",
+                server_state.output_image_format.content_type(),
+                general_purpose::STANDARD.encode(annotated)
+            ),
+            Err(response) => return response,
+        };
+    render_test_template(TestTemplate {
+        annotated_image,
+        image_url,
+        min_confidence,
+        confidence_percent: server_state.confidence_scale == ConfidenceScale::Percent,
+    })
+}
+
+/// Runs the detection on the default detector and returns the image in the
+/// output image format with the predictions drawn on it
+async fn annotate_image(
+    server_state: &ServerState,
+    vision_request: VisionDetectionRequest,
+    request_start_time: Instant,
+) -> Result<Bytes, Response> {
+    let data = vision_request.image_data.clone();
+    server_state
+        .metrics
+        .lock()
+        .await
+        .update_image_metrics(&data);
+
+    // Check detector state first
+    let detector_ready = server_state.detector_ready.lock().await;
+
+    match &*detector_ready {
+        DetectorReady::NotReady => Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Server not ready yet, detector is still initializing",
+        )
+            .into_response()),
+        DetectorReady::Failed(error_msg) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Detector initialization failed: {error_msg}"),
+        )
+            .into_response()),
+        DetectorReady::Ready {
+            sender,
+            detector_info,
+            worker_thread_handles: _,
+        } => {
+            // Annotate with the same base size as the detector uses when saving
+            // images so line thickness and legend size match save_image_path.
+            let (base_width, base_height) = detector_info.input_size;
+            let detection_wait = detector_info.request_timeout.max(MIN_DETECTION_WAIT);
+            let (response_sender, receiver) = tokio::sync::oneshot::channel();
+            if let Err(err) = sender.send((vision_request, response_sender, request_start_time)) {
+                error!(?err, "Failed to send request to detection worker");
+                return Err(
+                    (StatusCode::INTERNAL_SERVER_ERROR, "Failed to send request").into_response(),
+                );
+            }
+
+            drop(detector_ready); // Release the lock before waiting
+            let result = timeout(detection_wait, receiver).await;
+
+            let mut vision_response = match result {
+                Ok(Ok(Ok(response))) => response,
+                Ok(Ok(Err(err))) => {
+                    warn!(?err, "Detection failed");
+                    return Err(BlueOnyxError::from(err).into_response());
                 }
-                DetectorReady::Failed(error_msg) => {
-                    return (
+                Ok(Err(err)) => {
+                    error!("Failed to receive vision detection response: {:?}", err);
+                    return Err((
                         StatusCode::INTERNAL_SERVER_ERROR,
-                        format!("Detector initialization failed: {error_msg}"),
+                        "Failed to receive response",
                     )
-                        .into_response();
+                        .into_response());
                 }
-                DetectorReady::Ready {
-                    sender,
-                    detector_info: _,
-                    worker_thread_handle: _,
-                } => {
-                    let (response_sender, receiver) = tokio::sync::oneshot::channel();
-                    if let Err(err) =
-                        sender.send((vision_request, response_sender, request_start_time))
-                    {
-                        error!(?err, "Failed to send request to detection worker");
-                        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to send request")
-                            .into_response();
-                    }
-
-                    drop(detector_ready); // Release the lock before waiting
-                    let result = timeout(Duration::from_secs(30), receiver).await;
+                Err(_) => {
+                    error!("Timeout while waiting for vision detection response");
+                    return Err((StatusCode::INTERNAL_SERVER_ERROR, "Timeout").into_response());
+                }
+            };
 
-                    let mut vision_response = match result {
-                        Ok(Ok(response)) => response,
-                        Ok(Err(err)) => {
-                            error!("Failed to receive vision detection response: {:?}", err);
-                            return (
-                                StatusCode::INTERNAL_SERVER_ERROR,
-                                "Failed to receive response",
-                            )
-                                .into_response();
-                        }
-                        Err(_) => {
-                            error!("Timeout while waiting for vision detection response");
-                            return (StatusCode::INTERNAL_SERVER_ERROR, "Timeout").into_response();
-                        }
-                    };
+            vision_response.analysisRoundTripMs = request_start_time.elapsed().as_millis() as i32;
 
-                    let template = TestTemplate;
+            {
+                let mut metrics = server_state.metrics.lock().await;
+                metrics.update_metrics(&vision_response);
+            }
+            draw_boundary_boxes_on_encoded_image(
+                data,
+                &vision_response.predictions,
+                base_width as u32,
+                base_height as u32,
+                server_state.output_image_format,
+            )
+            .map_err(|err| {
+                warn!(?err, "Failed to annotate uploaded image");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Failed to annotate image",
+                )
+                    .into_response()
+            })
+        }
+    }
+}
 
-                    vision_response.analysisRoundTripMs =
-                        request_start_time.elapsed().as_millis() as i32;
+/// Errors returned by the API handlers, each maps to its own status code so
+/// clients can tell their own bugs from an overloaded or starting server
+enum BlueOnyxError {
+    /// Malformed request fields, client bugs should not show up as server errors
+    BadRequest(String),
+    /// The detector is still initializing or failed to initialize
+    NotReady(String),
+    /// The worker queue is saturated, sent with a Retry-After header so
+    /// well-behaved clients can back off instead of retrying immediately
+    QueueFull,
+    /// No detection response within the request timeout
+    Timeout,
+    Internal(anyhow::Error),
+}
 
-                    {
-                        let mut metrics = server_state.metrics.lock().await;
-                        metrics.update_metrics(&vision_response);
-                    }
-                    match template.render() {
-                        Ok(body) => {
-                            return (
-                                [
-                                    (CACHE_CONTROL, "no-store, no-cache, must-revalidate"),
-                                    (axum::http::header::CONTENT_TYPE, "text/html; charset=utf-8"),
-                                ],
-                                body,
-                            )
-                                .into_response();
-                        }
-                        Err(e) => {
-                            return (
-                                StatusCode::INTERNAL_SERVER_ERROR,
-                                format!("Template error: {e}"),
-                            )
-                                .into_response();
-                        }
-                    }
-                }
+impl BlueOnyxError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            BlueOnyxError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            BlueOnyxError::NotReady(_) | BlueOnyxError::QueueFull => {
+                StatusCode::SERVICE_UNAVAILABLE
             }
+            BlueOnyxError::Timeout => StatusCode::GATEWAY_TIMEOUT,
+            BlueOnyxError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
-    (StatusCode::BAD_REQUEST, "No image field found").into_response()
 }
 
-struct BlueOnyxError(anyhow::Error);
-
 impl IntoResponse for BlueOnyxError {
     fn into_response(self) -> Response {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
+        let status_code = self.status_code();
+        let queue_full = matches!(self, BlueOnyxError::QueueFull);
+        let (message, error) = match self {
+            BlueOnyxError::BadRequest(error) => ("Invalid request", error),
+            BlueOnyxError::NotReady(error) => ("Detector is not ready, retry later", error),
+            BlueOnyxError::QueueFull => (
+                "Server is overloaded, retry later",
+                "Worker queue is full".into(),
+            ),
+            BlueOnyxError::Timeout => ("", "Operation timed out".into()),
+            BlueOnyxError::Internal(error) => ("", error.to_string()),
+        };
+        let mut response = (
+            status_code,
             Json(VisionDetectionResponse {
                 success: false,
-                message: "".into(),
-                error: Some(self.0.to_string()),
-                predictions: vec![],
-                count: 0,
-                command: "".into(),
-                moduleId: "".into(),
-                executionProvider: "".into(),
-                canUseGPU: false,
-                inferenceMs: 0_i32,
-                processMs: 0_i32,
-                analysisRoundTripMs: 0_i32,
+                message: message.into(),
+                error: Some(error),
+                command: "detect".into(),
+                ..Default::default()
             }),
         )
-            .into_response()
+            .into_response();
+        if queue_full {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(QUEUE_FULL_RETRY_AFTER_SECS));
+        }
+        response
     }
 }
 
@@ -1059,7 +2531,26 @@ where
     E: Into<anyhow::Error>,
 {
     fn from(err: E) -> Self {
-        Self(err.into())
+        // A malformed multipart body or an image that can not be decoded is
+        // the client's fault
+        let err = err.into();
+        if err.downcast_ref::<InvalidImage>().is_some() {
+            return Self::BadRequest(format!("{err:#}"));
+        }
+        match err.downcast::<MultipartError>() {
+            Ok(err) => Self::BadRequest(err.body_text()),
+            Err(err) => Self::Internal(err),
+        }
+    }
+}
+
+/// Rejects profile names that are not configured before the request is queued
+fn check_profile(profile: Option<&str>, profiles: &[String]) -> Result<(), BlueOnyxError> {
+    match profile {
+        Some(profile) if !profiles.iter().any(|name| name == profile) => Err(
+            BlueOnyxError::BadRequest(format!("Unknown profile: {profile}")),
+        ),
+        _ => Ok(()),
     }
 }
 
@@ -1082,30 +2573,62 @@ async fn bootstrap_icons_css_handler() -> impl IntoResponse {
         .into_response()
 }
 
-/// Helper function to parse form data and update configuration
+fn rejected_fields_message(rejected_fields: &[RejectedField]) -> String {
+    let fields = rejected_fields
+        .iter()
+        .map(|field| format!("{} '{}'", field.name, field.value))
+        .collect::<Vec<_>>();
+    format!(
+        "Configuration not saved, invalid values: {}",
+        fields.join(", ")
+    )
+}
+
+/// Parses a numeric form field, a value that fails to parse is added to
+/// `rejected_fields` and the current setting is kept
+fn parse_form_field<T: std::str::FromStr>(
+    form_data: &std::collections::HashMap<String, String>,
+    name: &str,
+    rejected_fields: &mut Vec<RejectedField>,
+) -> Option<T> {
+    let value = form_data.get(name)?;
+    match value.trim().parse() {
+        Ok(value) => Some(value),
+        Err(_) => {
+            rejected_fields.push(RejectedField {
+                name: name.to_string(),
+                value: value.clone(),
+            });
+            None
+        }
+    }
+}
+
+/// Helper function to parse form data and update configuration, returns the
+/// fields whose values could not be parsed
 fn update_config_from_form_data(
     config: &mut crate::cli::Cli,
     form_data: &std::collections::HashMap<String, String>,
-) {
+) -> Vec<RejectedField> {
+    let mut rejected_fields = Vec::new();
+
     // Basic server configuration
-    if let Some(port_str) = form_data.get("port")
-        && let Ok(port) = port_str.parse::<u16>()
-    {
+    if let Some(port) = parse_form_field(form_data, "port", &mut rejected_fields) {
         config.port = port;
     }
 
-    if let Some(timeout_str) = form_data.get("request_timeout")
-        && let Ok(timeout) = timeout_str.parse::<u64>()
-    {
+    if let Some(timeout) = parse_form_field(form_data, "request_timeout", &mut rejected_fields) {
         config.request_timeout = std::time::Duration::from_secs(timeout);
     }
 
     if let Some(queue_str) = form_data.get("worker_queue_size") {
-        config.worker_queue_size = if queue_str.is_empty() {
-            None
-        } else {
-            queue_str.parse::<usize>().ok()
-        };
+        if queue_str.trim().is_empty() {
+            config.worker_queue_size = None;
+        } else if let Some(queue_size) =
+            parse_form_field(form_data, "worker_queue_size", &mut rejected_fields)
+        {
+            config.worker_queue_size = Some(queue_size);
+        }
     }
 
     // Model configuration
@@ -1119,20 +2642,18 @@ fn update_config_from_form_data(
                     config.model = Some(PathBuf::from(builtin_model));
 
                     // Determine model type and set object classes based on the model
-                    if builtin_model.starts_with("rt-detr") {
-                        config.object_detection_model_type =
-                            crate::detector::ObjectDetectionModel::RtDetrv2;
-                    } else if builtin_model.starts_with("rf-detr") {
-                        config.object_detection_model_type =
-                            crate::detector::ObjectDetectionModel::RfDetr;
-                    } else {
-                        config.object_detection_model_type =
-                            crate::detector::ObjectDetectionModel::Yolo5;
-                    }
+                    config.object_detection_model_type =
+                        Some(if builtin_model.starts_with("rt-detr") {
+                            crate::detector::ObjectDetectionModel::RtDetrv2
+                        } else if builtin_model.starts_with("rf-detr") {
+                            crate::detector::ObjectDetectionModel::RfDetr
+                        } else {
+                            crate::detector::ObjectDetectionModel::Yolo5
+                        });
 
                     // Set corresponding YAML file
                     let yaml_file = builtin_model.replace(".onnx", ".yaml");
-                    config.object_classes = Some(PathBuf::from(yaml_file));
+                    config.object_classes = vec![PathBuf::from(yaml_file)];
                 }
             }
             "custom" => {
@@ -1147,18 +2668,21 @@ fn update_config_from_form_data(
 
                 if let Some(custom_model_type) = form_data.get("custom_model_type") {
                     config.object_detection_model_type = match custom_model_type.as_str() {
-                        "Yolo5" => crate::detector::ObjectDetectionModel::Yolo5,
-                        "RfDetr" => crate::detector::ObjectDetectionModel::RfDetr,
-                        _ => crate::detector::ObjectDetectionModel::RtDetrv2,
+                        "Auto" => None,
+                        "Yolo5" => Some(crate::detector::ObjectDetectionModel::Yolo5),
+                        "Yolo8" => Some(crate::detector::ObjectDetectionModel::Yolo8),
+                        "RfDetr" => Some(crate::detector::ObjectDetectionModel::RfDetr),
+                        _ => Some(crate::detector::ObjectDetectionModel::RtDetrv2),
                     };
                 }
 
                 if let Some(custom_classes_str) = form_data.get("custom_object_classes") {
-                    config.object_classes = if custom_classes_str.is_empty() {
-                        None
-                    } else {
-                        Some(PathBuf::from(custom_classes_str))
-                    };
+                    config.object_classes = custom_classes_str
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|path| !path.is_empty())
+                        .map(PathBuf::from)
+                        .collect();
                 }
             }
             _ => {}
@@ -1177,8 +2701,19 @@ fn update_config_from_form_data(
         };
     }
 
-    if let Some(confidence_str) = form_data.get("confidence_threshold")
-        && let Ok(confidence) = confidence_str.parse::<f32>()
+    if let Some(exclude_str) = form_data.get("object_exclude") {
+        config.object_exclude = if exclude_str.is_empty() {
+            Vec::new()
+        } else {
+            exclude_str
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .collect()
+        };
+    }
+
+    if let Some(confidence) =
+        parse_form_field(form_data, "confidence_threshold", &mut rejected_fields)
     {
         config.confidence_threshold = confidence;
     }
@@ -1205,20 +2740,16 @@ fn update_config_from_form_data(
     // Performance configuration
     config.force_cpu = form_data.contains_key("force_cpu");
 
-    if let Some(gpu_str) = form_data.get("gpu_index")
-        && let Ok(gpu_index) = gpu_str.parse::<i32>()
-    {
+    if let Some(gpu_index) = parse_form_field(form_data, "gpu_index", &mut rejected_fields) {
         config.gpu_index = gpu_index;
     }
 
-    if let Some(intra_str) = form_data.get("intra_threads")
-        && let Ok(intra_threads) = intra_str.parse::<usize>()
+    if let Some(intra_threads) = parse_form_field(form_data, "intra_threads", &mut rejected_fields)
     {
         config.intra_threads = intra_threads;
     }
 
-    if let Some(inter_str) = form_data.get("inter_threads")
-        && let Ok(inter_threads) = inter_str.parse::<usize>()
+    if let Some(inter_threads) = parse_form_field(form_data, "inter_threads", &mut rejected_fields)
     {
         config.inter_threads = inter_threads;
     }
@@ -1241,4 +2772,264 @@ fn update_config_from_form_data(
             Some(PathBuf::from(save_stats_str))
         };
     }
+
+    rejected_fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::{Image, decode_image};
+
+    /// Error of a multipart body that ends before its closing boundary
+    fn malformed_multipart_error() -> MultipartError {
+        let request = Request::builder()
+            .header(CONTENT_TYPE, "multipart/form-data; boundary=X")
+            .body(Body::from(
+                "--X\r\nContent-Disposition: form-data; name=\"image\"\r\n\r\nabc",
+            ))
+            .unwrap();
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(async {
+                let mut multipart = Multipart::from_request(request, &()).await.unwrap();
+                match multipart.next_field().await {
+                    Ok(Some(field)) => field.bytes().await.unwrap_err(),
+                    Ok(None) => panic!("Expected an image field"),
+                    Err(err) => err,
+                }
+            })
+    }
+
+    #[test]
+    fn test_error_status_codes() {
+        assert_eq!(
+            BlueOnyxError::from(malformed_multipart_error()).status_code(),
+            StatusCode::BAD_REQUEST
+        );
+        let mut image = Image::default();
+        let invalid_image =
+            decode_image(None, Bytes::from_static(b"not an image"), &mut image, false).unwrap_err();
+        let response = BlueOnyxError::from(invalid_image).into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            BlueOnyxError::from(anyhow::anyhow!("Inference failed")).status_code(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(
+            BlueOnyxError::NotReady("Starting".into()).status_code(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            BlueOnyxError::Timeout.status_code(),
+            StatusCode::GATEWAY_TIMEOUT
+        );
+
+        let response = BlueOnyxError::QueueFull.into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(response.headers().contains_key(RETRY_AFTER));
+    }
+
+    fn test_server_state() -> Arc<ServerState> {
+        Arc::new(ServerState {
+            detector_ready: Mutex::new(DetectorReady::NotReady),
+            named_detectors: HashMap::new(),
+            metrics: Mutex::new(Metrics::new("model".into(), "CPU".into())),
+            restart_token: CancellationToken::new(),
+            config_path: PathBuf::new(),
+            detection_events: broadcast::channel(DETECTION_EVENT_BUFFER_SIZE).0,
+            cancellation_token: CancellationToken::new(),
+            confidence_scale: ConfidenceScale::Fraction,
+            module_id: None,
+            module_name: None,
+            restart_on_worker_failure: false,
+            models_dir: None,
+            port: 32168,
+            output_image_format: OutputImageFormat::Jpeg,
+        })
+    }
+
+    #[test]
+    fn test_to_prometheus_text() {
+        let mut metrics = Metrics::new("yolo\"v8".into(), "CPU".into());
+        metrics.update_metrics(&VisionDetectionResponse {
+            inferenceMs: 20,
+            processMs: 30,
+            analysisRoundTripMs: 40,
+            ..Default::default()
+        });
+        metrics.update_dropped_requests();
+        metrics.update_image_metrics(&[0xFF, 0xD8, 0xFF, 0xE0]);
+        let text = metrics.to_prometheus_text();
+        let lines: Vec<&str> = text.lines().collect();
+
+        assert!(lines.contains(&"# TYPE blue_onyx_requests_total counter"));
+        assert!(lines.contains(&"blue_onyx_requests_total 1"));
+        assert!(lines.contains(&"blue_onyx_dropped_requests_total 1"));
+        assert!(lines.contains(&"blue_onyx_inference_milliseconds_total 20"));
+        assert!(lines.contains(&"blue_onyx_processing_milliseconds_total 30"));
+        assert!(lines.contains(&"blue_onyx_image_bytes_total 4"));
+        // Label values are escaped
+        assert!(lines.iter().any(|line| line.starts_with(&format!(
+            "blue_onyx_info{{version=\"{}\",model=\"yolo\\\"v8\"",
+            env!("CARGO_PKG_VERSION")
+        ))));
+        // Every sample has its HELP and TYPE
+        for line in lines.iter().filter(|line| !line.starts_with('#')) {
+            let name = line.split(['{', ' ']).next().unwrap();
+            assert!(text.contains(&format!("# TYPE {name} ")), "{name}");
+            assert!(text.contains(&format!("# HELP {name} ")), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_push_metrics_keeps_running_when_the_gateway_is_down() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            // Nothing listens on the port once the listener is dropped
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let push_url = format!(
+                "http://{}/metrics/job/blue_onyx",
+                listener.local_addr().unwrap()
+            );
+            drop(listener);
+
+            let cancellation_token = CancellationToken::new();
+            let push_task = tokio::spawn(push_metrics(
+                test_server_state(),
+                push_url,
+                Duration::from_millis(10),
+                cancellation_token.clone(),
+                CancellationToken::new(),
+            ));
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            assert!(!push_task.is_finished());
+
+            cancellation_token.cancel();
+            timeout(Duration::from_secs(5), push_task)
+                .await
+                .unwrap()
+                .unwrap();
+        });
+    }
+
+    #[test]
+    fn test_check_profile() {
+        let profiles = ["night".to_string()];
+        assert!(check_profile(None, &profiles).is_ok());
+        assert!(check_profile(Some("night"), &profiles).is_ok());
+        let err = check_profile(Some("day"), &profiles).unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+    }
+
+    fn form(fields: &[(&str, &str)]) -> std::collections::HashMap<String, String> {
+        fields
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_form_field() {
+        let form_data = form(&[("port", " 8080 "), ("gpu_index", "abc")]);
+        let mut rejected_fields = Vec::new();
+
+        assert_eq!(
+            parse_form_field::<u16>(&form_data, "port", &mut rejected_fields),
+            Some(8080)
+        );
+        assert_eq!(
+            parse_form_field::<i32>(&form_data, "gpu_index", &mut rejected_fields),
+            None
+        );
+        assert_eq!(
+            parse_form_field::<usize>(&form_data, "intra_threads", &mut rejected_fields),
+            None
+        );
+        assert_eq!(
+            rejected_fields,
+            vec![RejectedField {
+                name: "gpu_index".to_string(),
+                value: "abc".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_update_config_from_form_data_accepts_valid_values() {
+        let mut config = crate::cli::Cli::default();
+        let form_data = form(&[
+            ("port", "8080"),
+            ("request_timeout", "30"),
+            ("worker_queue_size", ""),
+            ("confidence_threshold", "0.7"),
+            ("gpu_index", "1"),
+            ("intra_threads", "4"),
+            ("inter_threads", "2"),
+        ]);
+
+        let rejected_fields = update_config_from_form_data(&mut config, &form_data);
+
+        assert!(rejected_fields.is_empty());
+        assert_eq!(config.port, 8080);
+        assert_eq!(config.request_timeout, Duration::from_secs(30));
+        assert_eq!(config.worker_queue_size, None);
+        assert_eq!(config.confidence_threshold, 0.7);
+        assert_eq!(config.gpu_index, 1);
+        assert_eq!(config.intra_threads, 4);
+        assert_eq!(config.inter_threads, 2);
+    }
+
+    #[test]
+    fn test_update_config_from_form_data_rejects_invalid_values() {
+        let mut config = crate::cli::Cli::default();
+        let default_port = config.port;
+        let form_data = form(&[
+            ("port", "70000"),
+            ("worker_queue_size", "-1"),
+            ("confidence_threshold", "0.7"),
+        ]);
+
+        let rejected_fields = update_config_from_form_data(&mut config, &form_data);
+
+        let rejected_names = rejected_fields
+            .iter()
+            .map(|field| field.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(rejected_names, ["port", "worker_queue_size"]);
+        assert_eq!(config.port, default_port);
+        assert_eq!(config.worker_queue_size, None);
+        assert_eq!(config.confidence_threshold, 0.7);
+        assert_eq!(
+            rejected_fields_message(&rejected_fields),
+            "Configuration not saved, invalid values: port '70000', worker_queue_size '-1'"
+        );
+    }
+
+    #[test]
+    fn test_config_template_keeps_submitted_values() {
+        let mut config = crate::cli::Cli::default();
+        let form_data = form(&[("port", "70000"), ("intra_threads", "3")]);
+        let rejected_fields = update_config_from_form_data(&mut config, &form_data);
+
+        let template = config_template(
+            &config,
+            Path::new("blue_onyx_config.json"),
+            "".to_string(),
+            rejected_fields_message(&rejected_fields),
+            rejected_fields,
+        );
+
+        assert_eq!(template.config.port, "70000");
+        assert_eq!(template.config.intra_threads, "3");
+        assert_eq!(template.rejected_value("port"), Some("70000"));
+        assert_eq!(template.rejected_value("intra_threads"), None);
+        let body = template.render().unwrap();
+        assert!(body.contains(r#"name="port" value="70000""#));
+        assert!(body.contains("Invalid value '70000', not saved"));
+    }
 }
@@ -1,17 +1,37 @@
 use crate::{
-    api::VisionDetectionRequest, api::VisionDetectionResponse, detector::DetectorConfig,
-    detector::ExecutionProvider, worker::DetectorWorker,
+    api::VisionDetectionRequest,
+    api::VisionDetectionResponse,
+    benchmark::run_self_benchmark,
+    detector::DetectorConfig,
+    detector::ExecutionProvider,
+    worker::{AdaptiveQueueLimit, DetectorWorker},
 };
 use crossbeam::channel::Sender;
-use std::time::Instant;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::sync::oneshot;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 /// Information about the initialized detector
 #[derive(Debug, Clone)]
 pub struct DetectorInfo {
     pub model_name: String,
     pub execution_provider: ExecutionProvider,
+    /// Model input size (width, height), used as base size when annotating images
+    pub input_size: (usize, usize),
+    pub object_classes: Vec<String>,
+    /// Time it took to load the model and build the session
+    pub model_load_time: Duration,
+    /// Time the warmup detections took
+    pub warmup_time: Duration,
+    /// Queue limit following the live processing time, None if the queue size is fixed
+    pub queue_limit: Option<Arc<AdaptiveQueueLimit>>,
+    /// Request timeout of the workers, raised from the configured one with auto timeout
+    pub request_timeout: Duration,
+    /// Names of the detection profiles requests can select
+    pub profiles: Vec<String>,
 }
 
 /// Result of detector initialization
@@ -19,11 +39,11 @@ pub enum InitResult {
     Success {
         sender: Sender<(
             VisionDetectionRequest,
-            oneshot::Sender<VisionDetectionResponse>,
+            oneshot::Sender<anyhow::Result<VisionDetectionResponse>>,
             Instant,
         )>,
         detector_info: DetectorInfo,
-        worker_thread_handle: std::thread::JoinHandle<()>,
+        worker_thread_handles: Vec<std::thread::JoinHandle<()>>,
     },
     Failed(String),
 }
@@ -33,12 +53,13 @@ pub enum InitResult {
 pub fn spawn_detector_initialization(
     detector_config: DetectorConfig,
     worker_queue_size: Option<usize>,
+    workers: usize,
 ) -> tokio::sync::oneshot::Receiver<InitResult> {
     let (init_sender, init_receiver) = tokio::sync::oneshot::channel();
 
     // Spawn background thread to initialize detector
     std::thread::spawn(move || {
-        startup_worker_thread(init_sender, detector_config, worker_queue_size);
+        startup_worker_thread(init_sender, detector_config, worker_queue_size, workers);
     });
 
     init_receiver
@@ -50,50 +71,69 @@ fn startup_worker_thread(
     init_sender: tokio::sync::oneshot::Sender<InitResult>,
     detector_config: DetectorConfig,
     worker_queue_size: Option<usize>,
+    workers: usize,
 ) {
-    info!("Startup worker thread: Beginning detector initialization...");
+    let workers = workers.max(1);
+    info!(
+        workers,
+        "Startup worker thread: Beginning detector initialization..."
+    );
 
-    // Initialize the detector worker in this background thread
-    let init_result = DetectorWorker::new(detector_config.clone(), worker_queue_size);
+    // Initialize the detector workers in this background thread, each worker
+    // has its own detector and all of them pull from the same queue
+    let init_result = DetectorWorker::new(detector_config.clone(), worker_queue_size, workers)
+        .and_then(|(sender, mut detector_worker)| {
+            // The benchmark is informational, a failure doesn't stop startup
+            if detector_config.benchmark_on_start > 0
+                && let Err(err) = run_self_benchmark(
+                    detector_worker.get_detector_mut(),
+                    detector_config.benchmark_on_start,
+                )
+            {
+                warn!(?err, "Startup benchmark failed, continuing startup");
+            }
+            let mut pool_workers = Vec::with_capacity(workers - 1);
+            for _ in 1..workers {
+                pool_workers.push(detector_worker.new_pool_worker(detector_config.clone())?);
+            }
+            Ok((sender, detector_worker, pool_workers))
+        });
 
     match init_result {
-        Ok((sender, detector_worker)) => {
+        Ok((sender, detector_worker, pool_workers)) => {
             // Get detector information before transferring ownership
             let detector = detector_worker.get_detector();
 
-            let execution_provider = if detector_config.object_detection_onnx_config.force_cpu
-                || !detector.is_using_gpu()
-            {
-                ExecutionProvider::CPU
-            } else {
-                #[cfg(windows)]
-                {
-                    ExecutionProvider::DirectML(
-                        detector_config.object_detection_onnx_config.gpu_index as usize,
-                    )
-                }
-                #[cfg(not(windows))]
-                {
-                    ExecutionProvider::CPU
-                }
-            };
+            let execution_provider = detector.get_execution_provider();
 
             let detector_info = DetectorInfo {
                 model_name: detector.get_model_name().clone(),
-                execution_provider: execution_provider.clone(),
+                execution_provider,
+                input_size: detector.get_input_size(),
+                object_classes: detector.get_object_classes().to_vec(),
+                model_load_time: detector.get_model_load_time(),
+                warmup_time: detector.get_warmup_time(),
+                queue_limit: detector_worker.get_queue_limit(),
+                request_timeout: detector_worker.get_request_timeout(),
+                profiles: detector_config.profiles.keys().cloned().collect(),
             };
             info!(
                 model_name = %detector_info.model_name,
                 execution_provider = ?detector_info.execution_provider,
+                model_load_time = ?detector_info.model_load_time,
+                warmup_time = ?detector_info.warmup_time,
                 "Startup worker thread: Detector initialization complete, starting worker thread"
-            ); // Start the detector worker in a separate thread (this will continue running)
-            let worker_thread_handle = detector_worker.spawn_worker_thread();
+            ); // Start the detector workers in separate threads (these will continue running)
+            let worker_thread_handles = std::iter::once(detector_worker)
+                .chain(pool_workers)
+                .map(DetectorWorker::spawn_worker_thread)
+                .collect();
 
             // Hand over the sender to the server
             let result = InitResult::Success {
                 sender,
                 detector_info,
-                worker_thread_handle,
+                worker_thread_handles,
             };
             if init_sender.send(result).is_err() {
                 error!("Startup worker thread: Failed to send initialization result to server");
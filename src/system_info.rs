@@ -86,6 +86,102 @@ pub fn gpu_info(log_info: bool) -> anyhow::Result<Vec<String>> {
     Ok(gpu_names)
 }
 
+/// Video memory of a GPU as reported by DXGI, in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GpuMemoryInfo {
+    /// Local video memory used by this process
+    pub current_usage: u64,
+    /// Local video memory the OS currently lets this process use, shrinks
+    /// when other applications use the GPU
+    pub budget: u64,
+    /// Dedicated video memory of the adapter
+    pub dedicated: u64,
+}
+
+impl std::fmt::Display for GpuMemoryInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const MB: u64 = 1024 * 1024;
+        write!(
+            f,
+            "{} MB used, {} MB available of {} MB",
+            self.current_usage / MB,
+            self.budget.saturating_sub(self.current_usage) / MB,
+            self.dedicated / MB
+        )
+    }
+}
+
+#[cfg(not(windows))]
+pub fn gpu_memory_info(_index: usize) -> anyhow::Result<GpuMemoryInfo> {
+    anyhow::bail!("GPU memory info is only available on Windows")
+}
+
+/// Current video memory usage and budget of the adapter with the DirectML
+/// device id `index`.
+#[cfg(windows)]
+pub fn gpu_memory_info(index: usize) -> anyhow::Result<GpuMemoryInfo> {
+    use windows::Win32::Graphics::Dxgi::{
+        CreateDXGIFactory1, DXGI_ADAPTER_DESC1, DXGI_MEMORY_SEGMENT_GROUP_LOCAL,
+        DXGI_QUERY_VIDEO_MEMORY_INFO, IDXGIAdapter3, IDXGIFactory1,
+    };
+    use windows::core::Interface;
+    let factory: IDXGIFactory1 = unsafe { CreateDXGIFactory1().map_err(|e| anyhow::anyhow!(e))? };
+    let adapter = unsafe { factory.EnumAdapters1(index as u32) }
+        .map_err(|e| anyhow::anyhow!("No GPU adapter {index}: {e}"))?;
+    let desc: DXGI_ADAPTER_DESC1 = unsafe { adapter.GetDesc1().map_err(|e| anyhow::anyhow!(e))? };
+    // QueryVideoMemoryInfo needs DXGI 1.4, available from Windows 10
+    let adapter: IDXGIAdapter3 = adapter.cast().map_err(|e| anyhow::anyhow!(e))?;
+    let mut memory_info = DXGI_QUERY_VIDEO_MEMORY_INFO::default();
+    unsafe {
+        adapter
+            .QueryVideoMemoryInfo(0, DXGI_MEMORY_SEGMENT_GROUP_LOCAL, &mut memory_info)
+            .map_err(|e| anyhow::anyhow!(e))?
+    };
+    Ok(GpuMemoryInfo {
+        current_usage: memory_info.CurrentUsage,
+        budget: memory_info.Budget,
+        dedicated: desc.DedicatedVideoMemory as u64,
+    })
+}
+
+/// Resolve the DirectML device id of the first adapter whose description
+/// contains `name` (case-insensitive).
+#[cfg(windows)]
+pub fn gpu_index_by_name(name: &str) -> anyhow::Result<i32> {
+    use windows::Win32::Graphics::Dxgi::{CreateDXGIFactory1, DXGI_ADAPTER_DESC1, IDXGIFactory1};
+    let factory: IDXGIFactory1 = unsafe { CreateDXGIFactory1().map_err(|e| anyhow::anyhow!(e))? };
+    let name_lower = name.to_lowercase();
+    let mut adapter_index = 0;
+    let mut adapter_names = Vec::new();
+
+    while let Ok(adapter) = unsafe { factory.EnumAdapters1(adapter_index) } {
+        let desc: DXGI_ADAPTER_DESC1 =
+            unsafe { adapter.GetDesc1().map_err(|e| anyhow::anyhow!(e))? };
+        let device_name = String::from_utf16_lossy(&desc.Description)
+            .replace('\0', "")
+            .trim()
+            .to_string();
+        if device_name.to_lowercase().contains(&name_lower) {
+            info!(
+                "Resolved GPU name '{}' to adapter {}: {} (VRAM: {} MB)",
+                name,
+                adapter_index,
+                device_name,
+                desc.DedicatedVideoMemory / (1024 * 1024)
+            );
+            return Ok(adapter_index as i32);
+        }
+        adapter_names.push(device_name);
+        adapter_index += 1;
+    }
+
+    anyhow::bail!(
+        "No GPU matching '{}' found, available adapters: [{}]",
+        name,
+        adapter_names.join(", ")
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -94,6 +190,20 @@ mod tests {
         gpu_info(true).unwrap();
     }
 
+    #[test]
+    fn test_gpu_memory_info_display() {
+        const MB: u64 = 1024 * 1024;
+        let memory_info = GpuMemoryInfo {
+            current_usage: 512 * MB,
+            budget: 3584 * MB,
+            dedicated: 4096 * MB,
+        };
+        assert_eq!(
+            memory_info.to_string(),
+            "512 MB used, 3072 MB available of 4096 MB"
+        );
+    }
+
     #[test]
     fn print_cpu_info() {
         cpu_info().unwrap()
@@ -0,0 +1,204 @@
+//! Offline object detection on video files.
+//!
+//! Frames are decoded with ffmpeg, every Nth frame is encoded to JPEG and fed
+//! through the regular [`Detector`] so the same model, filters and annotation
+//! code as the server is used. Annotated frames are written by the detector
+//! and a JSON timeline of all detections is written next to them.
+
+use crate::{
+    api::Prediction,
+    cli::Cli,
+    detector::{DetectOptions, Detector},
+};
+use anyhow::{Context, anyhow};
+use bytes::Bytes;
+use ffmpeg_next::{
+    format::{Pixel, input},
+    media::Type,
+    software::scaling::{context::Context as ScalingContext, flag::Flags},
+    util::frame::video::Video,
+};
+use jpeg_encoder::{ColorType, Encoder};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+
+#[derive(Serialize)]
+pub struct FrameDetections {
+    pub frame_index: usize,
+    pub timestamp_ms: i64,
+    pub predictions: Vec<Prediction>,
+}
+
+#[derive(Serialize)]
+pub struct VideoTimeline {
+    pub video: String,
+    pub model: String,
+    pub fps_sample: usize,
+    pub frames: Vec<FrameDetections>,
+}
+
+/// Run detection on every `fps_sample` frame of `video_path` and write the
+/// annotated frames and a `<video>_timeline.json` file to the output directory.
+pub fn run_video_detection(video_path: &Path, fps_sample: usize, args: &Cli) -> anyhow::Result<()> {
+    let fps_sample = fps_sample.max(1);
+    let output_dir = video_output_dir(video_path, args.save_image_path.as_deref())?;
+    std::fs::create_dir_all(&output_dir)?;
+
+    let mut detector_config = args.detector_config();
+    detector_config.save_image_path = Some(output_dir.clone());
+    let mut detector = Detector::new(detector_config)?;
+
+    ffmpeg_next::init().context("Failed to initialize ffmpeg")?;
+    let mut input_context = input(&video_path)
+        .with_context(|| format!("Failed to open video {}", video_path.display()))?;
+    let stream = input_context
+        .streams()
+        .best(Type::Video)
+        .ok_or_else(|| anyhow!("No video stream found in {}", video_path.display()))?;
+    let stream_index = stream.index();
+    let time_base = stream.time_base();
+    let decoder_context =
+        ffmpeg_next::codec::context::Context::from_parameters(stream.parameters())?;
+    let mut decoder = decoder_context.decoder().video()?;
+    let mut scaler = ScalingContext::get(
+        decoder.format(),
+        decoder.width(),
+        decoder.height(),
+        Pixel::RGB24,
+        decoder.width(),
+        decoder.height(),
+        Flags::BILINEAR,
+    )?;
+
+    info!(
+        video = %video_path.display(),
+        width = decoder.width(),
+        height = decoder.height(),
+        fps_sample,
+        output_dir = %output_dir.display(),
+        "Running detection on video"
+    );
+
+    let video_stem = video_path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("video")
+        .to_string();
+    let mut frames = Vec::new();
+    let mut frame_index = 0;
+    let mut decoded = Video::empty();
+
+    let mut process_decoded_frames =
+        |decoder: &mut ffmpeg_next::decoder::Video| -> anyhow::Result<()> {
+            while decoder.receive_frame(&mut decoded).is_ok() {
+                if frame_index % fps_sample == 0 {
+                    let mut rgb_frame = Video::empty();
+                    scaler.run(&decoded, &mut rgb_frame)?;
+                    let jpeg = encode_rgb_frame(&rgb_frame)?;
+                    let timestamp_ms = decoded
+                        .timestamp()
+                        .map(|ts| {
+                            ts * 1000 * time_base.numerator() as i64
+                                / time_base.denominator().max(1) as i64
+                        })
+                        .unwrap_or_default();
+                    let frame_name = format!("{video_stem}_frame_{frame_index:06}.jpg");
+                    let result =
+                        detector.detect(jpeg, Some(frame_name), DetectOptions::default())?;
+                    debug!(
+                        frame_index,
+                        timestamp_ms,
+                        predictions = result.predictions.len(),
+                        "Processed video frame"
+                    );
+                    frames.push(FrameDetections {
+                        frame_index,
+                        timestamp_ms,
+                        predictions: result.predictions.to_vec(),
+                    });
+                }
+                frame_index += 1;
+            }
+            Ok(())
+        };
+
+    for (stream, packet) in input_context.packets() {
+        if stream.index() == stream_index {
+            decoder.send_packet(&packet)?;
+            process_decoded_frames(&mut decoder)?;
+        }
+    }
+    decoder.send_eof()?;
+    process_decoded_frames(&mut decoder)?;
+
+    let timeline = VideoTimeline {
+        video: video_path.to_string_lossy().to_string(),
+        model: detector.get_model_name().clone(),
+        fps_sample,
+        frames,
+    };
+    let timeline_path = output_dir.join(format!("{video_stem}_timeline.json"));
+    std::fs::write(&timeline_path, serde_json::to_string_pretty(&timeline)?)?;
+    info!(
+        frames = timeline.frames.len(),
+        timeline = %timeline_path.display(),
+        "Video detection complete"
+    );
+    Ok(())
+}
+
+fn video_output_dir(video_path: &Path, save_image_path: Option<&Path>) -> anyhow::Result<PathBuf> {
+    if let Some(save_image_path) = save_image_path {
+        return Ok(save_image_path.to_path_buf());
+    }
+    let stem = video_path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .ok_or_else(|| anyhow!("Failed to get file name from {}", video_path.display()))?;
+    let parent = video_path.parent().unwrap_or(Path::new("."));
+    Ok(parent.join(format!("{stem}_detections")))
+}
+
+/// Encode a packed RGB24 frame to JPEG, dropping any row padding ffmpeg added.
+fn encode_rgb_frame(frame: &Video) -> anyhow::Result<Bytes> {
+    let width = frame.width() as usize;
+    let height = frame.height() as usize;
+    let stride = frame.stride(0);
+    let data = frame.data(0);
+    let mut pixels = Vec::with_capacity(width * height * 3);
+    for row in data.chunks(stride).take(height) {
+        pixels.extend_from_slice(&row[..width * 3]);
+    }
+
+    let mut encoded = Vec::new();
+    let encoder = Encoder::new(&mut encoded, 90);
+    encoder.encode(&pixels, width as u16, height as u16, ColorType::Rgb)?;
+    Ok(Bytes::from(encoded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_video_output_dir() {
+        assert_eq!(
+            video_output_dir(
+                Path::new("/videos/driveway.mp4"),
+                Some(Path::new("/detections"))
+            )
+            .unwrap(),
+            PathBuf::from("/detections")
+        );
+        assert_eq!(
+            video_output_dir(Path::new("/videos/driveway.mp4"), None).unwrap(),
+            PathBuf::from("/videos/driveway_detections")
+        );
+        assert_eq!(
+            video_output_dir(Path::new("driveway.mp4"), None).unwrap(),
+            PathBuf::from("driveway_detections")
+        );
+        assert!(video_output_dir(Path::new("/"), None).is_err());
+    }
+}
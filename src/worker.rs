@@ -1,21 +1,333 @@
 use crate::{
-    api::{VisionDetectionRequest, VisionDetectionResponse},
-    detector::{Detector, DetectorConfig, DeviceType},
+    api::{DetectionTimings, Roi, VisionDetectionRequest, VisionDetectionResponse},
+    detector::{DetectOptions, DetectResult, Detector, DetectorConfig, DeviceType},
     image::create_random_jpeg_name,
 };
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
 use crossbeam::channel::{Receiver, Sender};
-use std::time::{Duration, Instant};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
 use tokio::sync::oneshot;
 use tracing::{debug, info, warn};
 
+/// Error set on responses for requests that expired in the worker queue and
+/// were dropped without running inference.
+pub const REQUEST_EXPIRED_IN_QUEUE_ERROR: &str = "Request expired in worker queue";
+
+/// Scheduling priority of the detector worker threads.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Serialize, serde::Deserialize,
+)]
+pub enum WorkerPriority {
+    /// Leave the priority unchanged
+    Normal,
+    High,
+    TimeCritical,
+}
+
+impl Default for WorkerPriority {
+    /// Time critical on Windows, unchanged elsewhere since raising the
+    /// priority needs extra privileges on Linux
+    fn default() -> Self {
+        if cfg!(windows) {
+            Self::TimeCritical
+        } else {
+            Self::Normal
+        }
+    }
+}
+
+impl WorkerPriority {
+    /// Nice value for the thread, None leaves it unchanged
+    #[cfg(target_os = "linux")]
+    fn nice(self) -> Option<libc::c_int> {
+        match self {
+            Self::Normal => None,
+            Self::High => Some(-5),
+            Self::TimeCritical => Some(-10),
+        }
+    }
+}
+
+/// With auto timeout the request timeout is at least this many times the
+/// minimum processing time measured at startup
+const AUTO_TIMEOUT_PROCESSING_TIMES: u32 = 20;
+
+/// The configured timeout, raised to fit AUTO_TIMEOUT_PROCESSING_TIMES
+/// detections of the given processing time
+fn auto_request_timeout(configured: Duration, min_processing_time: Duration) -> Duration {
+    configured.max(min_processing_time.saturating_mul(AUTO_TIMEOUT_PROCESSING_TIMES))
+}
+
+/// Backpressure threshold for the worker queue that follows the live
+/// throughput. It is recomputed from the average processing time of the last
+/// `WINDOW` detections the same way the queue size is estimated at startup,
+/// and never exceeds the queue capacity.
+#[derive(Debug)]
+pub struct AdaptiveQueueLimit {
+    limit: AtomicUsize,
+    capacity: usize,
+    request_timeout: Duration,
+    pool_size: usize,
+    recent_processing_times: Mutex<Vec<Duration>>,
+}
+
+impl AdaptiveQueueLimit {
+    const WINDOW: usize = 50;
+
+    pub fn new(capacity: usize, request_timeout: Duration, pool_size: usize) -> Self {
+        Self {
+            limit: AtomicUsize::new(capacity),
+            capacity,
+            request_timeout,
+            pool_size,
+            recent_processing_times: Mutex::new(Vec::with_capacity(Self::WINDOW)),
+        }
+    }
+
+    /// Number of queued requests at which new requests are rejected
+    pub fn limit(&self) -> usize {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    /// Records the processing time of a detection and recomputes the limit
+    /// once a full window has been recorded.
+    pub fn record(&self, processing_time: Duration) {
+        let mut recent = self
+            .recent_processing_times
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        recent.push(processing_time);
+        if recent.len() < Self::WINDOW {
+            return;
+        }
+        let average = recent.iter().sum::<Duration>() / recent.len() as u32;
+        recent.clear();
+        drop(recent);
+
+        let limit = self.limit_for(average);
+        let previous = self.limit.swap(limit, Ordering::Relaxed);
+        if limit != previous {
+            info!(
+                previous,
+                limit,
+                ?average,
+                "Adjusted worker queue limit to the recent processing time"
+            );
+        }
+    }
+
+    fn limit_for(&self, average_processing_time: Duration) -> usize {
+        let average_ms = average_processing_time.as_millis().max(1);
+        let limit = (self.request_timeout.as_millis() / average_ms) as usize * self.pool_size;
+        limit.clamp(1, self.capacity)
+    }
+}
+
+/// Image bytes and detection settings of a request. The whole key is
+/// compared on lookup so a hash collision can't return the detections of
+/// another image, the image bytes are shared with the request.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DetectionCacheKey {
+    image_data: Bytes,
+    min_confidence: Option<u32>,
+    profile: Option<String>,
+    rois: Vec<Roi>,
+    top_k_classes: usize,
+}
+
+/// Least recently used cache of detection responses keyed by the image bytes
+/// and the detection settings of the request, so clients that resend the
+/// same frame don't run inference again.
+#[derive(Debug)]
+pub struct DetectionCache {
+    capacity: usize,
+    responses: HashMap<DetectionCacheKey, VisionDetectionResponse>,
+    /// Keys from least to most recently used
+    order: VecDeque<DetectionCacheKey>,
+}
+
+impl DetectionCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            responses: HashMap::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn key(
+        image_data: &Bytes,
+        min_confidence: Option<f32>,
+        profile: Option<&str>,
+        rois: &[Roi],
+        top_k_classes: usize,
+    ) -> DetectionCacheKey {
+        DetectionCacheKey {
+            image_data: image_data.clone(),
+            min_confidence: min_confidence.map(f32::to_bits),
+            profile: profile.map(str::to_string),
+            rois: rois.to_vec(),
+            top_k_classes,
+        }
+    }
+
+    pub fn get(&mut self, key: &DetectionCacheKey) -> Option<VisionDetectionResponse> {
+        let response = self.responses.get(key)?.clone();
+        self.touch(key);
+        Some(response)
+    }
+
+    pub fn insert(&mut self, key: DetectionCacheKey, response: VisionDetectionResponse) {
+        if self.responses.insert(key.clone(), response).is_some() {
+            self.touch(&key);
+            return;
+        }
+        self.order.push_back(key);
+        if self.order.len() > self.capacity
+            && let Some(evicted) = self.order.pop_front()
+        {
+            self.responses.remove(&evicted);
+        }
+    }
+
+    fn touch(&mut self, key: &DetectionCacheKey) {
+        if let Some(index) = self.order.iter().position(|k| k == key)
+            && let Some(key) = self.order.remove(index)
+        {
+            self.order.push_back(key);
+        }
+    }
+}
+
+/// Suppresses repeated notifications for the same label until the cooldown
+/// has elapsed since the last notification for that label.
+pub struct NotificationCooldown {
+    cooldown: Duration,
+    last_fired: HashMap<String, Instant>,
+}
+
+impl NotificationCooldown {
+    pub fn new(cooldown: Duration) -> Self {
+        Self {
+            cooldown,
+            last_fired: HashMap::new(),
+        }
+    }
+
+    /// Returns true if a notification for `label` should fire at `now` and
+    /// records it as fired.
+    pub fn should_notify(&mut self, label: &str, now: Instant) -> bool {
+        match self.last_fired.get_mut(label) {
+            Some(last) if now.duration_since(*last) < self.cooldown => false,
+            Some(last) => {
+                *last = now;
+                true
+            }
+            None => {
+                self.last_fired.insert(label.to_string(), now);
+                true
+            }
+        }
+    }
+}
+
+/// Appends a CSV row with the stage timings of every detection to a file.
+pub struct TimingLog {
+    file: File,
+}
+
+impl TimingLog {
+    const HEADER: &str = "timestamp,image_name,decode_ms,resize_ms,pre_processing_ms,inference_ms,post_processing_ms,processing_ms";
+
+    /// Opens the log for appending, the header is only written to new files.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if file.metadata()?.len() == 0 {
+            writeln!(file, "{}", Self::HEADER)?;
+        }
+        Ok(Self { file })
+    }
+
+    pub fn record(
+        &mut self,
+        timestamp: DateTime<Utc>,
+        image_name: &str,
+        detect_result: &DetectResult,
+    ) -> std::io::Result<()> {
+        // Write the row in one call so rows from detectors sharing the file never interleave
+        self.file
+            .write_all(timing_row(timestamp, image_name, detect_result).as_bytes())
+    }
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+fn detection_timings(detect_result: &DetectResult) -> DetectionTimings {
+    DetectionTimings {
+        decode_ms: ms(detect_result.decode_image_time),
+        resize_ms: ms(detect_result.resize_image_time),
+        pre_processing_ms: ms(detect_result.pre_processing_time),
+        inference_ms: ms(detect_result.inference_time),
+        post_processing_ms: ms(detect_result.post_processing_time),
+    }
+}
+
+fn timing_row(timestamp: DateTime<Utc>, image_name: &str, detect_result: &DetectResult) -> String {
+    let image_name = if image_name.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", image_name.replace('"', "\"\""))
+    } else {
+        image_name.to_string()
+    };
+    format!(
+        "{},{},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3}\n",
+        timestamp.to_rfc3339(),
+        image_name,
+        ms(detect_result.decode_image_time),
+        ms(detect_result.resize_image_time),
+        ms(detect_result.pre_processing_time),
+        ms(detect_result.inference_time),
+        ms(detect_result.post_processing_time),
+        ms(detect_result.processing_time),
+    )
+}
+
 pub struct DetectorWorker {
     receiver: Receiver<(
         VisionDetectionRequest,
-        oneshot::Sender<VisionDetectionResponse>,
+        oneshot::Sender<anyhow::Result<VisionDetectionResponse>>,
         Instant,
     )>,
     detector: Detector,
     request_timeout: Duration,
+    /// Shared by all workers in the pool so the cooldown applies per label
+    /// regardless of which worker handled the request
+    notify_cooldown: Option<Arc<Mutex<NotificationCooldown>>>,
+    /// Per request timing log, shared by all workers in the pool
+    timing_log: Option<Arc<Mutex<TimingLog>>>,
+    /// Queue limit following the live processing time, shared by all workers in the pool
+    queue_limit: Option<Arc<AdaptiveQueueLimit>>,
+    /// Responses of recent detections, shared by all workers in the pool
+    detection_cache: Option<Arc<Mutex<DetectionCache>>>,
+    /// Number of workers pulling from the same queue
+    pool_size: usize,
+    priority: WorkerPriority,
+    /// Pin the worker thread to the core it starts on
+    pin_core: bool,
+    /// Number of requests this worker dropped because they expired in the queue
+    expired_requests: u64,
 }
 
 #[allow(clippy::type_complexity)]
@@ -23,17 +335,50 @@ impl DetectorWorker {
     pub fn new(
         detector_config: DetectorConfig,
         worker_queue_size: Option<usize>,
+        pool_size: usize,
     ) -> anyhow::Result<(
         Sender<(
             VisionDetectionRequest,
-            oneshot::Sender<VisionDetectionResponse>,
+            oneshot::Sender<anyhow::Result<VisionDetectionResponse>>,
             Instant,
         )>,
         Self,
     )> {
-        let request_timeout = detector_config.timeout;
+        let mut request_timeout = detector_config.timeout;
+        let auto_timeout = detector_config.auto_timeout;
+        let notify_cooldown = detector_config
+            .notify_cooldown
+            .map(|cooldown| Arc::new(Mutex::new(NotificationCooldown::new(cooldown))));
+        let timing_log = match &detector_config.timing_log {
+            Some(path) => {
+                info!(path = %path.display(), "Writing per request timings");
+                Some(Arc::new(Mutex::new(TimingLog::open(path)?)))
+            }
+            None => None,
+        };
+        let detection_cache = (detector_config.detection_cache_size > 0).then(|| {
+            Arc::new(Mutex::new(DetectionCache::new(
+                detector_config.detection_cache_size,
+            )))
+        });
+        let pool_size = pool_size.max(1);
+        let priority = detector_config.worker_priority;
+        let pin_core = detector_config.worker_pin_core;
+        let adaptive_queue = detector_config.adaptive_queue;
         let mut detector = Detector::new(detector_config)?;
 
+        if auto_timeout {
+            let configured_timeout = request_timeout;
+            let min_processing_time = detector.get_min_processing_time()?;
+            request_timeout = auto_request_timeout(configured_timeout, min_processing_time);
+            info!(
+                ?request_timeout,
+                ?configured_timeout,
+                ?min_processing_time,
+                "Resolved auto request timeout"
+            );
+        }
+
         let worker_queue_size = match worker_queue_size {
             Some(size) => {
                 info!(?size, "User set worker queue size");
@@ -47,12 +392,15 @@ impl DetectorWorker {
                 // A queue size of 1000 makes no sense if we can only process 10
                 // images in 1000ms. This allows us to drop requests at the
                 // service level instead of the worker level.
+                // With a pool of workers the throughput scales with the pool size.
                 let estimated_queue_size =
-                    (request_timeout.as_millis() / min_processing_time.as_millis()) as usize;
+                    (request_timeout.as_millis() / min_processing_time.as_millis()) as usize
+                        * pool_size;
                 info!(
                     ?estimated_queue_size,
                     ?request_timeout,
                     ?min_processing_time,
+                    pool_size,
                     "Estimated worker queue"
                 );
                 estimated_queue_size
@@ -60,6 +408,13 @@ impl DetectorWorker {
         };
 
         let (sender, receiver) = crossbeam::channel::bounded(worker_queue_size);
+        let queue_limit = adaptive_queue.then(|| {
+            Arc::new(AdaptiveQueueLimit::new(
+                worker_queue_size,
+                request_timeout,
+                pool_size,
+            ))
+        });
 
         Ok((
             sender,
@@ -67,12 +422,48 @@ impl DetectorWorker {
                 receiver,
                 detector,
                 request_timeout,
+                notify_cooldown,
+                timing_log,
+                queue_limit,
+                detection_cache,
+                pool_size,
+                priority,
+                pin_core,
+                expired_requests: 0,
             },
         ))
     }
+
+    /// Create another worker with its own detector that pulls requests from
+    /// the same queue as this worker
+    pub fn new_pool_worker(&self, detector_config: DetectorConfig) -> anyhow::Result<Self> {
+        Ok(DetectorWorker {
+            receiver: self.receiver.clone(),
+            detector: Detector::new(detector_config)?,
+            request_timeout: self.request_timeout,
+            notify_cooldown: self.notify_cooldown.clone(),
+            timing_log: self.timing_log.clone(),
+            queue_limit: self.queue_limit.clone(),
+            detection_cache: self.detection_cache.clone(),
+            pool_size: self.pool_size,
+            priority: self.priority,
+            pin_core: self.pin_core,
+            expired_requests: 0,
+        })
+    }
     pub fn get_detector(&self) -> &Detector {
         &self.detector
     }
+    pub fn get_detector_mut(&mut self) -> &mut Detector {
+        &mut self.detector
+    }
+    /// The adaptive queue limit, None if the queue size is fixed
+    pub fn get_queue_limit(&self) -> Option<Arc<AdaptiveQueueLimit>> {
+        self.queue_limit.clone()
+    }
+    pub fn get_request_timeout(&self) -> Duration {
+        self.request_timeout
+    }
     pub fn run(&mut self) {
         info!("Detector worker thread: Starting detector worker loop");
         while let Ok((vision_request, response_sender, start_request_time)) = self.receiver.recv() {
@@ -81,10 +472,39 @@ impl DetectorWorker {
                 ?queue_time,
                 "Received request from worker time spent in queue"
             );
+
+            // The client has already given up on requests that expired while
+            // queued, so skip inference for them instead of wasting cycles
+            if queue_time > self.request_timeout {
+                self.expired_requests += 1;
+                warn!(
+                    ?queue_time,
+                    ?self.request_timeout,
+                    expired_requests = self.expired_requests,
+                    "Request expired in queue, dropping it without running inference"
+                );
+                let expired_response = VisionDetectionResponse {
+                    success: false,
+                    message: "Server is overloaded, retry later".into(),
+                    error: Some(REQUEST_EXPIRED_IN_QUEUE_ERROR.into()),
+                    command: "detect".into(),
+                    moduleId: self.detector.get_model_name().clone(),
+                    ..Default::default()
+                };
+                if response_sender.send(Ok(expired_response)).is_err() {
+                    debug!("Client is gone, expired request response not delivered");
+                }
+                continue;
+            }
             let VisionDetectionRequest {
                 image_data,
                 image_name,
                 min_confidence,
+                camera,
+                timings,
+                profile,
+                rois,
+                top_k_classes,
                 ..
             } = vision_request;
 
@@ -96,38 +516,75 @@ impl DetectorWorker {
 
             let min_confidence = (min_confidence > 0.01).then_some(min_confidence);
 
-            let detect_result = self.detector.detect(image_data, image_name, min_confidence);
+            let cache_key = self.detection_cache.is_some().then(|| {
+                DetectionCache::key(
+                    &image_data,
+                    min_confidence,
+                    profile.as_deref(),
+                    &rois,
+                    top_k_classes,
+                )
+            });
+            if let Some(cache_key) = &cache_key
+                && let Some(cached_response) = self.cached_response(cache_key)
+            {
+                debug!("Same image as a recent request, returning the cached detection");
+                if response_sender.send(Ok(cached_response)).is_err() {
+                    debug!("Client is gone, cached response not delivered");
+                }
+                continue;
+            }
 
-            let detect_response = match detect_result {
-                Ok(detect_result) => VisionDetectionResponse {
-                    success: true,
-                    message: "".into(),
-                    error: None,
-                    predictions: detect_result.predictions.to_vec(),
-                    count: detect_result.predictions.len() as i32,
-                    command: "detect".into(),
-                    moduleId: self.detector.get_model_name().clone(),
-                    executionProvider: detect_result.endpoint_provider.to_string(),
-                    canUseGPU: detect_result.device_type == DeviceType::GPU,
-                    inferenceMs: detect_result.inference_time.as_millis() as i32,
-                    processMs: detect_result.processing_time.as_millis() as i32,
-                    analysisRoundTripMs: 0_i32,
-                },
-                Err(err) => VisionDetectionResponse {
-                    success: false,
-                    message: "Failboat".into(),
-                    error: Some(err.to_string()),
-                    predictions: vec![],
-                    count: 0,
-                    command: "detect".into(),
-                    moduleId: self.detector.get_model_name().clone(),
-                    executionProvider: "CPU".into(),
-                    canUseGPU: false,
-                    inferenceMs: 0_i32,
-                    processMs: 0_i32,
-                    analysisRoundTripMs: 0_i32,
+            let logged_image_name = self.timing_log.is_some().then(|| image_name.clone());
+
+            let detect_result = self.detector.detect(
+                image_data,
+                image_name,
+                DetectOptions {
+                    camera: camera.as_deref(),
+                    min_confidence,
+                    profile: profile.as_deref(),
+                    rois: &rois,
+                    top_k_classes,
                 },
-            };
+            );
+
+            if let Ok(detect_result) = &detect_result {
+                self.notify(&detect_result.predictions);
+                if let Some(queue_limit) = &self.queue_limit {
+                    queue_limit.record(detect_result.processing_time);
+                }
+                if let Some(image_name) = logged_image_name.flatten() {
+                    self.log_timing(&image_name, detect_result);
+                }
+            }
+
+            // Failures are sent back as errors so the server can tell an invalid
+            // image from a detector failure
+            let detect_response = detect_result.map(|detect_result| VisionDetectionResponse {
+                success: true,
+                message: "".into(),
+                error: None,
+                predictions: detect_result.predictions.to_vec(),
+                count: detect_result.predictions.len() as i32,
+                command: "detect".into(),
+                moduleId: self.detector.get_model_name().clone(),
+                moduleName: String::new(),
+                executionProvider: detect_result.endpoint_provider.to_string(),
+                canUseGPU: detect_result.device_type == DeviceType::GPU,
+                inferenceMs: detect_result.inference_time.as_millis() as i32,
+                processMs: detect_result.processing_time.as_millis() as i32,
+                analysisRoundTripMs: 0_i32,
+                timings: timings.then(|| detection_timings(&detect_result)),
+                imageWidth: detect_result.image_width as u32,
+                imageHeight: detect_result.image_height as u32,
+            });
+
+            if let Some(cache_key) = cache_key
+                && let Ok(detect_response) = &detect_response
+            {
+                self.cache_response(cache_key, detect_response);
+            }
 
             let request_time = start_request_time.elapsed();
             if request_time > self.request_timeout {
@@ -144,7 +601,61 @@ impl DetectorWorker {
                 );
             }
         }
-        info!("Detector worker thread: Completed and exiting");
+        // The loop only ends once every sender is dropped and the queue is empty
+        info!("Detector worker thread: Queue closed and drained, exiting");
+    }
+
+    /// Fires a detection notification for each label in the predictions,
+    /// skipping labels that are still within the notification cooldown.
+    fn notify(&self, predictions: &[crate::api::Prediction]) {
+        let Some(cooldown) = self.notify_cooldown.as_ref() else {
+            return;
+        };
+        let mut cooldown = cooldown.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        for prediction in predictions {
+            if cooldown.should_notify(&prediction.label, now) {
+                info!(
+                    label = %prediction.label,
+                    confidence = prediction.confidence,
+                    "Detection notification"
+                );
+            } else {
+                debug!(label = %prediction.label, "Detection notification suppressed by cooldown");
+            }
+        }
+    }
+
+    /// A cached response for the key, no inference ran for it so the
+    /// inference and processing times are 0
+    fn cached_response(&self, cache_key: &DetectionCacheKey) -> Option<VisionDetectionResponse> {
+        let detection_cache = self.detection_cache.as_ref()?;
+        let mut detection_cache = detection_cache.lock().unwrap_or_else(|e| e.into_inner());
+        let response = detection_cache.get(cache_key)?;
+        Some(VisionDetectionResponse {
+            inferenceMs: 0,
+            processMs: 0,
+            timings: None,
+            ..response
+        })
+    }
+
+    fn cache_response(&self, cache_key: DetectionCacheKey, response: &VisionDetectionResponse) {
+        let Some(detection_cache) = self.detection_cache.as_ref() else {
+            return;
+        };
+        let mut detection_cache = detection_cache.lock().unwrap_or_else(|e| e.into_inner());
+        detection_cache.insert(cache_key, response.clone());
+    }
+
+    fn log_timing(&self, image_name: &str, detect_result: &DetectResult) {
+        let Some(timing_log) = self.timing_log.as_ref() else {
+            return;
+        };
+        let mut timing_log = timing_log.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(err) = timing_log.record(Utc::now(), image_name, detect_result) {
+            warn!(?err, "Failed to write request timings");
+        }
     }
 
     /// Spawns the detector worker thread with optimized settings
@@ -154,20 +665,180 @@ impl DetectorWorker {
             unsafe {
                 use windows::Win32::System::Threading::{
                     GetCurrentProcessorNumber, GetCurrentThread, SetThreadAffinityMask,
-                    SetThreadPriority, THREAD_PRIORITY_TIME_CRITICAL,
+                    SetThreadPriority, THREAD_PRIORITY_HIGHEST, THREAD_PRIORITY_TIME_CRITICAL,
                 };
                 let thread_handle = GetCurrentThread();
-                if let Err(err) = SetThreadPriority(thread_handle, THREAD_PRIORITY_TIME_CRITICAL) {
-                    tracing::error!(?err, "Failed to set thread priority to time critical");
+                let thread_priority = match self.priority {
+                    WorkerPriority::Normal => None,
+                    WorkerPriority::High => Some(THREAD_PRIORITY_HIGHEST),
+                    WorkerPriority::TimeCritical => Some(THREAD_PRIORITY_TIME_CRITICAL),
+                };
+                if let Some(thread_priority) = thread_priority
+                    && let Err(err) = SetThreadPriority(thread_handle, thread_priority)
+                {
+                    tracing::error!(?err, priority = ?self.priority, "Failed to set thread priority");
+                }
+                // Pinning several pool workers could put them on the same core
+                if self.pin_core && self.pool_size == 1 {
+                    let processor_number = GetCurrentProcessorNumber();
+                    let core_mask = 1usize << processor_number;
+                    let previous_mask = SetThreadAffinityMask(thread_handle, core_mask);
+                    if previous_mask == 0 {
+                        tracing::error!("Failed to set thread affinity.");
+                    }
                 }
-                let processor_number = GetCurrentProcessorNumber();
-                let core_mask = 1usize << processor_number;
-                let previous_mask = SetThreadAffinityMask(thread_handle, core_mask);
-                if previous_mask == 0 {
-                    tracing::error!("Failed to set thread affinity.");
+            }
+            #[cfg(target_os = "linux")]
+            unsafe {
+                // With the thread id setpriority only changes the nice value of this thread
+                if let Some(nice) = self.priority.nice()
+                    && libc::setpriority(libc::PRIO_PROCESS, libc::gettid() as libc::id_t, nice)
+                        != 0
+                {
+                    warn!(
+                        nice,
+                        err = %std::io::Error::last_os_error(),
+                        "Failed to set worker thread priority, raising it requires CAP_SYS_NICE"
+                    );
+                }
+                // Pinning several pool workers could put them on the same core
+                if self.pin_core && self.pool_size == 1 {
+                    let cpu = libc::sched_getcpu();
+                    let mut cpu_set: libc::cpu_set_t = std::mem::zeroed();
+                    if cpu >= 0 {
+                        libc::CPU_SET(cpu as usize, &mut cpu_set);
+                    }
+                    if cpu < 0
+                        || libc::sched_setaffinity(
+                            0,
+                            std::mem::size_of::<libc::cpu_set_t>(),
+                            &cpu_set,
+                        ) != 0
+                    {
+                        tracing::error!("Failed to set thread affinity.");
+                    }
                 }
             }
             self.run();
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auto_request_timeout_never_lowers_configured() {
+        let configured = Duration::from_secs(15);
+        assert_eq!(
+            auto_request_timeout(configured, Duration::from_millis(100)),
+            configured
+        );
+        assert_eq!(
+            auto_request_timeout(configured, Duration::from_millis(1500)),
+            Duration::from_secs(30)
+        );
+    }
+
+    #[test]
+    fn test_notification_cooldown_per_label() {
+        let mut cooldown = NotificationCooldown::new(Duration::from_secs(10));
+        let start = Instant::now();
+
+        assert!(cooldown.should_notify("person", start));
+        assert!(!cooldown.should_notify("person", start + Duration::from_secs(5)));
+        assert!(cooldown.should_notify("car", start + Duration::from_secs(5)));
+        assert!(cooldown.should_notify("person", start + Duration::from_secs(10)));
+        assert!(!cooldown.should_notify("person", start + Duration::from_secs(15)));
+    }
+
+    #[test]
+    fn test_adaptive_queue_limit_follows_processing_time() {
+        let queue_limit = AdaptiveQueueLimit::new(20, Duration::from_secs(1), 1);
+        assert_eq!(queue_limit.limit(), 20);
+
+        // Slower than at startup, 1000ms / 200ms fits 5 requests
+        for _ in 0..AdaptiveQueueLimit::WINDOW {
+            queue_limit.record(Duration::from_millis(200));
+        }
+        assert_eq!(queue_limit.limit(), 5);
+
+        // Faster again, capped at the queue capacity
+        for _ in 0..AdaptiveQueueLimit::WINDOW {
+            queue_limit.record(Duration::from_millis(10));
+        }
+        assert_eq!(queue_limit.limit(), 20);
+    }
+
+    #[test]
+    fn test_detection_cache_evicts_least_recently_used() {
+        let response = |message: &str| VisionDetectionResponse {
+            message: message.to_string(),
+            ..Default::default()
+        };
+        let image = |data: &'static [u8]| Bytes::from_static(data);
+        let mut cache = DetectionCache::new(2);
+        let (a, b, c) = (
+            DetectionCache::key(&image(b"a"), None, None, &[], 0),
+            DetectionCache::key(&image(b"b"), None, None, &[], 0),
+            DetectionCache::key(&image(b"c"), None, None, &[], 0),
+        );
+        assert_eq!(a, DetectionCache::key(&image(b"a"), None, None, &[], 0));
+        assert_ne!(
+            a,
+            DetectionCache::key(&image(b"a"), Some(0.5), None, &[], 0)
+        );
+        assert_ne!(
+            a,
+            DetectionCache::key(&image(b"a"), None, Some("night"), &[], 0)
+        );
+        let roi = Roi {
+            x: 0,
+            y: 0,
+            width: 10,
+            height: 10,
+        };
+        assert_ne!(a, DetectionCache::key(&image(b"a"), None, None, &[roi], 0));
+        assert_ne!(a, DetectionCache::key(&image(b"a"), None, None, &[], 3));
+
+        cache.insert(a.clone(), response("a"));
+        cache.insert(b.clone(), response("b"));
+        // Using a makes b the least recently used entry
+        assert_eq!(cache.get(&a).unwrap().message, "a");
+        cache.insert(c.clone(), response("c"));
+        assert!(cache.get(&b).is_none());
+        assert_eq!(cache.get(&a).unwrap().message, "a");
+        assert_eq!(cache.get(&c).unwrap().message, "c");
+    }
+
+    #[test]
+    fn test_timing_row_csv() {
+        let detect_result = DetectResult {
+            predictions: Default::default(),
+            processing_time: Duration::from_millis(20),
+            decode_image_time: Duration::from_millis(5),
+            resize_image_time: Duration::from_micros(1500),
+            pre_processing_time: Duration::from_millis(1),
+            inference_time: Duration::from_millis(10),
+            post_processing_time: Duration::from_millis(2),
+            device_type: DeviceType::CPU,
+            endpoint_provider: crate::detector::EndpointProvider::CPU,
+            image_width: 640,
+            image_height: 480,
+        };
+        let timestamp = DateTime::parse_from_rfc3339("2025-01-02T03:04:05Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        let row = timing_row(timestamp, "front,door.jpg", &detect_result);
+        assert_eq!(
+            row,
+            "2025-01-02T03:04:05+00:00,\"front,door.jpg\",5.000,1.500,1.000,10.000,2.000,20.000\n"
+        );
+        assert_eq!(
+            row.split(',').count(),
+            TimingLog::HEADER.split(',').count() + 1
+        );
+    }
+}